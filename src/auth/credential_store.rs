@@ -1,13 +1,21 @@
-use anyhow::{Result, Context};
+use anyhow::{bail, Result, Context};
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 const GEMINI_DIR: &str = ".gemini";
 const CREDENTIAL_FILENAME: &str = "oauth_creds.json";
+/// Profile kept in gemini-cli's credentials file so both tools share one login.
+pub const DEFAULT_PROFILE: &str = "gemini";
+const PROFILES_DIR: &str = "oauth";
+// Refresh a little early so the token doesn't expire in flight.
+const EXPIRY_MARGIN_SECS: i64 = 60;
 
 use super::oauth_split::user_info::UserInfo;
+use super::vault::{read_vault, update_vault, Vault, VAULT_PREFIX};
+use super::{create_private_dir, write_private_file};
+use crate::config::Config;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Credentials {
@@ -19,18 +27,79 @@ pub struct Credentials {
     pub user_info: Option<UserInfo>,
 }
 
+impl Credentials {
+    /// `expiry_date` is in milliseconds, like the file gemini-cli writes; older files used seconds.
+    pub fn expires_at(&self) -> Option<i64> {
+        self.expiry_date
+            .map(|v| if v > 100_000_000_000 { v / 1000 } else { v })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at()
+            .is_some_and(|v| Utc::now().timestamp() + EXPIRY_MARGIN_SECS >= v)
+    }
+}
+
+#[derive(Debug)]
 pub struct CredentialStore {
     credentials_path: PathBuf,
+    // Name of the vault secret holding the credentials, if they are kept in the vault
+    vault_name: Option<String>,
 }
 
 impl CredentialStore {
-    pub fn new() -> Result<Self> {
-        let home_dir = dirs::home_dir().context("Could not find home directory")?;
-        let credentials_path = home_dir.join(GEMINI_DIR).join(CREDENTIAL_FILENAME);
-        Ok(Self { credentials_path })
+    /// The default profile lives in `~/.gemini/oauth_creds.json`, others in `<config_dir>/oauth/<profile>.json`.
+    pub fn new(profile: &str) -> Result<Self> {
+        let credentials_path = if profile == DEFAULT_PROFILE {
+            let home_dir = dirs::home_dir().context("Could not find home directory")?;
+            home_dir.join(GEMINI_DIR).join(CREDENTIAL_FILENAME)
+        } else {
+            if profile.is_empty() || profile.contains(['/', '\\']) || profile.starts_with('.') {
+                bail!("Invalid OAuth profile '{profile}'");
+            }
+            Config::local_path(PROFILES_DIR).join(format!("{profile}.json"))
+        };
+        Ok(Self {
+            credentials_path,
+            vault_name: None,
+        })
+    }
+
+    /// Keeps the profile's credentials in the encrypted vault as `oauth:<profile>`.
+    pub fn vault(profile: &str) -> Self {
+        Self {
+            credentials_path: Vault::default_path(),
+            vault_name: Some(format!("oauth:{profile}")),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn from_path(credentials_path: PathBuf) -> Self {
+        Self {
+            credentials_path,
+            vault_name: None,
+        }
+    }
+
+    /// Where the credentials are kept, for display.
+    pub fn location(&self) -> String {
+        match &self.vault_name {
+            Some(name) => format!("{VAULT_PREFIX}{name}"),
+            None => self.credentials_path.display().to_string(),
+        }
     }
 
     pub fn read_credentials(&self) -> Result<Credentials> {
+        if let Some(name) = &self.vault_name {
+            let contents = read_vault(|vault| {
+                vault
+                    .get(name)
+                    .map(|v| v.to_string())
+                    .with_context(|| format!("No secret '{name}' in the vault"))
+            })?;
+            return serde_json::from_str(&contents)
+                .with_context(|| format!("Could not parse credentials from {}", self.location()));
+        }
         let contents = fs::read_to_string(&self.credentials_path)
             .with_context(|| format!("Could not read credentials from {}", self.credentials_path.display()))?;
         let parsed_json: Credentials = serde_json::from_str(&contents)
@@ -39,21 +108,83 @@ impl CredentialStore {
     }
 
     pub fn write_credentials(&self, credentials: &Credentials) -> Result<()> {
+        if let Some(name) = &self.vault_name {
+            let contents =
+                serde_json::to_string(credentials).context("Could not serialize credentials")?;
+            return update_vault(|vault| vault.set(name, &contents));
+        }
         let parent_dir = self.credentials_path.parent().context("Invalid credentials path")?;
-        fs::create_dir_all(parent_dir).with_context(|| format!("Could not create directory {}", parent_dir.display()))?;
+        create_private_dir(parent_dir)?;
 
         let cred_string = serde_json::to_string_pretty(credentials)
             .context("Could not serialize credentials")?;
-        fs::write(&self.credentials_path, cred_string)
+        write_private_file(&self.credentials_path, &cred_string)
             .with_context(|| format!("Could not write credentials to {}", self.credentials_path.display()))?;
         Ok(())
     }
 
     pub fn clear_credentials(&self) -> Result<()> {
+        if let Some(name) = &self.vault_name {
+            if self.credentials_path.exists() {
+                update_vault(|vault| vault.remove(name))?;
+            }
+            return Ok(());
+        }
         if self.credentials_path.exists() {
             fs::remove_file(&self.credentials_path)
                 .with_context(|| format!("Could not remove credentials file {}", self.credentials_path.display()))?;
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(expiry_date: Option<i64>) -> Credentials {
+        Credentials {
+            access_token: "token".into(),
+            refresh_token: None,
+            token_type: None,
+            expiry_date,
+            user_info: None,
+        }
+    }
+
+    #[test]
+    fn test_credential_store_profiles() {
+        let store = CredentialStore::new(DEFAULT_PROFILE).unwrap();
+        assert!(store.location().ends_with(".gemini/oauth_creds.json"));
+        let store = CredentialStore::new("gateway").unwrap();
+        assert!(store.location().ends_with("oauth/gateway.json"));
+        assert_eq!(
+            CredentialStore::vault("gateway").location(),
+            "vault:oauth:gateway"
+        );
+        assert!(CredentialStore::new("../gateway").is_err());
+    }
+
+    #[test]
+    fn test_credentials_expiry() {
+        let now = Utc::now().timestamp();
+        assert_eq!(credentials(Some(now * 1000)).expires_at(), Some(now));
+        assert_eq!(credentials(Some(now)).expires_at(), Some(now));
+        assert!(credentials(Some((now + 30) * 1000)).is_expired());
+        assert!(!credentials(Some((now + 3600) * 1000)).is_expired());
+        assert!(!credentials(None).is_expired());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_credentials_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("oauth").join("gateway.json");
+        let store = CredentialStore::from_path(path.clone());
+        store.write_credentials(&credentials(None)).unwrap();
+        let mode = |path: &std::path::Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(path.parent().unwrap()), 0o700);
+        assert_eq!(store.read_credentials().unwrap().access_token, "token");
+    }
+}
@@ -1,28 +1,103 @@
-use async_trait::async_trait;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::config::ensure_parent_exists;
+
 pub mod credential_store;
 pub mod oauth_split;
+pub mod vault;
 
 pub use oauth_split::oauth_authenticator_struct::OAuthAuthenticator;
 pub use oauth_split::oauth_config::OAuthConfig;
 
-#[async_trait]
-pub trait Authenticator: Send + Sync {
-    async fn authenticate(&self) -> anyhow::Result<String>; // Returns an access token
+/// How a client authenticates with its provider.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum AuthMethod {
+    #[default]
+    #[serde(rename = "api_key")]
+    ApiKey,
+    #[serde(rename = "oauth")]
+    OAuth,
 }
 
-pub struct ApiKeyAuthenticator {
-    api_key: String,
+/// Writes secrets readable only by the current user. The file is created with mode 0600
+/// under a temporary name and renamed into place, so it is never visible with looser permissions.
+pub(crate) fn write_private_file(path: &Path, contents: &str) -> Result<()> {
+    ensure_parent_exists(path)?;
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Invalid path '{}'", path.display()))?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    let _ = fs::remove_file(&temp_path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let ret = options
+        .open(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+    if ret.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    ret.with_context(|| format!("Failed to write '{}'", path.display()))
 }
 
-impl ApiKeyAuthenticator {
-    pub fn new(api_key: String) -> Self {
-        Self { api_key }
+/// Creates a directory only the current user can enter, tightening it if it already exists.
+pub(crate) fn create_private_dir(path: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+        builder.mode(0o700);
+        builder
+            .create(path)
+            .and_then(|_| fs::set_permissions(path, fs::Permissions::from_mode(0o700)))
+            .with_context(|| format!("Failed to create directory '{}'", path.display()))?;
     }
+    #[cfg(not(unix))]
+    builder
+        .create(path)
+        .with_context(|| format!("Failed to create directory '{}'", path.display()))?;
+    Ok(())
 }
 
-#[async_trait]
-impl Authenticator for ApiKeyAuthenticator {
-    async fn authenticate(&self) -> anyhow::Result<String> {
-        Ok(self.api_key.clone())
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_write_private_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path().join("oauth");
+        create_private_dir(&dir).unwrap();
+        assert_eq!(mode(&dir), 0o700);
+
+        let path = dir.join("gateway.json");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private_file(&path, "secret").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "secret");
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
@@ -1,8 +1,30 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use std::net::TcpListener;
 
-pub fn find_available_port() -> Result<u16> {
-    let listener = TcpListener::bind("127.0.0.1:0")?;
-    let port = listener.local_addr()?.port();
-    Ok(port)
+pub fn find_available_port(range: Option<(u16, u16)>) -> Result<u16> {
+    let Some((start, end)) = range else {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        return Ok(port);
+    };
+    for port in start..=end {
+        if TcpListener::bind(("127.0.0.1", port)).is_ok() {
+            return Ok(port);
+        }
+    }
+    bail!("No available port for the OAuth redirect in {start}-{end}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_available_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let taken = listener.local_addr().unwrap().port();
+        assert!(find_available_port(Some((taken, taken))).is_err());
+        let port = find_available_port(None).unwrap();
+        assert_eq!(find_available_port(Some((port, port))).unwrap(), port);
+    }
 }
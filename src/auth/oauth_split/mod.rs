@@ -3,8 +3,12 @@ pub mod oauth_config;
 pub mod user_info;
 pub mod oauth_authenticator_struct;
 pub mod oauth_authenticator_impl_new;
+pub mod oauth_authenticator_impl_endpoints;
 pub mod oauth_authenticator_impl_refresh_token;
 pub mod oauth_authenticator_impl_fetch_and_cache_user_info;
 pub mod oauth_authenticator_impl_get_token_from_web_flow;
+pub mod oauth_authenticator_impl_get_token_from_device_flow;
 pub mod find_available_port;
-pub mod oauth_authenticator_trait_impl_authenticate;
+pub mod oauth_authenticator_impl_credentials;
+#[cfg(test)]
+pub mod mock_server;
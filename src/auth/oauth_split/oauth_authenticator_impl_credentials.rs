@@ -0,0 +1,80 @@
+use anyhow::{Context, Result};
+
+use oauth2::RefreshToken;
+
+use crate::auth::credential_store::Credentials;
+use crate::auth::oauth_split::oauth_authenticator_struct::OAuthAuthenticator;
+
+impl OAuthAuthenticator {
+    /// The saved credentials, refreshed first if the access token has expired.
+    pub async fn credentials(&self) -> Result<Credentials> {
+        // 1. Load the credentials saved by `aichat auth login`
+        let creds = self
+            .credential_store
+            .read_credentials()
+            .context("Not logged in, please run `aichat auth login`")?;
+        if !creds.is_expired() {
+            return Ok(creds);
+        }
+
+        // 2. Refresh the expired access token, keeping what the token endpoint doesn't resend
+        let refresh_token = creds
+            .refresh_token
+            .clone()
+            .context("The access token has expired, please run `aichat auth login`")?;
+        let mut new_creds = self.refresh_token(RefreshToken::new(refresh_token)).await?;
+        new_creds.refresh_token = new_creds.refresh_token.or(creds.refresh_token);
+        new_creds.user_info = creds.user_info;
+        self.credential_store.write_credentials(&new_creds)?;
+        Ok(new_creds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::credential_store::CredentialStore;
+    use crate::auth::OAuthConfig;
+
+    use crate::auth::oauth_split::mock_server::mock_oauth_server;
+
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_credentials_refreshes_expired_token() {
+        let (base_url, handle) = mock_oauth_server(vec![(
+            200,
+            r#"{"access_token":"new-token","token_type":"bearer","expires_in":3600}"#,
+        )]);
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(CredentialStore::from_path(
+            temp_dir.path().join("gateway.json"),
+        ));
+        store
+            .write_credentials(&Credentials {
+                access_token: "old-token".into(),
+                refresh_token: Some("refresh".into()),
+                token_type: None,
+                expiry_date: Some(1_000_000_000_000),
+                user_info: None,
+            })
+            .unwrap();
+        let config = OAuthConfig {
+            client_id: "client".into(),
+            client_secret: None,
+            auth_url: Some("http://127.0.0.1/authorize".into()),
+            token_url: Some(format!("{base_url}/token")),
+            ..Default::default()
+        };
+        let authenticator = OAuthAuthenticator::new(config, store.clone());
+
+        let creds = authenticator.credentials().await.unwrap();
+        assert_eq!(creds.access_token, "new-token");
+        let requests = handle.join().unwrap();
+        assert!(requests[0].1.contains("grant_type=refresh_token"));
+        assert!(requests[0].1.contains("refresh_token=refresh"));
+        let creds = store.read_credentials().unwrap();
+        assert_eq!(creds.refresh_token.as_deref(), Some("refresh"));
+        assert!(!creds.is_expired());
+    }
+}
@@ -0,0 +1,51 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::auth::oauth_split::oauth_authenticator_struct::OAuthAuthenticator;
+
+#[derive(Debug, Clone)]
+pub struct OAuthEndpoints {
+    pub auth_url: Option<String>,
+    pub token_url: String,
+    pub userinfo_url: Option<String>,
+    pub device_auth_url: Option<String>,
+}
+
+impl OAuthAuthenticator {
+    /// Resolves the endpoints from the config, filling the unset ones by OpenID discovery on the issuer.
+    pub async fn endpoints(&self) -> Result<OAuthEndpoints> {
+        let config = &self.config;
+        let mut auth_url = config.auth_url.clone();
+        let mut token_url = config.token_url.clone();
+        let mut userinfo_url = config.userinfo_url.clone();
+        let mut device_auth_url = config.device_auth_url.clone();
+        if let Some(issuer) = &config.issuer {
+            if auth_url.is_none() || token_url.is_none() || device_auth_url.is_none() {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    issuer.trim_end_matches('/')
+                );
+                let discovery: Value = reqwest::Client::new()
+                    .get(&url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await
+                    .with_context(|| format!("Failed to discover OAuth endpoints from '{url}'"))?;
+                let discovered = |key: &str| discovery[key].as_str().map(|v| v.to_string());
+                auth_url = auth_url.or_else(|| discovered("authorization_endpoint"));
+                token_url = token_url.or_else(|| discovered("token_endpoint"));
+                userinfo_url = userinfo_url.or_else(|| discovered("userinfo_endpoint"));
+                device_auth_url =
+                    device_auth_url.or_else(|| discovered("device_authorization_endpoint"));
+            }
+        }
+        Ok(OAuthEndpoints {
+            auth_url,
+            token_url: token_url.context("Missing `token_url` or `issuer` in the oauth config")?,
+            userinfo_url,
+            device_auth_url,
+        })
+    }
+}
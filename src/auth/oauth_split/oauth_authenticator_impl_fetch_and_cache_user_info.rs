@@ -1,14 +1,16 @@
-use anyhow::{Result, Context};
+use anyhow::Result;
 
 use crate::auth::oauth_split::user_info::UserInfo;
-use crate::auth::credential_store::CredentialStore;
 use crate::auth::oauth_split::oauth_authenticator_struct::OAuthAuthenticator;
 
 impl OAuthAuthenticator {
     pub async fn fetch_and_cache_user_info(&self, access_token: &str) -> Result<()> {
+        let Some(userinfo_url) = self.endpoints().await?.userinfo_url else {
+            return Ok(());
+        };
         let client = reqwest::Client::new();
         let response = client
-            .get("https://www.googleapis.com/oauth2/v2/userinfo")
+            .get(userinfo_url)
             .header("Authorization", format!("Bearer {}", access_token))
             .send()
             .await?
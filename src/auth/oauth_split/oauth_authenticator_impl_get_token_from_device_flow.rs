@@ -0,0 +1,114 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+
+use oauth2::basic::BasicClient;
+use oauth2::{
+    ClientId, ClientSecret, DeviceAuthorizationUrl, Scope, StandardDeviceAuthorizationResponse,
+    TokenResponse, TokenUrl,
+};
+
+use crate::auth::credential_store::Credentials;
+use crate::auth::oauth_split::oauth_authenticator_struct::OAuthAuthenticator;
+
+impl OAuthAuthenticator {
+    /// RFC 8628 device authorization grant, for machines without a browser.
+    pub async fn get_token_from_device_flow(&self) -> Result<Credentials> {
+        let endpoints = self.endpoints().await?;
+        let device_auth_url = endpoints
+            .device_auth_url
+            .context("Missing `device_auth_url` or `issuer` in the oauth config")?;
+        let client = BasicClient::new(ClientId::new(self.config.client_id.clone()))
+            .set_device_authorization_url(DeviceAuthorizationUrl::new(device_auth_url)?)
+            .set_token_uri(TokenUrl::new(endpoints.token_url)?);
+        let client = match &self.config.client_secret {
+            Some(secret) => client.set_client_secret(ClientSecret::new(secret.clone())),
+            None => client,
+        };
+        let http_client = reqwest::Client::new();
+
+        let details: StandardDeviceAuthorizationResponse = client
+            .exchange_device_code()
+            .add_scopes(self.config.scopes.iter().map(|s| Scope::new(s.to_string())))
+            .request_async(&http_client)
+            .await
+            .context("Failed to request a device code")?;
+
+        println!(
+            "Open {} and enter the code: {}",
+            details.verification_uri().as_str(),
+            details.user_code().secret()
+        );
+        if let Some(url) = details.verification_uri_complete() {
+            println!("Or open {}", url.secret());
+        }
+        println!("Waiting for authorization...");
+
+        // Polls at the server's interval, backing off on `slow_down` and retrying on `authorization_pending`
+        let token_response = client
+            .exchange_device_access_token(&details)
+            .request_async(&http_client, tokio::time::sleep, None)
+            .await
+            .context("Failed to exchange device code for token")?;
+
+        Ok(Credentials {
+            access_token: token_response.access_token().secret().to_string(),
+            refresh_token: token_response
+                .refresh_token()
+                .map(|t| t.secret().to_string()),
+            token_type: Some(format!("{:?}", token_response.token_type())),
+            expiry_date: token_response
+                .expires_in()
+                .map(|d| Utc::now().timestamp_millis() + d.as_millis() as i64),
+            user_info: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::credential_store::CredentialStore;
+    use crate::auth::oauth_split::mock_server::mock_oauth_server;
+    use crate::auth::OAuthConfig;
+
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_device_flow_polls_until_authorized() {
+        let (base_url, handle) = mock_oauth_server(vec![
+            (
+                200,
+                r#"{"device_code":"dev","user_code":"ABCD-EFGH","verification_uri":"https://example.com/device","expires_in":60,"interval":0}"#,
+            ),
+            (400, r#"{"error":"authorization_pending"}"#),
+            (
+                200,
+                r#"{"access_token":"token","refresh_token":"refresh","token_type":"bearer","expires_in":3600}"#,
+            ),
+        ]);
+        let config = OAuthConfig {
+            client_id: "client".into(),
+            client_secret: None,
+            auth_url: None,
+            token_url: Some(format!("{base_url}/token")),
+            device_auth_url: Some(format!("{base_url}/device")),
+            scopes: vec!["openid".into()],
+            ..Default::default()
+        };
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(CredentialStore::from_path(
+            temp_dir.path().join("device.json"),
+        ));
+        let authenticator = OAuthAuthenticator::new(config, store);
+
+        let creds = authenticator.get_token_from_device_flow().await.unwrap();
+        assert_eq!(creds.access_token, "token");
+        assert_eq!(creds.refresh_token.as_deref(), Some("refresh"));
+
+        let requests = handle.join().unwrap();
+        assert!(requests[0].0.starts_with("POST /device"));
+        assert!(requests[0].1.contains("scope=openid"));
+        assert!(requests[1].1.contains("device_code=dev"));
+        assert!(requests[2].0.starts_with("POST /token"));
+    }
+}
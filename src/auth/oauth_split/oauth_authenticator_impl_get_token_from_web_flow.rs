@@ -2,46 +2,57 @@ use anyhow::{Result, Context};
 use std::io::{BufReader, Write, BufRead};
 use std::net::TcpListener;
 use oauth2::url::Url;
-use chrono::{Utc, Duration};
+use chrono::Utc;
 use std::borrow::Cow;
 
 use oauth2::basic::BasicClient;
 
-use oauth2::{AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl, Scope, TokenResponse, TokenUrl};
+use oauth2::{AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge, RedirectUrl, Scope, TokenResponse, TokenUrl};
 use reqwest;
 use open;
 
 use crate::auth::credential_store::{Credentials};
-use crate::auth::oauth_split::constants::{OAUTH_CLIENT_ID, OAUTH_CLIENT_SECRET, OAUTH_SCOPE};
-use crate::auth::oauth_split::oauth_config::OAuthConfig;
 use crate::auth::oauth_split::oauth_authenticator_struct::OAuthAuthenticator;
 use crate::auth::oauth_split::find_available_port::find_available_port;
-use crate::auth::oauth_split::user_info::UserInfo;
 
 impl OAuthAuthenticator {
     pub async fn get_token_from_web_flow(&self) -> Result<Credentials> {
-        let client = BasicClient::new(
-            ClientId::new(self.config.client_id.clone()),
-        )
-        .set_client_secret(ClientSecret::new(self.config.client_secret.clone()))
-        .set_auth_uri(AuthUrl::new("https://accounts.google.com/o/oauth2/v2/auth".to_string())?)
-        .set_token_uri(TokenUrl::new("https://oauth2.googleapis.com/token".to_string())?);
-
-                                                                                let (pkce_code_challenge, pkce_code_verifier) =
-            oauth2::PkceCodeChallenge::new_random_sha256();
-
-        let port = find_available_port()?;
+        let endpoints = self.endpoints().await?;
+        let auth_url = endpoints
+            .auth_url
+            .context("Missing `auth_url` or `issuer` in the oauth config")?;
+        let client = BasicClient::new(ClientId::new(self.config.client_id.clone()))
+            .set_auth_uri(AuthUrl::new(auth_url)?)
+            .set_token_uri(TokenUrl::new(endpoints.token_url)?);
+        let client = match &self.config.client_secret {
+            Some(secret) => client.set_client_secret(ClientSecret::new(secret.clone())),
+            None => client,
+        };
+
+        let port = find_available_port(self.config.redirect_ports)?;
         let redirect_uri = format!("http://localhost:{}", port);
 
-        let (authorize_url, csrf_state) = client
+        let mut auth_request = client
             .authorize_url(CsrfToken::new_random)
-            .add_scopes(OAUTH_SCOPE.iter().map(|s| Scope::new(s.to_string())))
-            .set_pkce_challenge(pkce_code_challenge)
-            .set_redirect_uri(Cow::Owned(RedirectUrl::new(redirect_uri.clone()).unwrap()))
-            .url();
+            .add_scopes(self.config.scopes.iter().map(|s| Scope::new(s.to_string())))
+            .set_redirect_uri(Cow::Owned(RedirectUrl::new(redirect_uri.clone())?));
+        for (key, value) in &self.config.extra_params {
+            auth_request = auth_request.add_extra_param(key, value);
+        }
+        let pkce_code_verifier = if self.config.pkce {
+            let (pkce_code_challenge, pkce_code_verifier) = PkceCodeChallenge::new_random_sha256();
+            auth_request = auth_request.set_pkce_challenge(pkce_code_challenge);
+            Some(pkce_code_verifier)
+        } else {
+            None
+        };
+        let (authorize_url, csrf_state) = auth_request.url();
 
         println!("Open this URL in your browser:\n{}\n", authorize_url);
-        open::that(authorize_url.as_str()).context("Failed to open browser")?;
+        // The URL is printed above, so keep waiting for the redirect if no browser can be opened
+        if let Err(err) = open::that(authorize_url.as_str()) {
+            warn!("Failed to open browser: {err}");
+        }
 
         let listener = TcpListener::bind(format!("127.0.0.1:{}", port))?;
         let mut stream = listener.incoming().flatten().next().context("Listener terminated without accepting a connection")?;
@@ -69,21 +80,19 @@ impl OAuthAuthenticator {
             return Err(anyhow::anyhow!("State mismatch. Possible CSRF attack"));
         }
 
-        let token_response = client
+        let mut token_request = client
             .exchange_code(code)
-            .set_pkce_verifier(pkce_code_verifier)
-            .set_redirect_uri(Cow::Owned(RedirectUrl::new(redirect_uri.clone()).unwrap()))
-            .request_async(reqwest::Client::new())
+            .set_redirect_uri(Cow::Owned(RedirectUrl::new(redirect_uri.clone())?));
+        if let Some(pkce_code_verifier) = pkce_code_verifier {
+            token_request = token_request.set_pkce_verifier(pkce_code_verifier);
+        }
+        let token_response = token_request
+            .request_async(&reqwest::Client::new())
             .await
             .context("Failed to exchange code for token")?;
 
         let access_token = token_response.access_token().secret().to_string();
 
-        // Fetch and cache user info
-        if let Err(e) = self.fetch_and_cache_user_info(&access_token).await {
-            eprintln!("Failed to fetch and cache user info: {}", e);
-        }
-
         let message = "Authentication successful! You can close this tab.";
         let response = format!(
             "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
@@ -96,7 +105,9 @@ impl OAuthAuthenticator {
             access_token,
             refresh_token: token_response.refresh_token().map(|t| t.secret().to_string()),
             token_type: Some(format!("{:?}", token_response.token_type())),
-            expiry_date: token_response.expires_in().map(|d| Utc::now().timestamp() + d.as_secs() as i64),
+            expiry_date: token_response
+                .expires_in()
+                .map(|d| Utc::now().timestamp_millis() + d.as_millis() as i64),
             user_info: None, // User info is handled by fetch_and_cache_user_info
         })
     }
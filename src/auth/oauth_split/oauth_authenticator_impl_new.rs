@@ -1,3 +1,4 @@
+use anyhow::Result;
 use std::sync::Arc;
 
 use crate::auth::oauth_split::oauth_config::OAuthConfig;
@@ -8,4 +9,16 @@ impl OAuthAuthenticator {
     pub fn new(config: OAuthConfig, credential_store: Arc<CredentialStore>) -> Self {
         Self { config, credential_store }
     }
+
+    /// Stores the credentials under the configured profile, or the client name if unset.
+    pub fn for_client(client_name: &str, config: OAuthConfig) -> Result<Self> {
+        let profile = config.profile.as_deref().unwrap_or(client_name);
+        let credential_store = if config.vault {
+            CredentialStore::vault(profile)
+        } else {
+            CredentialStore::new(profile)?
+        };
+        let credential_store = Arc::new(credential_store);
+        Ok(Self::new(config, credential_store))
+    }
 }
@@ -1,6 +1,58 @@
-// Placeholder for OAuth configuration
+use indexmap::IndexMap;
+use serde::Deserialize;
+
+use crate::auth::oauth_split::constants::{
+    GOOGLE_AUTH_URL, GOOGLE_DEVICE_AUTH_URL, GOOGLE_TOKEN_URL, GOOGLE_USERINFO_URL,
+    OAUTH_CLIENT_ID, OAUTH_CLIENT_SECRET, OAUTH_SCOPE,
+};
+
+/// OAuth 2.0 settings for a client, defaulting to Google's endpoints.
+#[derive(Debug, Clone, Deserialize)]
 pub struct OAuthConfig {
     pub client_id: String,
-    pub client_secret: String,
-    // Add other OAuth related configs as needed
+    pub client_secret: Option<String>,
+    /// Issuer used for OpenID discovery when `auth_url`/`token_url` are not set
+    pub issuer: Option<String>,
+    pub auth_url: Option<String>,
+    pub token_url: Option<String>,
+    pub userinfo_url: Option<String>,
+    /// RFC 8628 endpoint used by `aichat auth login --device`
+    pub device_auth_url: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default = "default_pkce")]
+    pub pkce: bool,
+    /// Inclusive range of local ports for the redirect listener, any free port if unset
+    pub redirect_ports: Option<(u16, u16)>,
+    #[serde(default)]
+    pub extra_params: IndexMap<String, String>,
+    /// Name of the credentials profile, defaults to the client name
+    pub profile: Option<String>,
+    /// Keep the credentials in the encrypted vault instead of a plain file
+    #[serde(default)]
+    pub vault: bool,
+}
+
+impl Default for OAuthConfig {
+    fn default() -> Self {
+        Self {
+            client_id: OAUTH_CLIENT_ID.to_string(),
+            client_secret: Some(OAUTH_CLIENT_SECRET.to_string()),
+            issuer: None,
+            auth_url: Some(GOOGLE_AUTH_URL.to_string()),
+            token_url: Some(GOOGLE_TOKEN_URL.to_string()),
+            userinfo_url: Some(GOOGLE_USERINFO_URL.to_string()),
+            device_auth_url: Some(GOOGLE_DEVICE_AUTH_URL.to_string()),
+            scopes: OAUTH_SCOPE.iter().map(|v| v.to_string()).collect(),
+            pkce: true,
+            redirect_ports: None,
+            extra_params: IndexMap::from([("access_type".into(), "offline".into())]),
+            profile: None,
+            vault: false,
+        }
+    }
+}
+
+fn default_pkce() -> bool {
+    true
 }
@@ -0,0 +1,351 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Utc;
+use indexmap::IndexMap;
+use inquire::{Password, PasswordDisplayMode};
+use parking_lot::Mutex;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::IsTerminal;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+
+use super::write_private_file;
+use crate::config::Config;
+use crate::utils::{base64_decode, base64_encode, get_env_name};
+
+const VAULT_FILE_NAME: &str = "vault.json";
+const SESSION_FILE_NAME: &str = "aichat-vault-session";
+/// Prefix of config values that reference a vault secret, e.g. `api_key: vault:openai`.
+pub const VAULT_PREFIX: &str = "vault:";
+const VAULT_VERSION: u32 = 1;
+const PBKDF2_ITERATIONS: u32 = 600_000;
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const AAD: &[u8] = b"aichat-vault-v1";
+
+// The vault is unlocked at most once per process
+static UNLOCKED: Mutex<Option<Vault>> = Mutex::new(None);
+
+/// On-disk layout: the secrets map as JSON, sealed with ChaCha20-Poly1305 under a PBKDF2-SHA256 key.
+#[derive(Debug, Serialize, Deserialize)]
+struct VaultFile {
+    version: u32,
+    iterations: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct VaultSession {
+    key: String,
+    expires_at: i64,
+}
+
+pub struct Vault {
+    path: PathBuf,
+    key: [u8; KEY_LEN],
+    salt: Vec<u8>,
+    iterations: u32,
+    secrets: IndexMap<String, String>,
+}
+
+impl std::fmt::Debug for Vault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Vault")
+            .field("path", &self.path)
+            .field("names", &self.names())
+            .finish()
+    }
+}
+
+impl Vault {
+    pub fn default_path() -> PathBuf {
+        Config::local_path(VAULT_FILE_NAME)
+    }
+
+    /// Opens the vault with the passphrase, or starts an empty one if the file doesn't exist yet.
+    pub fn open(path: &Path, passphrase: &str) -> Result<Self> {
+        match read_vault_file(path)? {
+            Some(file) => {
+                let salt = base64_decode(&file.salt).context("Invalid vault salt")?;
+                let key = derive_key(passphrase, &salt, file.iterations)?;
+                Self::decrypt(path, file, key)
+            }
+            None => Self::create(path, passphrase, PBKDF2_ITERATIONS),
+        }
+    }
+
+    fn create(path: &Path, passphrase: &str, iterations: u32) -> Result<Self> {
+        let mut salt = vec![0; SALT_LEN];
+        SystemRandom::new()
+            .fill(&mut salt)
+            .map_err(|_| anyhow!("Failed to generate the vault salt"))?;
+        let key = derive_key(passphrase, &salt, iterations)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            key,
+            salt,
+            iterations,
+            secrets: IndexMap::new(),
+        })
+    }
+
+    fn open_with_key(path: &Path, key: [u8; KEY_LEN]) -> Result<Self> {
+        let file =
+            read_vault_file(path)?.with_context(|| format!("No vault at '{}'", path.display()))?;
+        Self::decrypt(path, file, key)
+    }
+
+    fn decrypt(path: &Path, file: VaultFile, key: [u8; KEY_LEN]) -> Result<Self> {
+        if file.version != VAULT_VERSION {
+            bail!("Unsupported vault version {}", file.version);
+        }
+        let salt = base64_decode(&file.salt).context("Invalid vault salt")?;
+        let nonce = base64_decode(&file.nonce).context("Invalid vault nonce")?;
+        let mut data = base64_decode(&file.ciphertext).context("Invalid vault ciphertext")?;
+        let nonce =
+            Nonce::try_assume_unique_for_key(&nonce).map_err(|_| anyhow!("Invalid vault nonce"))?;
+        let plaintext = sealing_key(&key)?
+            .open_in_place(nonce, Aad::from(AAD), &mut data)
+            .map_err(|_| anyhow!("Wrong passphrase or corrupted vault '{}'", path.display()))?;
+        let secrets = serde_json::from_slice(plaintext).context("Invalid vault contents")?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            key,
+            salt,
+            iterations: file.iterations,
+            secrets,
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("Failed to generate the vault nonce"))?;
+        let mut data = serde_json::to_vec(&self.secrets)?;
+        sealing_key(&self.key)?
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(AAD),
+                &mut data,
+            )
+            .map_err(|_| anyhow!("Failed to encrypt the vault"))?;
+        let file = VaultFile {
+            version: VAULT_VERSION,
+            iterations: self.iterations,
+            salt: base64_encode(&self.salt),
+            nonce: base64_encode(nonce),
+            ciphertext: base64_encode(&data),
+        };
+        write_private_file(&self.path, &serde_json::to_string_pretty(&file)?)
+            .with_context(|| format!("Failed to write the vault '{}'", self.path.display()))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.secrets.get(name).map(|v| v.as_str())
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        self.secrets.insert(name.to_string(), value.to_string());
+        self.save()
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<bool> {
+        if self.secrets.shift_remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.secrets.keys().map(|v| v.as_str()).collect()
+    }
+}
+
+/// Runs `f` on the unlocked vault, failing if no vault has been created yet.
+pub fn read_vault<T>(f: impl FnOnce(&Vault) -> Result<T>) -> Result<T> {
+    with_vault(false, |vault| f(vault))
+}
+
+/// Runs `f` on the unlocked vault, creating it on first use.
+pub fn update_vault<T>(f: impl FnOnce(&mut Vault) -> Result<T>) -> Result<T> {
+    with_vault(true, f)
+}
+
+fn with_vault<T>(create: bool, f: impl FnOnce(&mut Vault) -> Result<T>) -> Result<T> {
+    let mut unlocked = UNLOCKED.lock();
+    if unlocked.is_none() {
+        let path = Vault::default_path();
+        if !create && !path.exists() {
+            bail!(
+                "No vault at '{}', add a secret with `aichat auth vault set <NAME>`",
+                path.display()
+            );
+        }
+        *unlocked = Some(unlock(&path)?);
+    }
+    match unlocked.as_mut() {
+        Some(vault) => f(vault),
+        None => unreachable!(),
+    }
+}
+
+/// Resolves a `vault:<name>` reference, returning other values unchanged.
+pub fn resolve_vault_ref(value: &str) -> Result<String> {
+    match value.strip_prefix(VAULT_PREFIX) {
+        Some(name) => read_vault(|vault| {
+            vault
+                .get(name)
+                .map(|v| v.to_string())
+                .with_context(|| format!("No secret '{name}' in the vault"))
+        }),
+        None => Ok(value.to_string()),
+    }
+}
+
+// Tries the cached session first, then `AICHAT_VAULT_PASSPHRASE`, then prompts.
+fn unlock(path: &Path) -> Result<Vault> {
+    if let Some(key) = read_session() {
+        if let Ok(vault) = Vault::open_with_key(path, key) {
+            return Ok(vault);
+        }
+    }
+    let passphrase = match env::var(get_env_name("vault_passphrase")) {
+        Ok(v) => v,
+        Err(_) => prompt_passphrase(path.exists())?,
+    };
+    let vault = Vault::open(path, &passphrase)?;
+    if let Err(err) = write_session(&vault.key) {
+        warn!("Failed to cache the vault session: {err}");
+    }
+    Ok(vault)
+}
+
+fn prompt_passphrase(exists: bool) -> Result<String> {
+    if !std::io::stdin().is_terminal() {
+        bail!(
+            "The vault is locked, set {} to unlock it",
+            get_env_name("vault_passphrase")
+        );
+    }
+    let prompt = Password::new("Vault passphrase:").with_display_mode(PasswordDisplayMode::Masked);
+    let prompt = if exists {
+        prompt.without_confirmation()
+    } else {
+        prompt.with_custom_confirmation_message("Confirm the new vault passphrase:")
+    };
+    Ok(prompt.prompt()?)
+}
+
+// `AICHAT_VAULT_SESSION_TTL` (seconds) keeps the derived key around so later runs skip the prompt.
+// The key is cached unencrypted in a 0600 file, preferably on the per-user runtime dir, so anyone
+// who can read that file as this user can decrypt the vault until the session expires.
+fn session_ttl() -> Option<i64> {
+    env::var(get_env_name("vault_session_ttl"))
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v > 0)
+}
+
+fn session_path() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(Config::config_dir)
+        .join(SESSION_FILE_NAME)
+}
+
+fn read_session() -> Option<[u8; KEY_LEN]> {
+    session_ttl()?;
+    let path = session_path();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        // Ignore a session file that others could have read
+        if fs::metadata(&path).ok()?.permissions().mode() & 0o077 != 0 {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+    }
+    let session: VaultSession = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+    if session.expires_at <= Utc::now().timestamp() {
+        let _ = fs::remove_file(&path);
+        return None;
+    }
+    base64_decode(&session.key).ok()?.try_into().ok()
+}
+
+fn write_session(key: &[u8; KEY_LEN]) -> Result<()> {
+    let Some(ttl) = session_ttl() else {
+        return Ok(());
+    };
+    let session = VaultSession {
+        key: base64_encode(key),
+        expires_at: Utc::now().timestamp() + ttl,
+    };
+    write_private_file(&session_path(), &serde_json::to_string(&session)?)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> Result<[u8; KEY_LEN]> {
+    let iterations = NonZeroU32::new(iterations).context("Invalid vault iterations")?;
+    let mut key = [0; KEY_LEN];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    Ok(key)
+}
+
+fn sealing_key(key: &[u8; KEY_LEN]) -> Result<LessSafeKey> {
+    let key = UnboundKey::new(&CHACHA20_POLY1305, key).map_err(|_| anyhow!("Invalid vault key"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+fn read_vault_file(path: &Path) -> Result<Option<VaultFile>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the vault '{}'", path.display()))?;
+    let file = serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid vault '{}'", path.display()))?;
+    Ok(Some(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vault_roundtrip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(VAULT_FILE_NAME);
+
+        let mut vault = Vault::create(&path, "passphrase", 1000).unwrap();
+        vault.set("openai", "sk-secret").unwrap();
+        vault.set("claude", "sk-other").unwrap();
+        assert!(vault.remove("claude").unwrap());
+        assert!(!vault.remove("claude").unwrap());
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("sk-secret"));
+
+        let vault = Vault::open(&path, "passphrase").unwrap();
+        assert_eq!(vault.get("openai"), Some("sk-secret"));
+        assert_eq!(vault.names(), ["openai"]);
+        assert!(Vault::open(&path, "wrong").is_err());
+        assert!(Vault::open_with_key(&path, vault.key).is_ok());
+    }
+}
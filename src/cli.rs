@@ -44,6 +44,9 @@ pub struct Cli {
     /// Serve the LLM API and WebAPP
     #[clap(long, value_name = "ADDRESS")]
     pub serve: Option<Option<String>>,
+    /// Serve roles, RAGs, agents and sessions over MCP on stdio
+    #[clap(long)]
+    pub mcp: bool,
     /// Execute commands in natural language
     #[clap(short = 'e', long)]
     pub execute: bool,
@@ -53,15 +56,24 @@ pub struct Cli {
     /// Include files, directories, or URLs
     #[clap(short = 'f', long, value_name = "FILE")]
     pub file: Vec<String>,
+    /// Constrain the output to a JSON schema
+    #[clap(long, value_name = "FILE")]
+    pub schema: Option<String>,
     /// Turn off stream mode
     #[clap(short = 'S', long)]
     pub no_stream: bool,
     /// Display the message without sending it
     #[clap(long)]
     pub dry_run: bool,
+    /// Count the tokens of the message without sending it
+    #[clap(long)]
+    pub count_tokens: bool,
     /// Display information
     #[clap(long)]
     pub info: bool,
+    /// Display usage and cost, grouped by day, model, role, session or agent
+    #[clap(long, value_name = "GROUP")]
+    pub usage: Option<Option<String>>,
     /// Sync models updates
     #[clap(long)]
     pub sync_models: bool,
@@ -98,12 +110,40 @@ pub enum Commands {
 pub struct AuthCommands {
     #[clap(subcommand)]
     pub command: AuthSubcommands,
+    /// Client whose OAuth config and credentials to use
+    #[clap(long, global = true, value_name = "CLIENT", default_value = "gemini")]
+    pub client: String,
 }
 
 #[derive(Parser, Debug)]
 pub enum AuthSubcommands {
     /// Login with OAuth
-    Login,
+    Login {
+        /// Use the device code flow, for machines without a browser
+        #[clap(long)]
+        device: bool,
+    },
+    /// Show the saved OAuth credentials
+    Status,
+    /// Remove the saved OAuth credentials
+    Logout,
+    /// Manage the encrypted vault of API keys and OAuth credentials
+    Vault {
+        #[clap(subcommand)]
+        command: VaultSubcommands,
+    },
+}
+
+#[derive(Parser, Debug)]
+pub enum VaultSubcommands {
+    /// Store a secret, read from a hidden prompt or stdin
+    Set { name: String },
+    /// Print a secret
+    Get { name: String },
+    /// List the secret names
+    List,
+    /// Remove a secret
+    Rm { name: String },
 }
 
 impl Cli {
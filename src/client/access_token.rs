@@ -1,9 +1,14 @@
+use crate::auth::{OAuthAuthenticator, OAuthConfig};
+
 use anyhow::{anyhow, Result};
 use chrono::Utc;
 use indexmap::IndexMap;
 use parking_lot::RwLock;
 use std::sync::LazyLock;
 
+/// Renew an access token this many seconds before it expires
+pub const ACCESS_TOKEN_REFRESH_MARGIN: i64 = 60;
+
 static ACCESS_TOKENS: LazyLock<RwLock<IndexMap<String, (String, i64)>>> =
     LazyLock::new(|| RwLock::new(IndexMap::new()));
 
@@ -30,3 +35,19 @@ pub fn set_access_token(client_name: &str, token: String, expires_at: i64) {
     entry.0 = token;
     entry.1 = expires_at;
 }
+
+/// Returns the access token saved by `aichat auth login`, loading (and refreshing) it only
+/// once it is missing or about to expire.
+pub async fn prepare_oauth_access_token(client_name: &str, config: OAuthConfig) -> Result<String> {
+    if !is_valid_access_token(client_name) {
+        let credentials = OAuthAuthenticator::for_client(client_name, config)?
+            .credentials()
+            .await?;
+        let expires_at = credentials
+            .expires_at()
+            .map(|v| v - ACCESS_TOKEN_REFRESH_MARGIN)
+            .unwrap_or(i64::MAX);
+        set_access_token(client_name, credentials.access_token, expires_at);
+    }
+    get_access_token(client_name)
+}
@@ -0,0 +1,547 @@
+use crate::utils::{encode_uri, hex_encode, hmac_sha256, sha256};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use indexmap::IndexMap;
+use parking_lot::RwLock;
+use reqwest::{Client as ReqwestClient, Method, RequestBuilder, Url};
+use std::{env, path::PathBuf, sync::LazyLock};
+
+const DEFAULT_PROFILE: &str = "default";
+const DEFAULT_ROLE_SESSION_NAME: &str = "aichat";
+const ASSUME_ROLE_DURATION: i64 = 3600;
+// Assume the role again this many seconds before the temporary credentials expire
+const ASSUME_ROLE_REFRESH_MARGIN: i64 = 300;
+// Guards against `source_profile` cycles
+const MAX_ROLE_CHAIN: usize = 8;
+
+// (role_arn, source access key id, external id) -> temporary credentials and their expiry
+static ASSUMED_ROLES: LazyLock<RwLock<IndexMap<String, (AwsCredentials, i64)>>> =
+    LazyLock::new(Default::default);
+
+#[derive(Debug, Clone)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub region: String,
+    pub session_token: Option<String>,
+}
+
+/// Where to find the credentials: static keys first, then the `AWS_ACCESS_KEY_ID` env vars
+/// (unless a profile is set), then a profile of the shared `~/.aws/credentials`/`~/.aws/config`.
+/// `role_arn` is assumed on top of whichever credentials were found.
+#[derive(Debug, Default)]
+pub struct AwsCredentialsSource {
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+    pub region: Option<String>,
+    pub profile: Option<String>,
+    pub role_arn: Option<String>,
+    pub role_session_name: Option<String>,
+    pub external_id: Option<String>,
+    pub sts_endpoint: Option<String>,
+}
+
+pub async fn resolve_aws_credentials(
+    client: &ReqwestClient,
+    source: &AwsCredentialsSource,
+) -> Result<AwsCredentials> {
+    let profiles = load_aws_profiles()?;
+    resolve_aws_credentials_with(client, source, &profiles).await
+}
+
+async fn resolve_aws_credentials_with(
+    client: &ReqwestClient,
+    source: &AwsCredentialsSource,
+    profiles: &AwsProfiles,
+) -> Result<AwsCredentials> {
+    let profile_name = source
+        .profile
+        .clone()
+        .or_else(|| env::var("AWS_PROFILE").ok())
+        .unwrap_or_else(|| DEFAULT_PROFILE.into());
+    let profile = profiles.get(&profile_name);
+    let region = source
+        .region
+        .clone()
+        .or_else(|| env::var("AWS_REGION").ok())
+        .or_else(|| env::var("AWS_DEFAULT_REGION").ok())
+        .or_else(|| profile.and_then(|v| v.get("region").cloned()))
+        .ok_or_else(|| anyhow!("Miss 'region'"))?;
+    let sts_endpoint = source
+        .sts_endpoint
+        .clone()
+        .or_else(|| env::var("AWS_ENDPOINT_URL_STS").ok())
+        .unwrap_or_else(|| format!("https://sts.{region}.amazonaws.com"));
+
+    let env_keys = (
+        env::var("AWS_ACCESS_KEY_ID").ok(),
+        env::var("AWS_SECRET_ACCESS_KEY").ok(),
+    );
+    let credentials = match (&source.access_key_id, &source.secret_access_key) {
+        (Some(access_key_id), Some(secret_access_key)) => AwsCredentials {
+            access_key_id: access_key_id.clone(),
+            secret_access_key: secret_access_key.clone(),
+            region: region.clone(),
+            session_token: source.session_token.clone(),
+        },
+        _ => match env_keys {
+            (Some(access_key_id), Some(secret_access_key)) if source.profile.is_none() => {
+                AwsCredentials {
+                    access_key_id,
+                    secret_access_key,
+                    region: region.clone(),
+                    session_token: env::var("AWS_SESSION_TOKEN").ok(),
+                }
+            }
+            _ => {
+                profile_credentials(client, profiles, &profile_name, &region, &sts_endpoint).await?
+            }
+        },
+    };
+
+    match &source.role_arn {
+        Some(role_arn) => {
+            let role = AssumeRole {
+                role_arn,
+                role_session_name: source.role_session_name.as_deref(),
+                external_id: source.external_id.as_deref(),
+            };
+            assume_role(client, &credentials, &role, &sts_endpoint).await
+        }
+        None => Ok(credentials),
+    }
+}
+
+/// Follows the `source_profile` chain down to a profile with static keys, then assumes
+/// each `role_arn` on the way back up.
+async fn profile_credentials(
+    client: &ReqwestClient,
+    profiles: &AwsProfiles,
+    name: &str,
+    region: &str,
+    sts_endpoint: &str,
+) -> Result<AwsCredentials> {
+    let mut roles = vec![];
+    let mut name = name;
+    let mut credentials = loop {
+        let profile = profiles
+            .get(name)
+            .ok_or_else(|| anyhow!("AWS profile '{name}' not found"))?;
+        let static_keys = match (
+            profile.get("aws_access_key_id"),
+            profile.get("aws_secret_access_key"),
+        ) {
+            (Some(access_key_id), Some(secret_access_key)) => Some(AwsCredentials {
+                access_key_id: access_key_id.clone(),
+                secret_access_key: secret_access_key.clone(),
+                region: region.to_string(),
+                session_token: profile.get("aws_session_token").cloned(),
+            }),
+            _ => None,
+        };
+        match (profile.get("role_arn"), profile.get("source_profile")) {
+            (Some(role_arn), Some(source_profile)) => {
+                if roles.len() >= MAX_ROLE_CHAIN {
+                    bail!("AWS profile '{name}' has a `source_profile` cycle");
+                }
+                roles.push(AssumeRole {
+                    role_arn,
+                    role_session_name: profile.get("role_session_name").map(|v| v.as_str()),
+                    external_id: profile.get("external_id").map(|v| v.as_str()),
+                });
+                // A profile may hold both the role and the keys used to assume it
+                match static_keys {
+                    Some(credentials) if source_profile == name => break credentials,
+                    _ => name = source_profile,
+                }
+            }
+            (Some(_), None) => {
+                bail!("AWS profile '{name}' has a `role_arn` without a `source_profile`")
+            }
+            _ => match static_keys {
+                Some(credentials) => break credentials,
+                None => bail!("AWS profile '{name}' has no credentials"),
+            },
+        }
+    };
+    for role in roles.iter().rev() {
+        credentials = assume_role(client, &credentials, role, sts_endpoint).await?;
+    }
+    Ok(credentials)
+}
+
+#[derive(Debug)]
+struct AssumeRole<'a> {
+    role_arn: &'a str,
+    role_session_name: Option<&'a str>,
+    external_id: Option<&'a str>,
+}
+
+async fn assume_role(
+    client: &ReqwestClient,
+    credentials: &AwsCredentials,
+    role: &AssumeRole<'_>,
+    sts_endpoint: &str,
+) -> Result<AwsCredentials> {
+    let AssumeRole {
+        role_arn,
+        role_session_name,
+        external_id,
+    } = role;
+    let cache_key = format!(
+        "{role_arn}:{}:{}",
+        credentials.access_key_id,
+        external_id.unwrap_or_default()
+    );
+    if let Some((cached, expires_at)) = ASSUMED_ROLES.read().get(&cache_key) {
+        if Utc::now().timestamp() < *expires_at {
+            return Ok(cached.clone());
+        }
+    }
+
+    let mut params = vec![
+        ("Action", "AssumeRole".to_string()),
+        ("Version", "2011-06-15".to_string()),
+        ("RoleArn", role_arn.to_string()),
+        (
+            "RoleSessionName",
+            role_session_name
+                .unwrap_or(DEFAULT_ROLE_SESSION_NAME)
+                .to_string(),
+        ),
+        ("DurationSeconds", ASSUME_ROLE_DURATION.to_string()),
+    ];
+    if let Some(external_id) = external_id {
+        params.push(("ExternalId", external_id.to_string()));
+    }
+    let body = params
+        .iter()
+        .map(|(key, value)| format!("{key}={}", urlencoding::encode(value)))
+        .collect::<Vec<_>>()
+        .join("&");
+    let builder = aws_fetch(
+        client,
+        credentials,
+        AwsRequest {
+            method: Method::POST,
+            endpoint: sts_endpoint.to_string(),
+            service: "sts".into(),
+            uri: "/".into(),
+            querystring: "".into(),
+            headers: IndexMap::from([(
+                "content-type".into(),
+                "application/x-www-form-urlencoded; charset=utf-8".into(),
+            )]),
+            body,
+        },
+    )?;
+    let res = builder.send().await?;
+    let status = res.status();
+    let data = res.text().await?;
+    if !status.is_success() {
+        let message = xml_text(&data, "Message").unwrap_or(&data);
+        bail!("Failed to assume role '{role_arn}': {message}");
+    }
+
+    let field = |name: &str| {
+        xml_text(&data, name)
+            .map(|v| v.to_string())
+            .ok_or_else(|| anyhow!("Invalid AssumeRole response, missing '{name}'"))
+    };
+    let expiration = field("Expiration")?;
+    let expires_at = DateTime::parse_from_rfc3339(&expiration)
+        .with_context(|| format!("Invalid AssumeRole expiration '{expiration}'"))?
+        .timestamp();
+    let assumed = AwsCredentials {
+        access_key_id: field("AccessKeyId")?,
+        secret_access_key: field("SecretAccessKey")?,
+        region: credentials.region.clone(),
+        session_token: Some(field("SessionToken")?),
+    };
+    ASSUMED_ROLES.write().insert(
+        cache_key,
+        (assumed.clone(), expires_at - ASSUME_ROLE_REFRESH_MARGIN),
+    );
+    Ok(assumed)
+}
+
+fn xml_text<'a>(data: &'a str, tag: &str) -> Option<&'a str> {
+    let start = data.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let end = start + data[start..].find(&format!("</{tag}>"))?;
+    Some(data[start..end].trim())
+}
+
+/// Profile name -> settings, merged from the shared credentials and config files.
+type AwsProfiles = IndexMap<String, IndexMap<String, String>>;
+
+fn load_aws_profiles() -> Result<AwsProfiles> {
+    let aws_dir = dirs::home_dir().map(|v| v.join(".aws"));
+    let config_file = env::var("AWS_CONFIG_FILE")
+        .ok()
+        .map(PathBuf::from)
+        .or_else(|| aws_dir.as_ref().map(|v| v.join("config")));
+    let credentials_file = env::var("AWS_SHARED_CREDENTIALS_FILE")
+        .ok()
+        .map(PathBuf::from)
+        .or_else(|| aws_dir.as_ref().map(|v| v.join("credentials")));
+
+    let mut profiles = AwsProfiles::new();
+    for (path, is_config) in [(config_file, true), (credentials_file, false)] {
+        let Some(path) = path.filter(|v| v.exists()) else {
+            continue;
+        };
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        merge_aws_profiles(&mut profiles, &text, is_config);
+    }
+    Ok(profiles)
+}
+
+/// Parses an AWS ini file, where the config file names its sections `[profile <name>]`.
+fn merge_aws_profiles(profiles: &mut AwsProfiles, text: &str, is_config: bool) {
+    let mut section: Option<String> = None;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            let name = name.trim();
+            section = match (is_config, name.strip_prefix("profile ")) {
+                (true, Some(name)) => Some(name.trim().to_string()),
+                (true, None) if name != DEFAULT_PROFILE => None,
+                _ => Some(name.to_string()),
+            };
+            continue;
+        }
+        if let (Some(section), Some((key, value))) = (&section, line.split_once('=')) {
+            profiles
+                .entry(section.clone())
+                .or_default()
+                .insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AwsRequest {
+    pub method: Method,
+    pub endpoint: String,
+    pub service: String,
+    pub uri: String,
+    pub querystring: String,
+    pub headers: IndexMap<String, String>,
+    pub body: String,
+}
+
+pub fn aws_fetch(
+    client: &ReqwestClient,
+    credentials: &AwsCredentials,
+    request: AwsRequest,
+) -> Result<RequestBuilder> {
+    let AwsRequest {
+        method,
+        endpoint,
+        service,
+        uri,
+        querystring,
+        mut headers,
+        body,
+    } = request;
+    let region = &credentials.region;
+
+    let endpoint_url =
+        Url::parse(&endpoint).with_context(|| format!("Invalid endpoint '{endpoint}'"))?;
+    let host = match (endpoint_url.host_str(), endpoint_url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => bail!("Invalid endpoint '{endpoint}'"),
+    };
+    let endpoint = format!("{}{uri}", endpoint.trim_end_matches('/'));
+
+    let now: DateTime<Utc> = Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date_stamp = amz_date[0..8].to_string();
+    headers.insert("host".into(), host);
+    headers.insert("x-amz-date".into(), amz_date.clone());
+    if let Some(token) = credentials.session_token.clone() {
+        headers.insert("x-amz-security-token".into(), token);
+    }
+    headers.sort_keys();
+
+    let canonical_headers = headers
+        .iter()
+        .map(|(key, value)| format!("{key}:{value}\n"))
+        .collect::<Vec<_>>()
+        .join("");
+
+    let signed_headers = headers
+        .iter()
+        .map(|(key, _)| key.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let payload_hash = sha256(&body);
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        encode_uri(&uri),
+        querystring,
+        canonical_headers,
+        signed_headers,
+        payload_hash
+    );
+
+    let algorithm = "AWS4-HMAC-SHA256";
+    let credential_scope = format!("{date_stamp}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        algorithm,
+        amz_date,
+        credential_scope,
+        sha256(&canonical_request)
+    );
+
+    let signing_key = gen_signing_key(
+        &credentials.secret_access_key,
+        &date_stamp,
+        region,
+        &service,
+    );
+    let signature = hmac_sha256(&signing_key, &string_to_sign);
+    let signature = hex_encode(&signature);
+
+    let authorization_header = format!(
+        "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+        algorithm, credentials.access_key_id, credential_scope, signed_headers, signature
+    );
+
+    headers.insert("authorization".into(), authorization_header);
+
+    debug!("Request {endpoint} {body}");
+
+    let mut request_builder = client.request(method, endpoint).body(body);
+
+    for (key, value) in &headers {
+        request_builder = request_builder.header(key, value);
+    }
+
+    Ok(request_builder)
+}
+
+fn gen_signing_key(key: &str, date_stamp: &str, region: &str, service: &str) -> Vec<u8> {
+    let k_date = hmac_sha256(format!("AWS4{key}").as_bytes(), date_stamp);
+    let k_region = hmac_sha256(&k_date, region);
+    let k_service = hmac_sha256(&k_region, service);
+    hmac_sha256(&k_service, "aws4_request")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::oauth_split::mock_server::mock_oauth_server;
+
+    #[test]
+    fn test_merge_aws_profiles() {
+        let mut profiles = AwsProfiles::new();
+        merge_aws_profiles(
+            &mut profiles,
+            r#"
+[default]
+region = us-east-1
+
+[profile ci]
+role_arn = arn:aws:iam::123456789012:role/ci
+source_profile = default
+
+[sso-session corp]
+sso_region = us-east-1
+"#,
+            true,
+        );
+        merge_aws_profiles(
+            &mut profiles,
+            r#"
+# static keys
+[default]
+aws_access_key_id = AKIDEXAMPLE
+aws_secret_access_key = secret
+"#,
+            false,
+        );
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles["default"]["region"], "us-east-1");
+        assert_eq!(profiles["default"]["aws_access_key_id"], "AKIDEXAMPLE");
+        assert_eq!(profiles["ci"]["source_profile"], "default");
+    }
+
+    #[tokio::test]
+    async fn test_assume_role_from_profile() {
+        let (base_url, handle) = mock_oauth_server(vec![(
+            200,
+            r#"<AssumeRoleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleResult>
+    <Credentials>
+      <AccessKeyId>ASIATEMP</AccessKeyId>
+      <SecretAccessKey>temp-secret</SecretAccessKey>
+      <SessionToken>temp-token</SessionToken>
+      <Expiration>2099-01-01T00:00:00Z</Expiration>
+    </Credentials>
+  </AssumeRoleResult>
+</AssumeRoleResponse>"#,
+        )]);
+        let mut profiles = AwsProfiles::new();
+        merge_aws_profiles(
+            &mut profiles,
+            r#"
+[source]
+aws_access_key_id = AKIDSOURCE
+aws_secret_access_key = source-secret
+
+[ci]
+role_arn = arn:aws:iam::123456789012:role/ci
+source_profile = source
+external_id = ext-1
+"#,
+            false,
+        );
+        let source = AwsCredentialsSource {
+            region: Some("us-west-2".into()),
+            profile: Some("ci".into()),
+            sts_endpoint: Some(base_url),
+            ..Default::default()
+        };
+
+        let client = ReqwestClient::new();
+        for _ in 0..2 {
+            let credentials = resolve_aws_credentials_with(&client, &source, &profiles)
+                .await
+                .unwrap();
+            assert_eq!(credentials.access_key_id, "ASIATEMP");
+            assert_eq!(credentials.secret_access_key, "temp-secret");
+            assert_eq!(credentials.session_token.as_deref(), Some("temp-token"));
+            assert_eq!(credentials.region, "us-west-2");
+        }
+
+        let requests = handle.join().unwrap();
+        assert_eq!(requests.len(), 1);
+        let (request_line, body) = &requests[0];
+        assert_eq!(request_line, "POST / HTTP/1.1");
+        assert!(body.contains("Action=AssumeRole"));
+        assert!(body.contains("RoleArn=arn%3Aaws%3Aiam%3A%3A123456789012%3Arole%2Fci"));
+        assert!(body.contains("ExternalId=ext-1"));
+
+        let source = AwsCredentialsSource {
+            region: Some("us-west-2".into()),
+            profile: Some("missing".into()),
+            ..Default::default()
+        };
+        let err = resolve_aws_credentials_with(&client, &source, &profiles)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "AWS profile 'missing' not found");
+    }
+}
@@ -1,8 +1,17 @@
+use super::access_token::*;
 use super::openai::*;
 use super::*;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use reqwest::Client as ReqwestClient;
 use serde::Deserialize;
+use serde_json::Value;
+use std::env;
+
+const DEFAULT_AUTHORITY_HOST: &str = "https://login.microsoftonline.com";
+const COGNITIVE_SERVICES_SCOPE: &str = "https://cognitiveservices.azure.com/.default";
+const JWT_BEARER_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct AzureOpenAIConfig {
@@ -10,6 +19,14 @@ pub struct AzureOpenAIConfig {
     pub api_base: Option<String>,
     pub api_key: Option<String>,
     #[serde(default)]
+    pub api_keys: Vec<String>,
+    /// Authenticate with Azure AD (Entra ID) instead of the api key when set
+    pub tenant_id: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub federated_token_file: Option<String>,
+    pub authority_host: Option<String>,
+    #[serde(default)]
     pub models: Vec<ModelData>,
     pub patch: Option<RequestPatch>,
     pub extra: Option<ExtraConfig>,
@@ -17,7 +34,12 @@ pub struct AzureOpenAIConfig {
 
 impl AzureOpenAIClient {
     config_get_fn!(api_base, get_api_base);
-    config_get_fn!(api_key, get_api_key);
+    config_get_api_key_fn!(get_api_key);
+    config_get_fn!(tenant_id, get_tenant_id);
+    config_get_fn!(client_id, get_client_id);
+    config_get_fn!(client_secret, get_client_secret);
+    config_get_fn!(federated_token_file, get_federated_token_file);
+    config_get_fn!(authority_host, get_authority_host);
 
     pub const PROMPTS: [PromptAction<'static>; 2] = [
         (
@@ -27,6 +49,52 @@ impl AzureOpenAIClient {
         ),
         ("api_key", "API Key", None),
     ];
+
+    /// Sends an Azure AD bearer token if `tenant_id` is set, the `api-key` header otherwise.
+    async fn authenticate(&self, request_data: &mut RequestData) -> Result<()> {
+        let tenant_id = match self.get_tenant_id() {
+            Ok(v) => v,
+            Err(_) => {
+                request_data.header("api-key", self.get_api_key()?);
+                return Ok(());
+            }
+        };
+        if !is_valid_access_token(self.name()) {
+            // The workload identity webhook injects the standard `AZURE_*` variables
+            let client_id = self
+                .get_client_id()
+                .or_else(|_| env::var("AZURE_CLIENT_ID"))
+                .context("Miss 'client_id'")?;
+            let credential = match self.get_client_secret() {
+                Ok(client_secret) => AzureAdCredential::ClientSecret(client_secret),
+                Err(_) => match self
+                    .get_federated_token_file()
+                    .or_else(|_| env::var("AZURE_FEDERATED_TOKEN_FILE"))
+                {
+                    Ok(file) => AzureAdCredential::FederatedTokenFile(file),
+                    Err(_) => bail!("Miss 'client_secret' or 'federated_token_file'"),
+                },
+            };
+            let authority_host = self
+                .get_authority_host()
+                .or_else(|_| env::var("AZURE_AUTHORITY_HOST"))
+                .unwrap_or_else(|_| DEFAULT_AUTHORITY_HOST.into());
+            let client = self.build_client()?;
+            let (token, expires_in) = fetch_azure_ad_token(
+                &client,
+                &authority_host,
+                &tenant_id,
+                &client_id,
+                &credential,
+            )
+            .await
+            .with_context(|| "Failed to fetch Azure AD access token")?;
+            let expires_at = Utc::now().timestamp() + expires_in - ACCESS_TOKEN_REFRESH_MARGIN;
+            set_access_token(self.name(), token, expires_at);
+        }
+        request_data.bearer_auth(get_access_token(self.name())?);
+        Ok(())
+    }
 }
 
 impl_client_trait!(
@@ -45,7 +113,6 @@ async fn prepare_chat_completions(
     data: ChatCompletionsData,
 ) -> Result<RequestData> {
     let api_base = self_.get_api_base()?;
-    let api_key = self_.get_api_key()?;
 
     let url = format!(
         "{}/openai/deployments/{}/chat/completions?api-version=2024-12-01-preview",
@@ -57,14 +124,13 @@ async fn prepare_chat_completions(
 
     let mut request_data = RequestData::new(url, body);
 
-    request_data.header("api-key", api_key);
+    self_.authenticate(&mut request_data).await?;
 
     Ok(request_data)
 }
 
 async fn prepare_embeddings(self_: &AzureOpenAIClient, data: &EmbeddingsData) -> Result<RequestData> {
     let api_base = self_.get_api_base()?;
-    let api_key = self_.get_api_key()?;
 
     let url = format!(
         "{}/openai/deployments/{}/embeddings?api-version=2024-10-21",
@@ -76,7 +142,118 @@ async fn prepare_embeddings(self_: &AzureOpenAIClient, data: &EmbeddingsData) ->
 
     let mut request_data = RequestData::new(url, body);
 
-    request_data.header("api-key", api_key);
+    self_.authenticate(&mut request_data).await?;
 
     Ok(request_data)
 }
+
+#[derive(Debug)]
+enum AzureAdCredential {
+    ClientSecret(String),
+    /// Kubernetes workload identity, the file holds a projected service account token
+    FederatedTokenFile(String),
+}
+
+async fn fetch_azure_ad_token(
+    client: &ReqwestClient,
+    authority_host: &str,
+    tenant_id: &str,
+    client_id: &str,
+    credential: &AzureAdCredential,
+) -> Result<(String, i64)> {
+    let url = format!(
+        "{}/{tenant_id}/oauth2/v2.0/token",
+        authority_host.trim_end_matches('/')
+    );
+    let mut form = vec![
+        ("grant_type", "client_credentials".to_string()),
+        ("client_id", client_id.to_string()),
+        ("scope", COGNITIVE_SERVICES_SCOPE.to_string()),
+    ];
+    match credential {
+        AzureAdCredential::ClientSecret(client_secret) => {
+            form.push(("client_secret", client_secret.clone()));
+        }
+        AzureAdCredential::FederatedTokenFile(file) => {
+            let assertion = tokio::fs::read_to_string(file)
+                .await
+                .with_context(|| format!("Failed to read federated token file '{file}'"))?;
+            form.push((
+                "client_assertion_type",
+                JWT_BEARER_ASSERTION_TYPE.to_string(),
+            ));
+            form.push(("client_assertion", assertion.trim().to_string()));
+        }
+    }
+    let value: Value = client.post(url).form(&form).send().await?.json().await?;
+
+    // v1 endpoints return `expires_in` as a string
+    let expires_in = value["expires_in"]
+        .as_i64()
+        .or_else(|| value["expires_in"].as_str().and_then(|v| v.parse().ok()));
+    if let (Some(access_token), Some(expires_in)) = (value["access_token"].as_str(), expires_in) {
+        Ok((access_token.to_string(), expires_in))
+    } else if let Some(err_msg) = value["error_description"].as_str() {
+        bail!("{err_msg}")
+    } else {
+        bail!("Invalid response data: {value}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::oauth_split::mock_server::mock_oauth_server;
+
+    #[tokio::test]
+    async fn test_fetch_azure_ad_token() {
+        let (base_url, handle) = mock_oauth_server(vec![
+            (
+                200,
+                r#"{"token_type":"Bearer","expires_in":3599,"access_token":"secret-token"}"#,
+            ),
+            (
+                200,
+                r#"{"token_type":"Bearer","expires_in":"3599","access_token":"federated-token"}"#,
+            ),
+            (
+                400,
+                r#"{"error":"invalid_client","error_description":"AADSTS7000215: Invalid client secret provided."}"#,
+            ),
+        ]);
+        let temp_dir = tempfile::tempdir().unwrap();
+        let token_file = temp_dir.path().join("azure-identity-token");
+        std::fs::write(&token_file, "service-account-jwt\n").unwrap();
+        let client = ReqwestClient::new();
+
+        let credential = AzureAdCredential::ClientSecret("secret".into());
+        let output = fetch_azure_ad_token(&client, &base_url, "tenant-1", "app-1", &credential)
+            .await
+            .unwrap();
+        assert_eq!(output, ("secret-token".into(), 3599));
+
+        let credential = AzureAdCredential::FederatedTokenFile(token_file.display().to_string());
+        let output = fetch_azure_ad_token(&client, &base_url, "tenant-1", "app-1", &credential)
+            .await
+            .unwrap();
+        assert_eq!(output, ("federated-token".into(), 3599));
+
+        let credential = AzureAdCredential::ClientSecret("wrong".into());
+        let err = fetch_azure_ad_token(&client, &base_url, "tenant-1", "app-1", &credential)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("AADSTS7000215"));
+
+        let requests = handle.join().unwrap();
+        assert_eq!(requests[0].0, "POST /tenant-1/oauth2/v2.0/token HTTP/1.1");
+        assert!(requests[0].1.contains("grant_type=client_credentials"));
+        assert!(requests[0].1.contains("client_secret=secret"));
+        assert!(requests[0]
+            .1
+            .contains("scope=https%3A%2F%2Fcognitiveservices.azure.com%2F.default"));
+        assert!(requests[1]
+            .1
+            .ends_with("client_assertion=service-account-jwt"));
+        assert!(!requests[1].1.contains("client_secret"));
+    }
+}
@@ -1,14 +1,14 @@
+use super::aws::*;
+use super::claude::claude_thinking;
 use super::*;
 
-use crate::utils::{base64_decode, encode_uri, hex_encode, hmac_sha256, sha256, strip_think_tag};
+use crate::utils::{base64_decode, strip_think_tag};
 
 use anyhow::{bail, Context, Result};
 use aws_smithy_eventstream::frame::{DecodedFrame, MessageFrameDecoder};
 use aws_smithy_eventstream::smithy::parse_response_headers;
 use bytes::BytesMut;
-use chrono::{DateTime, Utc};
 use futures_util::StreamExt;
-use indexmap::IndexMap;
 use reqwest::{Client as ReqwestClient, Method, RequestBuilder};
 use serde::Deserialize;
 use serde_json::{json, Value};
@@ -20,6 +20,12 @@ pub struct BedrockConfig {
     pub secret_access_key: Option<String>,
     pub region: Option<String>,
     pub session_token: Option<String>,
+    pub profile: Option<String>,
+    pub role_arn: Option<String>,
+    pub role_session_name: Option<String>,
+    pub external_id: Option<String>,
+    pub endpoint: Option<String>,
+    pub sts_endpoint: Option<String>,
     #[serde(default)]
     pub models: Vec<ModelData>,
     pub patch: Option<RequestPatch>,
@@ -31,6 +37,9 @@ impl BedrockClient {
     config_get_fn!(secret_access_key, get_secret_access_key);
     config_get_fn!(region, get_region);
     config_get_fn!(session_token, get_session_token);
+    config_get_fn!(profile, get_profile);
+    config_get_fn!(role_arn, get_role_arn);
+    config_get_fn!(endpoint, get_endpoint);
 
     pub const PROMPTS: [PromptAction<'static>; 3] = [
         ("access_key_id", "AWS Access Key ID", None),
@@ -38,16 +47,37 @@ impl BedrockClient {
         ("region", "AWS Region", None),
     ];
 
-    fn chat_completions_builder(
+    async fn credentials(&self, client: &ReqwestClient) -> Result<AwsCredentials> {
+        let source = AwsCredentialsSource {
+            access_key_id: self.get_access_key_id().ok(),
+            secret_access_key: self.get_secret_access_key().ok(),
+            session_token: self.get_session_token().ok(),
+            region: self.get_region().ok(),
+            profile: self.get_profile().ok(),
+            role_arn: self.get_role_arn().ok(),
+            role_session_name: self.config.role_session_name.clone(),
+            external_id: self.config.external_id.clone(),
+            sts_endpoint: self.config.sts_endpoint.clone(),
+        };
+        resolve_aws_credentials(client, &source)
+            .await
+            .with_context(|| "Failed to load AWS credentials")
+    }
+
+    fn runtime_endpoint(&self, region: &str) -> String {
+        self.get_endpoint()
+            .ok()
+            .or_else(|| std::env::var("AWS_ENDPOINT_URL_BEDROCK_RUNTIME").ok())
+            .unwrap_or_else(|| format!("https://bedrock-runtime.{region}.amazonaws.com"))
+    }
+
+    async fn chat_completions_builder(
         &self,
         client: &ReqwestClient,
         data: ChatCompletionsData,
     ) -> Result<RequestBuilder> {
-        let access_key_id = self.get_access_key_id()?;
-        let secret_access_key = self.get_secret_access_key()?;
-        let region = self.get_region()?;
-        let session_token = self.get_session_token().ok();
-        let host = format!("bedrock-runtime.{region}.amazonaws.com");
+        let credentials = self.credentials(client).await?;
+        let endpoint = self.runtime_endpoint(&credentials.region);
 
         let model_name = &self.model.real_name();
 
@@ -61,6 +91,7 @@ impl BedrockClient {
 
         let mut request_data = RequestData::new("", body);
         self.patch_request_data(&mut request_data);
+        cassette_capture_request(&request_data);
         let RequestData {
             url: _,
             headers,
@@ -69,15 +100,10 @@ impl BedrockClient {
 
         let builder = aws_fetch(
             client,
-            &AwsCredentials {
-                access_key_id,
-                secret_access_key,
-                region,
-                session_token,
-            },
+            &credentials,
             AwsRequest {
                 method: Method::POST,
-                host,
+                endpoint,
                 service: "bedrock".into(),
                 uri,
                 querystring: "".into(),
@@ -89,16 +115,13 @@ impl BedrockClient {
         Ok(builder)
     }
 
-    fn embeddings_builder(
+    async fn embeddings_builder(
         &self,
         client: &ReqwestClient,
         data: &EmbeddingsData,
     ) -> Result<RequestBuilder> {
-        let access_key_id = self.get_access_key_id()?;
-        let secret_access_key = self.get_secret_access_key()?;
-        let region = self.get_region()?;
-        let session_token = self.get_session_token().ok();
-        let host = format!("bedrock-runtime.{region}.amazonaws.com");
+        let credentials = self.credentials(client).await?;
+        let endpoint = self.runtime_endpoint(&credentials.region);
 
         let uri = format!("/model/{}/invoke", self.model.real_name());
 
@@ -114,6 +137,7 @@ impl BedrockClient {
 
         let mut request_data = RequestData::new("", body);
         self.patch_request_data(&mut request_data);
+        cassette_capture_request(&request_data);
         let RequestData {
             url: _,
             headers,
@@ -122,15 +146,10 @@ impl BedrockClient {
 
         let builder = aws_fetch(
             client,
-            &AwsCredentials {
-                access_key_id,
-                secret_access_key,
-                region,
-                session_token,
-            },
+            &credentials,
             AwsRequest {
                 method: Method::POST,
-                host,
+                endpoint,
                 service: "bedrock".into(),
                 uri,
                 querystring: "".into(),
@@ -152,7 +171,7 @@ impl Client for BedrockClient {
         client: &ReqwestClient,
         data: ChatCompletionsData,
     ) -> Result<ChatCompletionsOutput> {
-        let builder = self.chat_completions_builder(client, data)?;
+        let builder = self.chat_completions_builder(client, data).await?;
         chat_completions(builder).await
     }
 
@@ -162,7 +181,7 @@ impl Client for BedrockClient {
         handler: &mut SseHandler,
         data: ChatCompletionsData,
     ) -> Result<()> {
-        let builder = self.chat_completions_builder(client, data)?;
+        let builder = self.chat_completions_builder(client, data).await?;
         chat_completions_streaming(builder, handler).await
     }
 
@@ -171,20 +190,15 @@ impl Client for BedrockClient {
         client: &ReqwestClient,
         data: &EmbeddingsData,
     ) -> Result<EmbeddingsOutput> {
-        let builder = self.embeddings_builder(client, data)?;
+        let builder = self.embeddings_builder(client, data).await?;
         embeddings(builder).await
     }
 }
 
 async fn chat_completions(builder: RequestBuilder) -> Result<ChatCompletionsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
 
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
-
     debug!("non-stream-data: {data}");
     extract_chat_completions(&data)
 }
@@ -193,18 +207,11 @@ async fn chat_completions_streaming(
     builder: RequestBuilder,
     handler: &mut SseHandler,
 ) -> Result<()> {
-    let res = builder.send().await?;
-    let status = res.status();
-    if !status.is_success() {
-        let data: Value = res.json().await?;
-        catch_error(&data, status.as_u16())?;
-        bail!("Invalid response data: {data}");
-    }
+    let res = send_request(builder).await?;
 
     let mut function_name = String::new();
     let mut function_arguments = String::new();
     let mut function_id = String::new();
-    let mut reasoning_state = 0;
 
     let mut stream = res.bytes_stream();
     let mut buffer = BytesMut::new();
@@ -253,33 +260,26 @@ async fn chat_completions_streaming(
                             } else if let Some(text) =
                                 data["delta"]["reasoningContent"]["text"].as_str()
                             {
-                                if reasoning_state == 0 {
-                                    handler.text("<think>\n")?;
-                                    reasoning_state = 1;
-                                }
-                                handler.text(text)?;
+                                handler.reasoning(text)?;
                             } else if let Some(input) = data["delta"]["toolUse"]["input"].as_str() {
                                 function_arguments.push_str(input);
                             }
                         }
-                        "contentBlockStop" => {
-                            if reasoning_state == 1 {
-                                handler.text("\n</think>\n\n")?;
-                                reasoning_state = 0;
-                            }
-                            if !function_name.is_empty() {
-                                if function_arguments.is_empty() {
-                                    function_arguments = String::from("{}");
-                                }
-                                let arguments: Value = function_arguments.parse().with_context(|| {
-                                    format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                                })?;
-                                handler.tool_call(ToolCall::new(
-                                    function_name.clone(),
-                                    arguments,
-                                    Some(function_id.clone()),
-                                ))?;
+                        "metadata" => {
+                            handler.update_usage(extract_usage(&data["usage"]));
+                        }
+                        "contentBlockStop" if !function_name.is_empty() => {
+                            if function_arguments.is_empty() {
+                                function_arguments = String::from("{}");
                             }
+                            let arguments: Value = function_arguments.parse().with_context(|| {
+                                format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
+                            })?;
+                            handler.tool_call(ToolCall::new(
+                                function_name.clone(),
+                                arguments,
+                                Some(function_id.clone()),
+                            ))?;
                         }
                         _ => {}
                     }
@@ -300,14 +300,9 @@ async fn chat_completions_streaming(
 }
 
 async fn embeddings(builder: RequestBuilder) -> Result<EmbeddingsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
 
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
-
     let res_body: EmbeddingsResBody =
         serde_json::from_value(data).context("Invalid embeddings data")?;
     Ok(res_body.embeddings)
@@ -325,6 +320,8 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
         top_p,
         functions,
         stream: _,
+        schema,
+        reasoning,
     } = data;
 
     let system_message = extract_system_message(&mut messages);
@@ -336,7 +333,7 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
         .into_iter()
         .enumerate()
         .flat_map(|(i, message)| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             match content {
                 MessageContent::Text(text) if role.is_assistant() && i != messages_len - 1 => {
                     vec![json!({ "role": role, "content": [ { "text": strip_think_tag(&text) } ] })]
@@ -455,6 +452,21 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
     if let Some(v) = top_p {
         body["inferenceConfig"]["topP"] = v.into();
     }
+    if let Some(reasoning) = reasoning.filter(|v| !v.is_off() && schema.is_none()) {
+        if model.real_name().contains("anthropic.") {
+            let thinking = claude_thinking(reasoning, &mut body["inferenceConfig"]["maxTokens"]);
+            body["additionalModelRequestFields"]["thinking"] = thinking;
+            if let Some(config) = body["inferenceConfig"].as_object_mut() {
+                config.remove("temperature");
+                config.remove("topP");
+            }
+        } else if let Some(effort) = reasoning.effort() {
+            body["additionalModelRequestFields"]["reasoningConfig"] = json!({
+                "type": "enabled",
+                "maxReasoningEffort": effort,
+            });
+        }
+    }
     if let Some(functions) = functions {
         let tools: Vec<_> = functions
             .iter()
@@ -474,9 +486,48 @@ fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Resu
             "tools": tools,
         })
     }
+    if let Some(schema) = schema {
+        let tool = json!({
+            "toolSpec": {
+                "name": STRUCTURED_OUTPUT_TOOL,
+                "description": "Respond with the structured output.",
+                "inputSchema": {
+                    "json": schema,
+                },
+            }
+        });
+        match body["toolConfig"]["tools"].as_array_mut() {
+            Some(tools) => tools.push(tool),
+            None => body["toolConfig"]["tools"] = json!([tool]),
+        }
+        body["toolConfig"]["toolChoice"] = json!({ "tool": { "name": STRUCTURED_OUTPUT_TOOL } });
+    }
+    if model.real_name().contains("anthropic.") && !model.no_prompt_caching() {
+        add_cache_points(&mut body);
+    }
     Ok(body)
 }
 
+/// Bedrock's counterpart of Claude's `cache_control`: a `cachePoint` block after the system
+/// prompt, the tools and, once a conversation has history, its latest message.
+fn add_cache_points(body: &mut Value) {
+    let cache_point = json!({ "cachePoint": { "type": "default" } });
+    if let Some(system) = body["system"].as_array_mut() {
+        system.push(cache_point.clone());
+    }
+    if let Some(tools) = body["toolConfig"]["tools"].as_array_mut() {
+        tools.push(cache_point.clone());
+    }
+    let content = body["messages"]
+        .as_array_mut()
+        .filter(|v| v.len() > 1)
+        .and_then(|v| v.last_mut())
+        .and_then(|v| v["content"].as_array_mut());
+    if let Some(content) = content {
+        content.push(cache_point);
+    }
+}
+
 fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
     let mut text = String::new();
     let mut reasoning = None;
@@ -500,6 +551,10 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
                     json_str_from_map(tool_use, "name"),
                     tool_use.get("input"),
                 ) {
+                    if name == STRUCTURED_OUTPUT_TOOL {
+                        text.push_str(&input.to_string());
+                        continue;
+                    }
                     tool_calls.push(ToolCall::new(
                         name.to_string(),
                         input.clone(),
@@ -510,134 +565,85 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
         }
     }
 
-    if let Some(reasoning) = reasoning {
-        text = format!("<think>\n{reasoning}\n</think>\n\n{text}")
-    }
-
     if text.is_empty() && tool_calls.is_empty() {
         bail!("Invalid response data: {data}");
     }
 
+    let usage = extract_usage(&data["usage"]);
     let output = ChatCompletionsOutput {
         text,
+        reasoning,
         tool_calls,
         id: None,
-        input_tokens: data["usage"]["inputTokens"].as_u64(),
-        output_tokens: data["usage"]["outputTokens"].as_u64(),
+        input_tokens: usage.input_tokens,
+        output_tokens: usage.output_tokens,
+        cache_creation_input_tokens: usage.cache_creation_input_tokens,
+        cache_read_input_tokens: usage.cache_read_input_tokens,
     };
     Ok(output)
 }
 
-#[derive(Debug)]
-struct AwsCredentials {
-    access_key_id: String,
-    secret_access_key: String,
-    region: String,
-    session_token: Option<String>,
-}
-
-#[derive(Debug)]
-struct AwsRequest {
-    method: Method,
-    host: String,
-    service: String,
-    uri: String,
-    querystring: String,
-    headers: IndexMap<String, String>,
-    body: String,
+fn extract_usage(usage: &Value) -> StreamUsage {
+    StreamUsage {
+        input_tokens: usage["inputTokens"].as_u64(),
+        output_tokens: usage["outputTokens"].as_u64(),
+        cache_creation_input_tokens: usage["cacheWriteInputTokens"].as_u64(),
+        cache_read_input_tokens: usage["cacheReadInputTokens"].as_u64(),
+    }
 }
 
-fn aws_fetch(
-    client: &ReqwestClient,
-    credentials: &AwsCredentials,
-    request: AwsRequest,
-) -> Result<RequestBuilder> {
-    let AwsRequest {
-        method,
-        host,
-        service,
-        uri,
-        querystring,
-        mut headers,
-        body,
-    } = request;
-    let region = &credentials.region;
-
-    let endpoint = format!("https://{host}{uri}");
-
-    let now: DateTime<Utc> = Utc::now();
-    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
-    let date_stamp = amz_date[0..8].to_string();
-    headers.insert("host".into(), host.clone());
-    headers.insert("x-amz-date".into(), amz_date.clone());
-    if let Some(token) = credentials.session_token.clone() {
-        headers.insert("x-amz-security-token".into(), token);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_data() -> ChatCompletionsData {
+        let messages = vec![
+            Message::new(MessageRole::System, MessageContent::Text("Be brief".into())),
+            Message::new(MessageRole::User, MessageContent::Text("Hi".into())),
+            Message::new(MessageRole::Assistant, MessageContent::Text("Hello".into())),
+            Message::new(MessageRole::User, MessageContent::Text("Bye".into())),
+        ];
+        let functions = serde_json::from_value(json!([{
+            "name": "get_weather",
+            "description": "Get the weather",
+            "parameters": { "type": "object", "properties": {} },
+        }]))
+        .unwrap();
+        ChatCompletionsData {
+            messages,
+            temperature: None,
+            top_p: None,
+            functions: Some(functions),
+            stream: false,
+            schema: None,
+            reasoning: None,
+        }
     }
 
-    let canonical_headers = headers
-        .iter()
-        .map(|(key, value)| format!("{key}:{value}\n"))
-        .collect::<Vec<_>>()
-        .join("");
-
-    let signed_headers = headers
-        .iter()
-        .map(|(key, _)| key.as_str())
-        .collect::<Vec<_>>()
-        .join(";");
-
-    let payload_hash = sha256(&body);
-
-    let canonical_request = format!(
-        "{}\n{}\n{}\n{}\n{}\n{}",
-        method,
-        encode_uri(&uri),
-        querystring,
-        canonical_headers,
-        signed_headers,
-        payload_hash
-    );
-
-    let algorithm = "AWS4-HMAC-SHA256";
-    let credential_scope = format!("{date_stamp}/{region}/{service}/aws4_request");
-    let string_to_sign = format!(
-        "{}\n{}\n{}\n{}",
-        algorithm,
-        amz_date,
-        credential_scope,
-        sha256(&canonical_request)
-    );
-
-    let signing_key = gen_signing_key(
-        &credentials.secret_access_key,
-        &date_stamp,
-        region,
-        &service,
-    );
-    let signature = hmac_sha256(&signing_key, &string_to_sign);
-    let signature = hex_encode(&signature);
-
-    let authorization_header = format!(
-        "{} Credential={}/{}, SignedHeaders={}, Signature={}",
-        algorithm, credentials.access_key_id, credential_scope, signed_headers, signature
-    );
-
-    headers.insert("authorization".into(), authorization_header);
-
-    debug!("Request {endpoint} {body}");
-
-    let mut request_builder = client.request(method, endpoint).body(body);
-
-    for (key, value) in &headers {
-        request_builder = request_builder.header(key, value);
-    }
+    #[test]
+    fn test_build_chat_completions_body_cache_points() {
+        let cache_point = json!({ "cachePoint": { "type": "default" } });
+        let mut model = Model::new("bedrock", "anthropic.claude-sonnet-4-20250514-v1:0");
+        let body = build_chat_completions_body(create_data(), &model).unwrap();
+        assert_eq!(body["system"], json!([{ "text": "Be brief" }, cache_point]));
+        let tools = body["toolConfig"]["tools"].as_array().unwrap();
+        assert_eq!(tools.len(), 2);
+        assert_eq!(tools[1], cache_point);
+        assert_eq!(
+            body["messages"],
+            json!([
+                { "role": "user", "content": [{ "text": "Hi" }] },
+                { "role": "assistant", "content": [{ "text": "Hello" }] },
+                { "role": "user", "content": [{ "text": "Bye" }, cache_point] },
+            ])
+        );
 
-    Ok(request_builder)
-}
+        model.data_mut().no_prompt_caching = true;
+        let body = build_chat_completions_body(create_data(), &model).unwrap();
+        assert!(!body.to_string().contains("cachePoint"));
 
-fn gen_signing_key(key: &str, date_stamp: &str, region: &str, service: &str) -> Vec<u8> {
-    let k_date = hmac_sha256(format!("AWS4{key}").as_bytes(), date_stamp);
-    let k_region = hmac_sha256(&k_date, region);
-    let k_service = hmac_sha256(&k_region, service);
-    hmac_sha256(&k_service, "aws4_request")
+        let model = Model::new("bedrock", "meta.llama3-70b-instruct-v1:0");
+        let body = build_chat_completions_body(create_data(), &model).unwrap();
+        assert!(!body.to_string().contains("cachePoint"));
+    }
 }
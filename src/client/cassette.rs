@@ -0,0 +1,363 @@
+use super::{Model, RequestData, SseHandler, StreamUsage, ToolCall};
+
+use crate::config::{Config, GlobalConfig};
+use crate::utils::sha256;
+
+use anyhow::{anyhow, bail, Context, Result};
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{read_to_string, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+const CASSETTE_FILE_NAME: &str = "cassette.jsonl";
+
+const REDACTED: &str = "<redacted>";
+
+pub const STREAMING_API: &str = "chat_completions_streaming";
+
+static CASSETTE: OnceLock<Option<Cassette>> = OnceLock::new();
+
+tokio::task_local! {
+    static RECORDED_REQUEST: RefCell<Option<Value>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+impl FromStr for CassetteMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "record" => Ok(Self::Record),
+            "replay" => Ok(Self::Replay),
+            _ => bail!("Invalid cassette mode '{s}', expected 'record' or 'replay'"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CassetteEntry {
+    pub key: String,
+    pub api: String,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<Value>,
+    pub response: Value,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CassetteFrame {
+    Text(String),
+    Reasoning(String),
+    ToolCall(ToolCall),
+    Usage(StreamUsage),
+}
+
+/// Records provider responses to a JSONL file or serves them back, keyed by a hash of
+/// the model and the provider-agnostic request data, so replays need no credentials.
+#[derive(Debug)]
+pub struct Cassette {
+    mode: CassetteMode,
+    path: PathBuf,
+    entries: Mutex<HashMap<String, VecDeque<CassetteEntry>>>,
+}
+
+impl Cassette {
+    pub fn get(config: &GlobalConfig) -> Result<Option<&'static Cassette>> {
+        if let Some(cassette) = CASSETTE.get() {
+            return Ok(cassette.as_ref());
+        }
+        let (mode, path) = {
+            let config = config.read();
+            let path = config
+                .cassette_file
+                .as_ref()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| Config::local_path(CASSETTE_FILE_NAME));
+            (config.cassette_mode, path)
+        };
+        let cassette = match mode {
+            Some(mode) => Some(Self::load(mode, path)?),
+            None => None,
+        };
+        Ok(CASSETTE.get_or_init(|| cassette).as_ref())
+    }
+
+    pub fn load(mode: CassetteMode, path: PathBuf) -> Result<Self> {
+        let mut entries: HashMap<String, VecDeque<CassetteEntry>> = HashMap::new();
+        if mode == CassetteMode::Replay {
+            let content = read_to_string(&path)
+                .with_context(|| format!("Failed to load cassette at '{}'", path.display()))?;
+            for (i, line) in content.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let entry: CassetteEntry = serde_json::from_str(line).with_context(|| {
+                    format!("Invalid cassette entry at '{}:{}'", path.display(), i + 1)
+                })?;
+                entries
+                    .entry(entry.key.clone())
+                    .or_default()
+                    .push_back(entry);
+            }
+        }
+        Ok(Self {
+            mode,
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn key<T: Serialize>(model: &Model, api: &str, data: &T) -> Result<String> {
+        let value = json!({
+            "model": model.id(),
+            "api": api,
+            "data": data,
+        });
+        Ok(sha256(&serde_json::to_string(&value)?))
+    }
+
+    /// Serves the next recorded response for the key. The last one is kept for
+    /// subsequent identical requests.
+    pub fn replay(&self, key: &str, api: &str, model: &Model) -> Result<Value> {
+        let mut entries = self.entries.lock();
+        let queue = entries
+            .get_mut(key)
+            .filter(|v| !v.is_empty())
+            .ok_or_else(|| {
+                anyhow!(
+                    "No cassette entry for {api} request to '{}' (key: {key})",
+                    model.id()
+                )
+            })?;
+        let entry = if queue.len() > 1 {
+            queue.pop_front()
+        } else {
+            queue.front().cloned()
+        };
+        Ok(entry.map(|v| v.response).unwrap_or_default())
+    }
+
+    pub fn record(&self, entry: CassetteEntry) -> Result<()> {
+        let mut entries = self.entries.lock();
+        let line = serde_json::to_string(&entry)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open cassette at '{}'", self.path.display()))?;
+        writeln!(file, "{line}")
+            .with_context(|| format!("Failed to write cassette at '{}'", self.path.display()))?;
+        entries
+            .entry(entry.key.clone())
+            .or_default()
+            .push_back(entry);
+        Ok(())
+    }
+
+    pub fn replay_streaming(
+        &self,
+        key: &str,
+        model: &Model,
+        handler: &mut SseHandler,
+    ) -> Result<()> {
+        let response = self.replay(key, STREAMING_API, model)?;
+        let frames: Vec<CassetteFrame> =
+            serde_json::from_value(response).context("Invalid cassette response")?;
+        for frame in frames {
+            match frame {
+                CassetteFrame::Text(text) => handler.text(&text)?,
+                CassetteFrame::Reasoning(text) => handler.reasoning(&text)?,
+                CassetteFrame::ToolCall(call) => handler.tool_call(call)?,
+                CassetteFrame::Usage(usage) => handler.update_usage(usage),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn record_streaming(
+        &self,
+        key: String,
+        model: &Model,
+        request: Option<Value>,
+        frames: Vec<CassetteFrame>,
+    ) -> Result<()> {
+        self.record(CassetteEntry {
+            key,
+            api: STREAMING_API.to_string(),
+            model: model.id(),
+            request,
+            response: serde_json::to_value(&frames)?,
+        })
+    }
+}
+
+pub async fn with_cassette<T, D, F>(
+    config: &GlobalConfig,
+    model: &Model,
+    api: &str,
+    data: &D,
+    future: F,
+) -> Result<T>
+where
+    T: Serialize + DeserializeOwned,
+    D: Serialize,
+    F: Future<Output = Result<T>>,
+{
+    let cassette = match Cassette::get(config)? {
+        Some(v) => v,
+        None => return future.await,
+    };
+    let key = Cassette::key(model, api, data)?;
+    match cassette.mode() {
+        CassetteMode::Replay => {
+            let response = cassette.replay(&key, api, model)?;
+            serde_json::from_value(response).context("Invalid cassette response")
+        }
+        CassetteMode::Record => {
+            let (ret, request) = capture_request(future).await;
+            let output = ret?;
+            cassette.record(CassetteEntry {
+                key,
+                api: api.to_string(),
+                model: model.id(),
+                request,
+                response: serde_json::to_value(&output)?,
+            })?;
+            Ok(output)
+        }
+    }
+}
+
+/// Remembers the outgoing request so that it can be stored alongside the response.
+pub fn cassette_capture_request(request_data: &RequestData) {
+    let _ = RECORDED_REQUEST.try_with(|v| {
+        *v.borrow_mut() = Some(redact_request_data(request_data));
+    });
+}
+
+pub async fn capture_request<T, F>(future: F) -> (T, Option<Value>)
+where
+    F: Future<Output = T>,
+{
+    RECORDED_REQUEST
+        .scope(RefCell::new(None), async {
+            let ret = future.await;
+            let request = RECORDED_REQUEST.with(|v| v.borrow_mut().take());
+            (ret, request)
+        })
+        .await
+}
+
+fn redact_request_data(request_data: &RequestData) -> Value {
+    let headers: serde_json::Map<String, Value> = request_data
+        .headers
+        .iter()
+        .map(|(key, value)| {
+            let value = if is_secret_name(key) {
+                REDACTED.to_string()
+            } else {
+                value.clone()
+            };
+            (key.clone(), value.into())
+        })
+        .collect();
+    json!({
+        "url": redact_url(&request_data.url),
+        "headers": headers,
+        "body": request_data.body,
+    })
+}
+
+fn redact_url(url: &str) -> String {
+    let (base, query) = match url.split_once('?') {
+        Some(v) => v,
+        None => return url.to_string(),
+    };
+    let query = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, _)) if is_secret_name(key) => format!("{key}={REDACTED}"),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{base}?{query}")
+}
+
+fn is_secret_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.contains("authorization")
+        || name.contains("key")
+        || name.contains("token")
+        || name.contains("secret")
+        || name.contains("signature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_request_data() {
+        let mut request_data = RequestData::new(
+            "https://example.com/v1/models/m:embed?key=sk-123&alt=sse",
+            json!({"model": "m"}),
+        );
+        request_data.bearer_auth("sk-123");
+        request_data.header("x-api-key", "sk-123");
+        request_data.header("anthropic-version", "2023-06-01");
+        let value = redact_request_data(&request_data);
+        assert_eq!(
+            value["url"],
+            "https://example.com/v1/models/m:embed?key=<redacted>&alt=sse"
+        );
+        assert_eq!(value["headers"]["authorization"], REDACTED);
+        assert_eq!(value["headers"]["x-api-key"], REDACTED);
+        assert_eq!(value["headers"]["anthropic-version"], "2023-06-01");
+    }
+
+    #[test]
+    fn test_replay_order() {
+        let path = std::env::temp_dir().join(format!("aichat-{}.jsonl", uuid::Uuid::new_v4()));
+        let model = Model::new("test", "model");
+        let recorder = Cassette::load(CassetteMode::Record, path.clone()).unwrap();
+        for text in ["first", "second"] {
+            recorder
+                .record(CassetteEntry {
+                    key: "k".into(),
+                    api: "chat_completions".into(),
+                    model: model.id(),
+                    request: None,
+                    response: json!(text),
+                })
+                .unwrap();
+        }
+        let player = Cassette::load(CassetteMode::Replay, path.clone()).unwrap();
+        let replay = || player.replay("k", "chat_completions", &model).unwrap();
+        assert_eq!(replay(), json!("first"));
+        assert_eq!(replay(), json!("second"));
+        assert_eq!(replay(), json!("second"));
+        assert!(player
+            .replay("missing", "chat_completions", &model)
+            .is_err());
+        let _ = std::fs::remove_file(path);
+    }
+}
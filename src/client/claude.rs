@@ -13,6 +13,8 @@ const API_BASE: &str = "https://api.anthropic.com/v1";
 pub struct ClaudeConfig {
     pub name: Option<String>,
     pub api_key: Option<String>,
+    #[serde(default)]
+    pub api_keys: Vec<String>,
     pub api_base: Option<String>,
     #[serde(default)]
     pub models: Vec<ModelData>,
@@ -21,7 +23,7 @@ pub struct ClaudeConfig {
 }
 
 impl ClaudeClient {
-    config_get_fn!(api_key, get_api_key);
+    config_get_api_key_fn!(get_api_key);
     config_get_fn!(api_base, get_api_base);
 
     pub const PROMPTS: [PromptAction<'static>; 1] = [("api_key", "API Key", None)];
@@ -62,12 +64,8 @@ pub async fn claude_chat_completions(
     builder: RequestBuilder,
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
     debug!("non-stream-data: {data}");
     claude_extract_chat_completions(&data)
 }
@@ -80,12 +78,17 @@ pub async fn claude_chat_completions_streaming(
     let mut function_name = String::new();
     let mut function_arguments = String::new();
     let mut function_id = String::new();
-    let mut reasoning_state = 0;
     let handle = |message: SseMmessage| -> Result<bool> {
         let data: Value = serde_json::from_str(&message.data)?;
         debug!("stream-data: {data}");
         if let Some(typ) = data["type"].as_str() {
             match typ {
+                "message_start" => {
+                    handler.update_usage(claude_extract_usage(&data["message"]["usage"]));
+                }
+                "message_delta" => {
+                    handler.update_usage(claude_extract_usage(&data["usage"]));
+                }
                 "content_block_start" => {
                     if let (Some("tool_use"), Some(name), Some(id)) = (
                         data["content_block"]["type"].as_str(),
@@ -112,11 +115,7 @@ pub async fn claude_chat_completions_streaming(
                     if let Some(text) = data["delta"]["text"].as_str() {
                         handler.text(text)?;
                     } else if let Some(text) = data["delta"]["thinking"].as_str() {
-                        if reasoning_state == 0 {
-                            handler.text("<think>\n")?;
-                            reasoning_state = 1;
-                        }
-                        handler.text(text)?;
+                        handler.reasoning(text)?;
                     } else if let (true, Some(partial_json)) = (
                         !function_name.is_empty(),
                         data["delta"]["partial_json"].as_str(),
@@ -124,25 +123,19 @@ pub async fn claude_chat_completions_streaming(
                         function_arguments.push_str(partial_json);
                     }
                 }
-                "content_block_stop" => {
-                    if reasoning_state == 1 {
-                        handler.text("\n</think>\n\n")?;
-                        reasoning_state = 0;
-                    }
-                    if !function_name.is_empty() {
-                        let arguments: Value = if function_arguments.is_empty() {
-                            json!({})
-                        } else {
-                            function_arguments.parse().with_context(|| {
-                                format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
-                            })?
-                        };
-                        handler.tool_call(ToolCall::new(
-                            function_name.clone(),
-                            arguments,
-                            Some(function_id.clone()),
-                        ))?;
-                    }
+                "content_block_stop" if !function_name.is_empty() => {
+                    let arguments: Value = if function_arguments.is_empty() {
+                        json!({})
+                    } else {
+                        function_arguments.parse().with_context(|| {
+                            format!("Tool call '{function_name}' have non-JSON arguments '{function_arguments}'")
+                        })?
+                    };
+                    handler.tool_call(ToolCall::new(
+                        function_name.clone(),
+                        arguments,
+                        Some(function_id.clone()),
+                    ))?;
                 }
                 _ => {}
             }
@@ -163,6 +156,8 @@ pub fn claude_build_chat_completions_body(
         top_p,
         functions,
         stream,
+        schema,
+        reasoning,
     } = data;
 
     let system_message = extract_system_message(&mut messages);
@@ -174,7 +169,7 @@ pub fn claude_build_chat_completions_body(
         .into_iter()
         .enumerate()
         .flat_map(|(i, message)| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             match content {
                 MessageContent::Text(text) if role.is_assistant() && i != messages_len - 1 => {
                     vec![json!({ "role": role, "content": strip_think_tag(&text) })]
@@ -279,6 +274,15 @@ pub fn claude_build_chat_completions_body(
     if let Some(v) = top_p {
         body["top_p"] = v.into();
     }
+    // Forcing the structured output tool is not allowed while thinking.
+    if let Some(reasoning) = reasoning.filter(|v| !v.is_off() && schema.is_none()) {
+        let thinking = claude_thinking(reasoning, &mut body["max_tokens"]);
+        body["thinking"] = thinking;
+        if let Some(body) = body.as_object_mut() {
+            body.remove("temperature");
+            body.remove("top_p");
+        }
+    }
     if stream {
         body["stream"] = true.into();
     }
@@ -294,9 +298,59 @@ pub fn claude_build_chat_completions_body(
             })
             .collect();
     }
+    if let Some(schema) = schema {
+        let tool = json!({
+            "name": STRUCTURED_OUTPUT_TOOL,
+            "description": "Respond with the structured output.",
+            "input_schema": schema,
+        });
+        match body["tools"].as_array_mut() {
+            Some(tools) => tools.push(tool),
+            None => body["tools"] = json!([tool]),
+        }
+        body["tool_choice"] = json!({ "type": "tool", "name": STRUCTURED_OUTPUT_TOOL });
+    }
+    if !model.no_prompt_caching() {
+        claude_add_cache_control(&mut body);
+    }
     Ok(body)
 }
 
+/// Places prompt caching breakpoints on the system prompt, the tool declarations and, once a
+/// conversation has history, its latest message so the next turn reuses the whole prefix.
+fn claude_add_cache_control(body: &mut Value) {
+    let cache_control = json!({ "type": "ephemeral" });
+    if let Some(system) = body["system"].as_str() {
+        body["system"] =
+            json!([{ "type": "text", "text": system, "cache_control": cache_control }]);
+    }
+    if let Some(tool) = body["tools"].as_array_mut().and_then(|v| v.last_mut()) {
+        tool["cache_control"] = cache_control.clone();
+    }
+    let message = body["messages"]
+        .as_array_mut()
+        .filter(|v| v.len() > 1)
+        .and_then(|v| v.last_mut());
+    if let Some(message) = message {
+        if let Some(text) = message["content"].as_str() {
+            message["content"] = json!([{ "type": "text", "text": text }]);
+        }
+        if let Some(part) = message["content"].as_array_mut().and_then(|v| v.last_mut()) {
+            part["cache_control"] = cache_control;
+        }
+    }
+}
+
+/// Builds the `thinking` field and grows `max_tokens` so it exceeds the budget, as the API requires.
+pub fn claude_thinking(reasoning: Reasoning, max_tokens: &mut Value) -> Value {
+    let budget_tokens = reasoning.budget_tokens().max(1024);
+    let current = max_tokens.as_u64().unwrap_or(4096);
+    if current <= budget_tokens {
+        *max_tokens = (budget_tokens + current).into();
+    }
+    json!({ "type": "enabled", "budget_tokens": budget_tokens })
+}
+
 pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
     let mut text = String::new();
     let mut reasoning = None;
@@ -323,6 +377,10 @@ pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
                         item.get("input"),
                         item["id"].as_str(),
                     ) {
+                        if name == STRUCTURED_OUTPUT_TOOL {
+                            text.push_str(&input.to_string());
+                            continue;
+                        }
                         tool_calls.push(ToolCall::new(
                             name.to_string(),
                             input.clone(),
@@ -334,20 +392,87 @@ pub fn claude_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
             }
         }
     }
-    if let Some(reasoning) = reasoning {
-        text = format!("<think>\n{reasoning}\n</think>\n\n{text}")
-    }
-
     if text.is_empty() && tool_calls.is_empty() {
         bail!("Invalid response data: {data}");
     }
 
+    let usage = claude_extract_usage(&data["usage"]);
     let output = ChatCompletionsOutput {
         text: text.to_string(),
+        reasoning,
         tool_calls,
         id: data["id"].as_str().map(|v| v.to_string()),
-        input_tokens: data["usage"]["input_tokens"].as_u64(),
-        output_tokens: data["usage"]["output_tokens"].as_u64(),
+        input_tokens: usage.input_tokens,
+        output_tokens: usage.output_tokens,
+        cache_creation_input_tokens: usage.cache_creation_input_tokens,
+        cache_read_input_tokens: usage.cache_read_input_tokens,
     };
     Ok(output)
 }
+
+/// `input_tokens` excludes the tokens written to or read from the prompt cache.
+fn claude_extract_usage(usage: &Value) -> StreamUsage {
+    StreamUsage {
+        input_tokens: usage["input_tokens"].as_u64(),
+        output_tokens: usage["output_tokens"].as_u64(),
+        cache_creation_input_tokens: usage["cache_creation_input_tokens"].as_u64(),
+        cache_read_input_tokens: usage["cache_read_input_tokens"].as_u64(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_data() -> ChatCompletionsData {
+        let messages = vec![
+            Message::new(MessageRole::System, MessageContent::Text("Be brief".into())),
+            Message::new(MessageRole::User, MessageContent::Text("Hi".into())),
+            Message::new(MessageRole::Assistant, MessageContent::Text("Hello".into())),
+            Message::new(MessageRole::User, MessageContent::Text("Bye".into())),
+        ];
+        let functions = serde_json::from_value(json!([{
+            "name": "get_weather",
+            "description": "Get the weather",
+            "parameters": { "type": "object", "properties": {} },
+        }]))
+        .unwrap();
+        ChatCompletionsData {
+            messages,
+            temperature: None,
+            top_p: None,
+            functions: Some(functions),
+            stream: false,
+            schema: None,
+            reasoning: None,
+        }
+    }
+
+    #[test]
+    fn test_build_chat_completions_body_cache_control() {
+        let cache_control = json!({ "type": "ephemeral" });
+        let mut model = Model::new("claude", "claude-sonnet-4-0");
+        let body = claude_build_chat_completions_body(create_data(), &model).unwrap();
+        assert_eq!(
+            body["system"],
+            json!([{ "type": "text", "text": "Be brief", "cache_control": cache_control }])
+        );
+        assert_eq!(body["tools"][0]["cache_control"], cache_control);
+        assert_eq!(
+            body["messages"],
+            json!([
+                { "role": "user", "content": "Hi" },
+                { "role": "assistant", "content": "Hello" },
+                {
+                    "role": "user",
+                    "content": [{ "type": "text", "text": "Bye", "cache_control": cache_control }],
+                },
+            ])
+        );
+
+        model.data_mut().no_prompt_caching = true;
+        let body = claude_build_chat_completions_body(create_data(), &model).unwrap();
+        assert_eq!(body["system"], "Be brief");
+        assert!(!body.to_string().contains("cache_control"));
+    }
+}
@@ -13,6 +13,8 @@ const API_BASE: &str = "https://api.cohere.ai/v2";
 pub struct CohereConfig {
     pub name: Option<String>,
     pub api_key: Option<String>,
+    #[serde(default)]
+    pub api_keys: Vec<String>,
     pub api_base: Option<String>,
     #[serde(default)]
     pub models: Vec<ModelData>,
@@ -21,7 +23,7 @@ pub struct CohereConfig {
 }
 
 impl CohereClient {
-    config_get_fn!(api_key, get_api_key);
+    config_get_api_key_fn!(get_api_key);
     config_get_fn!(api_base, get_api_base);
 
     pub const PROMPTS: [PromptAction<'static>; 1] = [("api_key", "API Key", None)];
@@ -53,6 +55,13 @@ async fn prepare_chat_completions(
         if let Some(top_p) = obj.remove("top_p") {
             obj.insert("p".to_string(), top_p);
         }
+        if let Some(mut response_format) = obj.remove("response_format") {
+            let schema = response_format["json_schema"]["schema"].take();
+            obj.insert(
+                "response_format".to_string(),
+                json!({ "type": "json_object", "json_schema": schema }),
+            );
+        }
     }
 
     let mut request_data = RequestData::new(url, body);
@@ -89,7 +98,7 @@ async fn prepare_embeddings(self_: &CohereClient, data: &EmbeddingsData) -> Resu
     Ok(request_data)
 }
 
-fn prepare_rerank(self_: &CohereClient, data: &RerankData) -> Result<RequestData> {
+async fn prepare_rerank(self_: &CohereClient, data: &RerankData) -> Result<RequestData> {
     let api_key = self_.get_api_key()?;
     let api_base = self_
         .get_api_base()
@@ -109,12 +118,8 @@ async fn chat_completions(
     builder: RequestBuilder,
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
 
     debug!("non-stream-data: {data}");
     extract_chat_completions(&data)
@@ -179,6 +184,14 @@ async fn chat_completions_streaming(
                     function_arguments.clear();
                     function_id.clear();
                 }
+                "message-end" => {
+                    let usage = &data["delta"]["usage"]["billed_units"];
+                    handler.update_usage(StreamUsage {
+                        input_tokens: usage["input_tokens"].as_u64(),
+                        output_tokens: usage["output_tokens"].as_u64(),
+                        ..Default::default()
+                    });
+                }
                 _ => {}
             }
         }
@@ -189,12 +202,8 @@ async fn chat_completions_streaming(
 }
 
 async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
     let res_body: EmbeddingsResBody =
         serde_json::from_value(data).context("Invalid embeddings data")?;
     Ok(res_body.embeddings.float)
@@ -246,10 +255,13 @@ fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
     }
     let output = ChatCompletionsOutput {
         text,
+        reasoning: None,
         tool_calls,
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["billed_units"]["input_tokens"].as_u64(),
         output_tokens: data["usage"]["billed_units"]["output_tokens"].as_u64(),
+        cache_creation_input_tokens: None,
+        cache_read_input_tokens: None,
     };
     Ok(output)
 }
@@ -1,20 +1,22 @@
 use super::*;
 
 use crate::{
-    config::{Config, GlobalConfig, Input},
+    config::{
+        calculate_cost, mask_secrets, resolve_secret, Config, GlobalConfig, Input, UsageRecord,
+    },
     function::{eval_tool_calls, FunctionDeclaration, ToolCall, ToolResult},
-    render::render_stream,
+    render::{render_reasoning, render_stream},
     utils::*,
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use fancy_regex::Regex;
 use indexmap::IndexMap;
 use inquire::{
     list_option::ListOption, required, validator::Validation, MultiSelect, Select, Text,
 };
-use reqwest::{Client as ReqwestClient, RequestBuilder};
-use serde::Deserialize;
+use reqwest::{Client as ReqwestClient, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::sync::LazyLock;
 use std::time::Duration;
@@ -22,6 +24,11 @@ use tokio::sync::mpsc::unbounded_channel;
 
 const MODELS_YAML: &str = include_str!("../../models.yaml");
 
+/// The tool forced on providers that produce structured output through tool use.
+pub const STRUCTURED_OUTPUT_TOOL: &str = "structured_output";
+
+const STRUCTURED_OUTPUT_ATTEMPTS: usize = 3;
+
 pub static ALL_PROVIDER_MODELS: LazyLock<Vec<ProviderModels>> = LazyLock::new(|| {
     Config::loal_models_override()
         .ok()
@@ -48,6 +55,10 @@ pub trait Client: Sync + Send {
 
     fn model_mut(&mut self) -> &mut Model;
 
+    fn retry_config(&self) -> Option<&RetryConfig> {
+        self.extra_config().and_then(|v| v.retry.as_ref())
+    }
+
     fn build_client(&self) -> Result<ReqwestClient> {
         let mut builder = ReqwestClient::builder();
         let extra = self.extra_config();
@@ -72,9 +83,22 @@ pub trait Client: Sync + Send {
         }
         let client = self.build_client()?;
         let data = input.prepare_completion_data(self.model(), false)?;
-        self.chat_completions_inner(&client, data)
-            .await
-            .with_context(|| "Failed to call chat-completions api")
+        let tokens = self.model().total_tokens(&data.messages);
+        with_cassette(
+            self.global_config(),
+            self.model(),
+            "chat_completions",
+            &data,
+            with_retry(self.retry_config(), "chat-completions", || {
+                with_rate_limit(
+                    self,
+                    tokens,
+                    self.chat_completions_inner(&client, data.clone()),
+                )
+            }),
+        )
+        .await
+        .with_context(|| "Failed to call chat-completions api")
     }
 
     async fn chat_completions_streaming(
@@ -93,7 +117,21 @@ pub trait Client: Sync + Send {
                 }
                 let client = self.build_client()?;
                 let data = input.prepare_completion_data(self.model(), true)?;
-                self.chat_completions_streaming_inner(&client, handler, data).await
+                if let Some(cassette) = Cassette::get(self.global_config())? {
+                    let key = Cassette::key(self.model(), STREAMING_API, &data)?;
+                    if cassette.mode() == CassetteMode::Replay {
+                        return cassette.replay_streaming(&key, self.model(), handler);
+                    }
+                    handler.start_recording();
+                    let (ret, request) = capture_request(
+                        self.chat_completions_streaming_with_retry(&client, handler, data),
+                    )
+                    .await;
+                    let frames = handler.take_recorded_frames();
+                    ret?;
+                    return cassette.record_streaming(key, self.model(), request, frames);
+                }
+                self.chat_completions_streaming_with_retry(&client, handler, data).await
             } => {
                 handler.done();
                 ret.with_context(|| "Failed to call chat-completions api")
@@ -107,16 +145,82 @@ pub trait Client: Sync + Send {
 
     async fn embeddings(&self, data: &EmbeddingsData) -> Result<Vec<Vec<f32>>> {
         let client = self.build_client()?;
-        self.embeddings_inner(&client, data)
-            .await
-            .context("Failed to call embeddings api")
+        let tokens = data
+            .texts
+            .iter()
+            .map(|v| self.model().count_tokens(v))
+            .sum();
+        let cost = calculate_cost(self.model(), tokens as u64, 0).unwrap_or_default();
+        self.global_config().read().guard_budgets(None, cost)?;
+        let output = with_cassette(
+            self.global_config(),
+            self.model(),
+            "embeddings",
+            data,
+            with_retry(self.retry_config(), "embeddings", || {
+                with_rate_limit(self, tokens, self.embeddings_inner(&client, data))
+            }),
+        )
+        .await
+        .context("Failed to call embeddings api")?;
+        UsageRecord::new("embeddings", self.model(), tokens as u64, 0).save();
+        Ok(output)
     }
 
     async fn rerank(&self, data: &RerankData) -> Result<RerankOutput> {
         let client = self.build_client()?;
-        self.rerank_inner(&client, data)
-            .await
-            .context("Failed to call rerank api")
+        let tokens = std::iter::once(&data.query)
+            .chain(data.documents.iter())
+            .map(|v| self.model().count_tokens(v))
+            .sum();
+        let output = with_cassette(
+            self.global_config(),
+            self.model(),
+            "rerank",
+            data,
+            with_retry(self.retry_config(), "rerank", || {
+                with_rate_limit(self, tokens, self.rerank_inner(&client, data))
+            }),
+        )
+        .await
+        .context("Failed to call rerank api")?;
+        UsageRecord::new("rerank", self.model(), tokens as u64, 0).save();
+        Ok(output)
+    }
+
+    /// Retries only while nothing has been streamed to the handler yet.
+    async fn chat_completions_streaming_with_retry(
+        &self,
+        client: &ReqwestClient,
+        handler: &mut SseHandler,
+        data: ChatCompletionsData,
+    ) -> Result<()> {
+        let tokens = self.model().total_tokens(&data.messages);
+        let mut attempt = 1;
+        loop {
+            let ret = with_rate_limit(
+                self,
+                tokens,
+                self.chat_completions_streaming_inner(client, handler, data.clone()),
+            )
+            .await;
+            let err = match ret {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            let delay = match self.retry_config() {
+                Some(retry) if handler.is_untouched() => retry.next_delay(attempt, &err),
+                _ => None,
+            };
+            match delay {
+                Some(delay) => {
+                    log_retry("chat-completions", attempt, delay, &err);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => return Err(err),
+            }
+        }
     }
 
     async fn chat_completions_inner(
@@ -154,6 +258,7 @@ pub trait Client: Sync + Send {
         mut request_data: RequestData,
     ) -> RequestBuilder {
         self.patch_request_data(&mut request_data);
+        cassette_capture_request(&request_data);
         request_data.into_builder(client)
     }
 
@@ -201,6 +306,11 @@ impl Default for ClientConfig {
 pub struct ExtraConfig {
     pub proxy: Option<String>,
     pub connect_timeout: Option<u64>,
+    pub retry: Option<RetryConfig>,
+    pub api_key_strategy: Option<ApiKeyStrategy>,
+    pub api_key_cooldown: Option<u64>,
+    pub requests_per_minute: Option<usize>,
+    pub tokens_per_minute: Option<usize>,
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -248,7 +358,7 @@ impl RequestData {
 
     pub fn into_builder(self, client: &ReqwestClient) -> RequestBuilder {
         let RequestData { url, headers, body } = self;
-        debug!("Request {url} {body}");
+        debug!("Request {}", mask_secrets(&format!("{url} {body}")));
 
         let mut builder = client.post(url);
         for (key, value) in headers {
@@ -277,22 +387,29 @@ impl RequestData {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ChatCompletionsData {
     pub messages: Vec<Message>,
     pub temperature: Option<f64>,
     pub top_p: Option<f64>,
     pub functions: Option<Vec<FunctionDeclaration>>,
     pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<Reasoning>,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ChatCompletionsOutput {
     pub text: String,
+    pub reasoning: Option<String>,
     pub tool_calls: Vec<ToolCall>,
     pub id: Option<String>,
     pub input_tokens: Option<u64>,
     pub output_tokens: Option<u64>,
+    pub cache_creation_input_tokens: Option<u64>,
+    pub cache_read_input_tokens: Option<u64>,
 }
 
 impl ChatCompletionsOutput {
@@ -304,7 +421,7 @@ impl ChatCompletionsOutput {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct EmbeddingsData {
     pub texts: Vec<String>,
     pub query: bool,
@@ -318,7 +435,7 @@ impl EmbeddingsData {
 
 pub type EmbeddingsOutput = Vec<Vec<f32>>;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct RerankData {
     pub query: String,
     pub documents: Vec<String>,
@@ -337,7 +454,7 @@ impl RerankData {
 
 pub type RerankOutput = Vec<RerankResult>;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct RerankResult {
     pub index: usize,
     pub relevance_score: f64,
@@ -404,77 +521,276 @@ pub async fn create_openai_compatible_client_config(
 }
 
 pub async fn call_chat_completions(
-    input: &Input,
+    input: &mut Input,
     print: bool,
     extract_code: bool,
     client: &dyn Client,
     abort_signal: AbortSignal,
 ) -> Result<(String, Vec<ToolResult>)> {
-    let ret = abortable_run_with_spinner(
-        client.chat_completions(input.clone()),
-        "Generating",
-        abort_signal,
-    )
-    .await;
+    input.guard_budgets()?;
+    let mut fallback_models = input.fallback_models().into_iter();
+    let mut fallback_client: Option<Box<dyn Client>> = None;
+    let ret = loop {
+        let client = fallback_client.as_deref().unwrap_or(client);
+        let ret = abortable_run_with_spinner(
+            client.chat_completions(input.clone()),
+            "Generating",
+            abort_signal.clone(),
+        )
+        .await;
+        match ret {
+            Err(err) => {
+                match next_fallback_client(input, client, &mut fallback_models, &err).await? {
+                    Some(v) => fallback_client = Some(v),
+                    None => break Err(err),
+                }
+            }
+            ret => break ret,
+        }
+    };
 
     match ret {
         Ok(ret) => {
             let ChatCompletionsOutput {
                 mut text,
+                reasoning,
                 tool_calls,
+                input_tokens,
+                output_tokens,
+                cache_creation_input_tokens,
+                cache_read_input_tokens,
                 ..
             } = ret;
+            let client = fallback_client.as_deref().unwrap_or(client);
+            set_input_usage(
+                input,
+                client,
+                &text,
+                &tool_calls,
+                input_tokens,
+                output_tokens,
+            )?;
+            input.set_cache_usage(cache_creation_input_tokens, cache_read_input_tokens);
             if !text.is_empty() {
                 if extract_code {
                     text = extract_code_block(&strip_think_tag(&text)).to_string();
                 }
                 if print {
-                    client.global_config().read().print_markdown(&text)?;
+                    let config = client.global_config().read();
+                    let reasoning = reasoning
+                        .as_deref()
+                        .filter(|_| !extract_code)
+                        .and_then(|v| render_reasoning(config.reasoning_display, v))
+                        .unwrap_or_default();
+                    config.print_markdown(&format!("{reasoning}{text}"))?;
                 }
             }
-            Ok((text, eval_tool_calls(client.global_config(), tool_calls)?))
+            input.set_reasoning(reasoning);
+            Ok((
+                text,
+                eval_tool_calls(client.global_config(), tool_calls).await?,
+            ))
         }
         Err(err) => Err(err),
     }
 }
 
 pub async fn call_chat_completions_streaming(
-    input: &Input,
+    input: &mut Input,
     client: &dyn Client,
     abort_signal: AbortSignal,
 ) -> Result<(String, Vec<ToolResult>)> {
-    let (tx, rx) = unbounded_channel();
-    let mut handler = SseHandler::new(tx, abort_signal.clone());
-
-    let (send_ret, render_ret) = tokio::join!(
-        client.chat_completions_streaming(input, &mut handler),
-        render_stream(rx, client.global_config(), abort_signal.clone()),
-    );
+    input.guard_budgets()?;
+    let mut fallback_models = input.fallback_models().into_iter();
+    let mut fallback_client: Option<Box<dyn Client>> = None;
+    loop {
+        let client = fallback_client.as_deref().unwrap_or(client);
+        let (tx, rx) = unbounded_channel();
+        let mut handler = SseHandler::new(tx, abort_signal.clone());
+
+        let (send_ret, render_ret) = tokio::join!(
+            client.chat_completions_streaming(input, &mut handler),
+            render_stream(rx, client.global_config(), abort_signal.clone()),
+        );
+
+        if handler.abort().aborted() {
+            bail!("Aborted.");
+        }
 
-    if handler.abort().aborted() {
-        bail!("Aborted.");
+        render_ret?;
+
+        let untouched = handler.is_untouched();
+        let reasoning = handler.reasoning_buffer().to_string();
+        let usage = handler.usage().clone();
+        let (text, tool_calls) = handler.take();
+        match send_ret {
+            Ok(_) => {
+                let output = format!("{reasoning}{text}");
+                set_input_usage(
+                    input,
+                    client,
+                    &output,
+                    &tool_calls,
+                    usage.input_tokens,
+                    usage.output_tokens,
+                )?;
+                input.set_cache_usage(
+                    usage.cache_creation_input_tokens,
+                    usage.cache_read_input_tokens,
+                );
+                input.set_reasoning(Some(reasoning));
+                if !text.is_empty() && !text.ends_with('\n') {
+                    println!();
+                }
+                return Ok((
+                    text,
+                    eval_tool_calls(client.global_config(), tool_calls).await?,
+                ));
+            }
+            Err(err) => {
+                if !untouched {
+                    if !text.is_empty() {
+                        println!();
+                    }
+                    return Err(err);
+                }
+                match next_fallback_client(input, client, &mut fallback_models, &err).await? {
+                    Some(v) => fallback_client = Some(v),
+                    None => return Err(err),
+                }
+            }
+        }
     }
+}
 
-    render_ret?;
-
-    let (text, tool_calls) = handler.take();
-    match send_ret {
-        Ok(_) => {
-            if !text.is_empty() && !text.ends_with('\n') {
-                println!();
+/// Requests output matching the role's JSON schema, feeding validation errors back to the
+/// model for a few more attempts, and prints only the JSON.
+pub async fn call_structured_output(
+    input: &mut Input,
+    client: &dyn Client,
+    abort_signal: AbortSignal,
+) -> Result<(String, Vec<ToolResult>)> {
+    let config = client.global_config().clone();
+    let schema = match input.role().schema() {
+        Some(v) => v.clone(),
+        None => bail!("No schema"),
+    };
+    let mut attempt = 1;
+    loop {
+        let (text, tool_results) =
+            call_chat_completions(input, false, false, client, abort_signal.clone()).await?;
+        if config.read().dry_run {
+            config.read().print_markdown(&text)?;
+            return Ok((text, tool_results));
+        }
+        if !tool_results.is_empty() {
+            return Ok((text, tool_results));
+        }
+        let ret = serde_json::from_str::<Value>(extract_code_block(&strip_think_tag(&text)))
+            .map_err(|err| anyhow!("invalid JSON, {err}"))
+            .and_then(|value| validate_json_schema(&schema, &value).map(|_| value));
+        match ret {
+            Ok(value) => {
+                let output = serde_json::to_string_pretty(&value)?;
+                println!("{output}");
+                return Ok((output, vec![]));
+            }
+            Err(err) if attempt < STRUCTURED_OUTPUT_ATTEMPTS => {
+                eprintln!(
+                    "{}",
+                    warning_text(&format!(
+                        "⚠ The output does not match the schema: {err}. Retrying."
+                    ))
+                );
+                config.write().record_usage(input);
+                input.set_schema_feedback(&text, &err.to_string());
+                attempt += 1;
+            }
+            Err(err) => {
+                bail!("The output does not match the schema after {attempt} attempts: {err}")
             }
-            Ok((text, eval_tool_calls(client.global_config(), tool_calls)?))
         }
-        Err(err) => {
-            if !text.is_empty() {
-                println!();
+    }
+}
+
+/// Records the tokens of the completion on the input, estimating the ones the provider didn't report.
+fn set_input_usage(
+    input: &mut Input,
+    client: &dyn Client,
+    text: &str,
+    tool_calls: &[ToolCall],
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+) -> Result<()> {
+    let input_tokens = match input_tokens {
+        Some(v) => v,
+        None => client.model().total_tokens(&input.build_messages()?) as u64,
+    };
+    let output_tokens =
+        output_tokens.unwrap_or_else(|| estimate_output_tokens(client.model(), text, tool_calls));
+    input.set_usage(input_tokens, output_tokens);
+    Ok(())
+}
+
+pub fn estimate_output_tokens(model: &Model, text: &str, tool_calls: &[ToolCall]) -> u64 {
+    let tool_calls_tokens: usize = tool_calls
+        .iter()
+        .map(|v| model.count_tokens(&v.name) + model.count_tokens(&v.arguments.to_string()))
+        .sum();
+    (model.count_tokens(text) + tool_calls_tokens) as u64
+}
+
+/// Switches the input to the next fallback model if the error is worth falling back on.
+async fn next_fallback_client(
+    input: &mut Input,
+    client: &dyn Client,
+    fallback_models: &mut std::vec::IntoIter<String>,
+    err: &anyhow::Error,
+) -> Result<Option<Box<dyn Client>>> {
+    let retryable = match client.retry_config() {
+        Some(retry) => retry.is_retryable(err),
+        None => RetryConfig::default().is_retryable(err),
+    };
+    let exceeded = err
+        .chain()
+        .any(|v| v.to_string() == EXCEED_MAX_INPUT_TOKENS);
+    if !retryable && !exceeded {
+        return Ok(None);
+    }
+    let current_model_id = client.model().id();
+    for model_id in fallback_models.by_ref() {
+        let model = {
+            let config = client.global_config().read();
+            match Model::retrieve_model(&config, &model_id, ModelType::Chat) {
+                Ok(v) => v,
+                Err(err) => {
+                    warn!("Skip fallback model '{model_id}': {err}");
+                    continue;
+                }
             }
-            Err(err)
+        };
+        if model.id() == current_model_id {
+            continue;
         }
+        let model_id = model.id();
+        // The fallback model may cost more, so it must fit the budgets too
+        input.set_model(model);
+        input.guard_budgets()?;
+        eprintln!(
+            "{}",
+            warning_text(&format!(
+                "⚠ {current_model_id} failed: {err}. Answering with {model_id}."
+            ))
+        );
+        return Ok(Some(input.create_client()?));
     }
+    Ok(None)
 }
 
-pub fn noop_prepare_embeddings<T>(_client: &T, _data: &EmbeddingsData) -> Result<RequestData> {
+pub async fn noop_prepare_embeddings<T>(
+    _client: &T,
+    _data: &EmbeddingsData,
+) -> Result<RequestData> {
     bail!("The client doesn't support embeddings api")
 }
 
@@ -482,7 +798,7 @@ pub async fn noop_embeddings(_builder: RequestBuilder, _model: &Model) -> Result
     bail!("The client doesn't support embeddings api")
 }
 
-pub fn noop_prepare_rerank<T>(_client: &T, _data: &RerankData) -> Result<RequestData> {
+pub async fn noop_prepare_rerank<T>(_client: &T, _data: &RerankData) -> Result<RequestData> {
     bail!("The client doesn't support rerank api")
 }
 
@@ -490,46 +806,75 @@ pub async fn noop_rerank(_builder: RequestBuilder, _model: &Model) -> Result<Rer
     bail!("The client doesn't support rerank api")
 }
 
-pub fn catch_error(data: &Value, status: u16) -> Result<()> {
-    if (200..300).contains(&status) {
-        return Ok(());
+/// Sends the request, turning a non-success response into an `ApiError`.
+pub async fn send_request(builder: RequestBuilder) -> Result<Response> {
+    throttle_request().await;
+    let res = builder.send().await?;
+    if res.status().is_success() {
+        return Ok(res);
     }
-    debug!("Invalid response, status: {status}, data: {data}");
+    Err(catch_response_error(res).await)
+}
+
+pub async fn catch_response_error(res: Response) -> anyhow::Error {
+    let status = res.status().as_u16();
+    let retry_after = parse_retry_after(res.headers());
+    let text = match res.text().await {
+        Ok(v) => v,
+        Err(err) => return err.into(),
+    };
+    debug!(
+        "Invalid response, status: {status}, data: {}",
+        mask_secrets(&text)
+    );
+    let message = match serde_json::from_str(&text) {
+        Ok(data) => extract_error_message(&data, status),
+        Err(_) => format!("Invalid response data: {text} (status: {status})"),
+    };
+    ApiError {
+        status,
+        message,
+        retry_after,
+    }
+    .into()
+}
+
+fn extract_error_message(data: &Value, status: u16) -> String {
     if let Some(error) = data["error"].as_object() {
         if let (Some(typ), Some(message)) = (
             json_str_from_map(error, "type"),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (type: {typ})");
+            return format!("{message} (type: {typ})");
         } else if let (Some(typ), Some(message)) = (
             json_str_from_map(error, "code"),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (code: {typ})");
+            return format!("{message} (code: {typ})");
         }
     } else if let Some(error) = data["errors"][0].as_object() {
         if let (Some(code), Some(message)) = (
             error.get("code").and_then(|v| v.as_u64()),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (status: {code})")
+            return format!("{message} (status: {code})");
         }
     } else if let Some(error) = data[0]["error"].as_object() {
         if let (Some(status), Some(message)) = (
             json_str_from_map(error, "status"),
             json_str_from_map(error, "message"),
         ) {
-            bail!("{message} (status: {status})")
+            return format!("{message} (status: {status})");
         }
     } else if let (Some(detail), Some(status)) = (data["detail"].as_str(), data["status"].as_i64())
     {
-        bail!("{detail} (status: {status})");
+        return format!("{detail} (status: {status})");
     } else if let Some(error) = data["error"].as_str() {
-        bail!("{error}");
+        return error.to_string();
     } else if let Some(message) = data["message"].as_str() {
-        bail!("{message}");
+        return message.to_string();
     }
-    bail!("Invalid response data: {data} (status: {status})");
+    format!("Invalid response data: {data} (status: {status})")
 }
 
 pub fn json_str_from_map<'a>(
@@ -551,43 +896,58 @@ async fn set_client_models_config(client_config: &mut Value, client: &str) -> Re
         return Ok(format!("{client}:{model_name}"));
     }
     let mut model_names = vec![];
-    if let (Some(true), Some(api_base), api_key) = (
-        client_config["type"]
-            .as_str()
-            .map(|v| v == OpenAICompatibleClient::NAME),
+    let api_key = client_config["api_key"]
+        .as_str()
+        .map(|v| v.to_string())
+        .or_else(|| {
+            let env_name = format!("{client}_api_key").to_ascii_uppercase();
+            std::env::var(&env_name).ok()
+        })
+        .map(|v| resolve_secret(&v))
+        .transpose()?;
+    let fetch_ret = match (
+        client_config["type"].as_str(),
         client_config["api_base"].as_str(),
-        client_config["api_key"]
-            .as_str()
-            .map(|v| v.to_string())
-            .or_else(|| {
-                let env_name = format!("{client}_api_key").to_ascii_uppercase();
-                std::env::var(&env_name).ok()
-            }),
     ) {
-        match abortable_run_with_spinner(
-            fetch_models(api_base, api_key.as_deref()),
-            "Fetching models",
-            create_abort_signal(),
-        )
-        .await
-        {
-            Ok(fetched_models) => {
-                model_names = MultiSelect::new("LLMs to include (required):", fetched_models)
-                    .with_validator(|list: &[ListOption<&String>]| {
-                        if list.is_empty() {
-                            Ok(Validation::Invalid(
-                                "At least one item must be selected".into(),
-                            ))
-                        } else {
-                            Ok(Validation::Valid)
-                        }
-                    })
-                    .prompt()?;
-            }
-            Err(err) => {
-                eprintln!("✗ Fetch models failed: {err}");
-            }
+        (Some(OpenAICompatibleClient::NAME), Some(api_base)) => Some(
+            abortable_run_with_spinner(
+                fetch_models(api_base, api_key.as_deref()),
+                "Fetching models",
+                create_abort_signal(),
+            )
+            .await,
+        ),
+        (Some(OllamaClient::NAME), api_base) => Some(
+            abortable_run_with_spinner(
+                super::ollama::fetch_ollama_models(
+                    api_base.unwrap_or(super::ollama::API_BASE),
+                    api_key.as_deref(),
+                ),
+                "Fetching models",
+                create_abort_signal(),
+            )
+            .await,
+        ),
+        _ => None,
+    };
+    match fetch_ret {
+        Some(Ok(fetched_models)) => {
+            model_names = MultiSelect::new("LLMs to include (required):", fetched_models)
+                .with_validator(|list: &[ListOption<&String>]| {
+                    if list.is_empty() {
+                        Ok(Validation::Invalid(
+                            "At least one item must be selected".into(),
+                        ))
+                    } else {
+                        Ok(Validation::Valid)
+                    }
+                })
+                .prompt()?;
         }
+        Some(Err(err)) => {
+            eprintln!("✗ Fetch models failed: {err}");
+        }
+        None => {}
     }
     if model_names.is_empty() {
         model_names = prompt_input_string(
@@ -674,3 +1034,126 @@ fn prompt_input_string(
     let text = text.prompt()?;
     Ok(text)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Budget, Role, RoleLike};
+
+    use std::sync::Arc;
+
+    fn create_config(daily_budget: Option<Budget>) -> GlobalConfig {
+        let config = Config {
+            clients: vec![
+                serde_json::from_value(json!({ "type": "openai", "api_key": "test" })).unwrap(),
+            ],
+            daily_budget,
+            ..Default::default()
+        };
+        Arc::new(parking_lot::RwLock::new(config))
+    }
+
+    fn create_input(config: &GlobalConfig, model_id: &str, fallback_models: &str) -> Input {
+        let role = Role::new(
+            "test",
+            &format!("---\nfallback_models: {fallback_models}\n---\n"),
+        );
+        let mut input = Input::from_str(config, "Hello", Some(role));
+        let model = Model::retrieve_model(&config.read(), model_id, ModelType::Chat).unwrap();
+        input.set_model(model);
+        input
+    }
+
+    fn api_error(status: u16) -> anyhow::Error {
+        anyhow::Error::new(ApiError {
+            status,
+            message: format!("status {status}"),
+            retry_after: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_next_fallback_client() {
+        let config = create_config(None);
+        let mut input = create_input(
+            &config,
+            "openai:gpt-4o",
+            "openai:gpt-4o,unknown:model,openai:gpt-4o-mini,openai:gpt-4.1-mini",
+        );
+        let client = input.create_client().unwrap();
+        let mut fallback_models = input.fallback_models().into_iter();
+
+        // A bad request would fail the same way with another model
+        let ret = next_fallback_client(
+            &mut input,
+            client.as_ref(),
+            &mut fallback_models,
+            &api_error(400),
+        )
+        .await
+        .unwrap();
+        assert!(ret.is_none());
+        assert_eq!(input.role().model().id(), "openai:gpt-4o");
+
+        // The current and unknown models are skipped
+        let client = next_fallback_client(
+            &mut input,
+            client.as_ref(),
+            &mut fallback_models,
+            &api_error(503),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(client.model().id(), "openai:gpt-4o-mini");
+        assert_eq!(input.role().model().id(), "openai:gpt-4o-mini");
+
+        let client = next_fallback_client(
+            &mut input,
+            client.as_ref(),
+            &mut fallback_models,
+            &api_error(429),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(client.model().id(), "openai:gpt-4.1-mini");
+
+        let ret = next_fallback_client(
+            &mut input,
+            client.as_ref(),
+            &mut fallback_models,
+            &api_error(503),
+        )
+        .await
+        .unwrap();
+        assert!(ret.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_next_fallback_client_guards_budgets() {
+        // Enough for a reply of gpt-4o-mini, not for one of gpt-4o
+        let budget = Budget {
+            soft: None,
+            hard: Some(0.05),
+        };
+        let config = create_config(Some(budget));
+        let mut input = create_input(&config, "openai:gpt-4o-mini", "openai:gpt-4o");
+        input.guard_budgets().unwrap();
+        let client = input.create_client().unwrap();
+        let mut fallback_models = input.fallback_models().into_iter();
+
+        let err = next_fallback_client(
+            &mut input,
+            client.as_ref(),
+            &mut fallback_models,
+            &api_error(503),
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("Hard budget of today would be exceeded"));
+    }
+}
@@ -1,3 +1,4 @@
+use super::access_token::prepare_oauth_access_token;
 use super::vertexai::*;
 use super::*;
 
@@ -6,42 +7,51 @@ use reqwest::RequestBuilder;
 use serde::Deserialize;
 use serde_json::{json, Value};
 
-use crate::auth::Authenticator;
+use crate::auth::{AuthMethod, OAuthConfig};
 
 const API_BASE: &str = "https://generativelanguage.googleapis.com/v1beta";
 
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct GeminiConfig {
     pub name: Option<String>,
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub api_keys: Vec<String>,
     pub api_base: Option<String>,
     #[serde(default)]
+    pub auth: AuthMethod,
+    pub oauth: Option<OAuthConfig>,
+    #[serde(default)]
     pub models: Vec<ModelData>,
     pub patch: Option<RequestPatch>,
     pub extra: Option<ExtraConfig>,
 }
 
-pub struct GeminiClient {
-    pub model: Model,
-    pub config: GeminiConfig,
-    pub authenticator: Box<dyn Authenticator + Send + Sync>,
-}
-
 impl GeminiClient {
-    pub fn name(local_config: &GeminiConfig) -> &str {
-        local_config.name.as_deref().unwrap_or(Self::NAME)
-    }
-
-    pub fn new(model: Model, config: GeminiConfig, authenticator: Box<dyn Authenticator + Send + Sync>) -> Self {
-        Self { model, config, authenticator }
-    }
-
+    config_get_api_key_fn!(get_api_key);
     config_get_fn!(api_base, get_api_base);
 
-    pub const PROMPTS: [PromptAction<'static>; 0] = [];
+    pub const PROMPTS: [PromptAction<'static>; 1] = [("api_key", "API Key", None)];
+
+    /// Sends the OAuth access token from `aichat auth login`, refreshed when expired, or the API key.
+    async fn authenticate(&self, request_data: &mut RequestData) -> Result<()> {
+        match self.config.auth {
+            AuthMethod::OAuth => {
+                let oauth = self.config.oauth.clone().unwrap_or_default();
+                let access_token = prepare_oauth_access_token(self.name(), oauth).await?;
+                request_data.bearer_auth(access_token);
+            }
+            AuthMethod::ApiKey => {
+                let api_key = self.get_api_key()?;
+                request_data.header("x-goog-api-key", api_key);
+            }
+        }
+        Ok(())
+    }
 }
 
 impl_client_trait!(
-    crate::client::gemini::GeminiClient,
+    GeminiClient,
     (
         prepare_chat_completions,
         gemini_chat_completions,
@@ -55,7 +65,6 @@ async fn prepare_chat_completions(
     self_: &GeminiClient,
     data: ChatCompletionsData,
 ) -> Result<RequestData> {
-    let api_key = self_.authenticator.authenticate().await?;
     let api_base = self_
         .get_api_base()
         .unwrap_or_else(|_| API_BASE.to_string());
@@ -76,22 +85,20 @@ async fn prepare_chat_completions(
 
     let mut request_data = RequestData::new(url, body);
 
-    request_data.header("x-goog-api-key", api_key);
+    self_.authenticate(&mut request_data).await?;
 
     Ok(request_data)
 }
 
 async fn prepare_embeddings(self_: &GeminiClient, data: &EmbeddingsData) -> Result<RequestData> {
-    let api_key = self_.authenticator.authenticate().await?;
     let api_base = self_
         .get_api_base()
         .unwrap_or_else(|_| API_BASE.to_string());
 
     let url = format!(
-        "{}/models/{}:batchEmbedContents?key={}",
+        "{}/models/{}:batchEmbedContents",
         api_base.trim_end_matches('/'),
-        self_.model.real_name(),
-        api_key
+        self_.model.real_name()
     );
 
     let model_id = format!("models/{}", self_.model.real_name());
@@ -117,18 +124,16 @@ async fn prepare_embeddings(self_: &GeminiClient, data: &EmbeddingsData) -> Resu
         "requests": requests,
     });
 
-    let request_data = RequestData::new(url, body);
+    let mut request_data = RequestData::new(url, body);
+
+    self_.authenticate(&mut request_data).await?;
 
     Ok(request_data)
 }
 
 async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
     let res_body: EmbeddingsResBody =
         serde_json::from_value(data).context("Invalid embeddings data")?;
     let output = res_body
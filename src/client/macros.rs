@@ -1,4 +1,3 @@
-use crate::Authenticator;
 #[macro_export]
 macro_rules! register_client {
     (
@@ -28,13 +27,12 @@ macro_rules! register_client {
                 global_config: $crate::config::GlobalConfig,
                 config: $config,
                 model: $crate::client::Model,
-                authenticator: Option<std::sync::Arc<dyn Authenticator + Send + Sync>>,
             }
 
             impl $client {
                 pub const NAME: &'static str = $name;
 
-                pub fn init(global_config: &$crate::config::GlobalConfig, model: &$crate::client::Model, authenticator: Option<std::sync::Arc<dyn Authenticator + Send + Sync>>) -> Option<Box<dyn Client>> {
+                pub fn init(global_config: &$crate::config::GlobalConfig, model: &$crate::client::Model) -> Option<Box<dyn Client>> {
                     let config = global_config.read().clients.iter().find_map(|client_config| {
                         if let ClientConfig::$config(c) = client_config {
                             if Self::name(c) == model.client_name() {
@@ -48,7 +46,6 @@ macro_rules! register_client {
                         global_config: global_config.clone(),
                         config,
                         model: model.clone(),
-                        authenticator: authenticator.clone(),
                     }))
                 }
 
@@ -75,10 +72,10 @@ macro_rules! register_client {
 
         )+
 
-        pub fn init_client(config: &$crate::config::GlobalConfig, model: Option<$crate::client::Model>, authenticator: Option<std::sync::Arc<dyn Authenticator + Send + Sync>>) -> anyhow::Result<Box<dyn Client>> {
+        pub fn init_client(config: &$crate::config::GlobalConfig, model: Option<$crate::client::Model>) -> anyhow::Result<Box<dyn Client>> {
             let model = model.unwrap_or_else(|| config.read().model.clone());
             None
-            $(.or_else(|| $client::init(config, &model, authenticator.clone())))+
+            $(.or_else(|| $client::init(config, &model)))+
             .ok_or_else(|| {
                 anyhow::anyhow!("Invalid model '{}'", model.id())
             })
@@ -181,8 +178,6 @@ macro_rules! impl_client_trait {
         impl $crate::client::Client for $crate::client::$client {
             client_common_fns!();
 
-            const PROMPTS: &[(&'static str, &'static str)] = &[];
-
             async fn chat_completions_inner(
                 &self,
                 client: &reqwest::Client,
@@ -219,7 +214,7 @@ macro_rules! impl_client_trait {
                 client: &reqwest::Client,
                 data: &$crate::client::RerankData,
             ) -> Result<$crate::client::RerankOutput> {
-                let request_data = $prepare_rerank(self, data)?;
+                let request_data = $prepare_rerank(self, data).await?;
                 let builder = self.request_builder(client, request_data);
                 $rerank(builder, self.model()).await
             }
@@ -234,10 +229,34 @@ macro_rules! config_get_fn {
             let env_prefix = Self::name(&self.config);
             let env_name =
                 format!("{}_{}", env_prefix, stringify!($field_name)).to_ascii_uppercase();
-            std::env::var(&env_name)
+            let value = std::env::var(&env_name)
                 .ok()
                 .or_else(|| self.config.$field_name.clone())
-                .ok_or_else(|| anyhow::anyhow!("Miss '{}'", stringify!($field_name)))
+                .ok_or_else(|| anyhow::anyhow!("Miss '{}'", stringify!($field_name)))?;
+            $crate::config::resolve_field(stringify!($field_name), &value)
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! config_get_api_key_fn {
+    ($fn_name:ident) => {
+        fn $fn_name(&self) -> anyhow::Result<String> {
+            let client_name = Self::name(&self.config);
+            let env_name = format!("{client_name}_api_key").to_ascii_uppercase();
+            let api_keys = match std::env::var(&env_name) {
+                Ok(v) => vec![v],
+                Err(_) => self
+                    .config
+                    .api_key
+                    .iter()
+                    .chain(self.config.api_keys.iter())
+                    .cloned()
+                    .collect(),
+            };
+            let strategy = self.config.extra.as_ref().and_then(|v| v.api_key_strategy);
+            let api_key = $crate::client::select_api_key(client_name, &api_keys, strategy)?;
+            $crate::config::resolve_secret(&api_key)
         }
     };
 }
@@ -8,6 +8,12 @@ use serde::{Deserialize, Serialize};
 pub struct Message {
     pub role: MessageRole,
     pub content: MessageContent,
+    /// The model that produced this reply, when it differs from the session model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// The reasoning behind this reply, kept apart from the content sent back to the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
 }
 
 impl Default for Message {
@@ -15,13 +21,20 @@ impl Default for Message {
         Self {
             role: MessageRole::User,
             content: MessageContent::Text(String::new()),
+            model: None,
+            reasoning: None,
         }
     }
 }
 
 impl Message {
     pub fn new(role: MessageRole, content: MessageContent) -> Self {
-        Self { role, content }
+        Self {
+            role,
+            content,
+            model: None,
+            reasoning: None,
+        }
     }
 
     pub fn merge_system(&mut self, system: MessageContent) {
@@ -211,10 +224,10 @@ pub fn patch_messages(messages: &mut Vec<Message>, model: &Model) {
         } else {
             messages.insert(
                 0,
-                Message {
-                    role: MessageRole::System,
-                    content: MessageContent::Text(prefix.to_string()),
-                },
+                Message::new(
+                    MessageRole::System,
+                    MessageContent::Text(prefix.to_string()),
+                ),
             );
         }
     }
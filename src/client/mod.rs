@@ -1,17 +1,25 @@
 mod access_token;
+mod aws;
+mod cassette;
 mod common;
 mod message;
 #[macro_use]
 mod macros;
 mod model;
+mod rate_limit;
+mod reasoning;
+mod retry;
 mod stream;
 
 pub use crate::function::ToolCall;
+pub use cassette::*;
 pub use common::*;
 pub use message::*;
 pub use model::*;
+pub use rate_limit::*;
+pub use reasoning::*;
+pub use retry::*;
 pub use stream::*;
-use crate::auth::Authenticator;
 
 register_client!(
     (openai, "openai", OpenAIConfig, OpenAIClient),
@@ -32,6 +40,7 @@ register_client!(
     ),
     (vertexai, "vertexai", VertexAIConfig, VertexAIClient),
     (bedrock, "bedrock", BedrockConfig, BedrockClient),
+    (ollama, "ollama", OllamaConfig, OllamaClient),
 );
 
 pub const OPENAI_COMPATIBLE_PROVIDERS: [(&str, &str); 18] = [
@@ -61,3 +70,26 @@ pub const OPENAI_COMPATIBLE_PROVIDERS: [(&str, &str); 18] = [
     ("jina", "https://api.jina.ai/v1"),
     ("voyageai", "https://api.voyageai.com/v1"),
 ];
+
+/// OAuth settings of the named client, used by `aichat auth`.
+pub fn oauth_client_config(
+    config: &crate::config::Config,
+    client_name: &str,
+) -> Option<crate::auth::OAuthConfig> {
+    let oauth = config.clients.iter().find_map(|v| match v {
+        ClientConfig::GeminiConfig(c) if GeminiClient::name(c) == client_name => {
+            Some(c.oauth.clone().unwrap_or_default())
+        }
+        ClientConfig::OpenAICompatibleConfig(c)
+            if OpenAICompatibleClient::name(c) == client_name =>
+        {
+            c.oauth.clone()
+        }
+        _ => None,
+    });
+    match oauth {
+        Some(v) => Some(v),
+        None if client_name == GeminiClient::NAME => Some(Default::default()),
+        None => None,
+    }
+}
@@ -5,7 +5,7 @@ use super::{
 };
 
 use crate::config::Config;
-use crate::utils::{estimate_token_length, strip_think_tag};
+use crate::utils::{count_tokens, strip_think_tag};
 
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
@@ -15,6 +15,8 @@ use std::fmt::Display;
 const PER_MESSAGES_TOKENS: usize = 5;
 const BASIS_TOKENS: usize = 2;
 
+pub const EXCEED_MAX_INPUT_TOKENS: &str = "Exceed max_input_tokens limit";
+
 #[derive(Debug, Clone)]
 pub struct Model {
     client_name: String,
@@ -136,6 +138,7 @@ impl Model {
                     output_price,
                     supports_vision,
                     supports_function_calling,
+                    supports_reasoning,
                     ..
                 } = &self.data;
                 let max_input_tokens = stringify_option_value(max_input_tokens);
@@ -149,6 +152,9 @@ impl Model {
                 if *supports_function_calling {
                     capabilities.push('⚒');
                 };
+                if *supports_reasoning {
+                    capabilities.push('🧠');
+                };
                 let capabilities: String = capabilities
                     .into_iter()
                     .map(|v| format!("{v} "))
@@ -186,6 +192,10 @@ impl Model {
         self.data.max_output_tokens
     }
 
+    pub fn supports_reasoning(&self) -> bool {
+        self.data.supports_reasoning
+    }
+
     pub fn no_stream(&self) -> bool {
         self.data.no_stream
     }
@@ -194,6 +204,10 @@ impl Model {
         self.data.no_system_message
     }
 
+    pub fn no_prompt_caching(&self) -> bool {
+        self.data.no_prompt_caching
+    }
+
     pub fn system_prompt_prefix(&self) -> Option<&str> {
         self.data.system_prompt_prefix.as_deref()
     }
@@ -231,6 +245,14 @@ impl Model {
         self
     }
 
+    pub fn tokenizer(&self) -> Option<&str> {
+        self.data.tokenizer.as_deref()
+    }
+
+    pub fn count_tokens(&self, text: &str) -> usize {
+        count_tokens(self.tokenizer(), text)
+    }
+
     pub fn messages_tokens(&self, messages: &[Message]) -> usize {
         let messages_len = messages.len();
         messages
@@ -239,27 +261,27 @@ impl Model {
             .map(|(i, v)| match &v.content {
                 MessageContent::Text(text) => {
                     if v.role.is_assistant() && i != messages_len - 1 {
-                        estimate_token_length(&strip_think_tag(text))
+                        self.count_tokens(&strip_think_tag(text))
                     } else {
-                        estimate_token_length(text)
+                        self.count_tokens(text)
                     }
                 }
                 MessageContent::Array(list) => list
                     .iter()
                     .map(|v| match v {
-                        MessageContentPart::Text { text } => estimate_token_length(text),
+                        MessageContentPart::Text { text } => self.count_tokens(text),
                         MessageContentPart::ImageUrl { .. } => 0,
                     })
                     .sum(),
                 MessageContent::ToolCalls(MessageContentToolCalls {
                     tool_results, text, ..
                 }) => {
-                    estimate_token_length(text)
+                    self.count_tokens(text)
                         + tool_results
                             .iter()
                             .map(|v| {
                                 serde_json::to_string(v)
-                                    .map(|v| estimate_token_length(&v))
+                                    .map(|v| self.count_tokens(&v))
                                     .unwrap_or_default()
                             })
                             .sum::<usize>()
@@ -285,7 +307,7 @@ impl Model {
         let total_tokens = self.total_tokens(messages) + BASIS_TOKENS;
         if let Some(max_input_tokens) = self.data.max_input_tokens {
             if total_tokens >= max_input_tokens {
-                bail!("Exceed max_input_tokens limit")
+                bail!("{EXCEED_MAX_INPUT_TOKENS}")
             }
         }
         Ok(())
@@ -300,6 +322,8 @@ pub struct ModelData {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub real_name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokenizer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max_input_tokens: Option<usize>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub input_price: Option<f64>,
@@ -318,9 +342,13 @@ pub struct ModelData {
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     pub supports_function_calling: bool,
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub supports_reasoning: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     no_stream: bool,
     #[serde(default, skip_serializing_if = "std::ops::Not::not")]
     no_system_message: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_prompt_caching: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     system_prompt_prefix: Option<String>,
 
@@ -0,0 +1,529 @@
+use super::*;
+
+use crate::utils::strip_think_tag;
+
+use anyhow::{bail, Context, Result};
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+pub const API_BASE: &str = "http://localhost:11434";
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct OllamaConfig {
+    pub name: Option<String>,
+    pub api_base: Option<String>,
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+    pub num_ctx: Option<u64>,
+    pub keep_alive: Option<Value>,
+    #[serde(default)]
+    pub models: Vec<ModelData>,
+    pub patch: Option<RequestPatch>,
+    pub extra: Option<ExtraConfig>,
+}
+
+impl OllamaClient {
+    config_get_fn!(api_base, get_api_base);
+    config_get_api_key_fn!(get_api_key);
+
+    pub const PROMPTS: [PromptAction<'static>; 1] =
+        [("api_base", "API Base", Some("e.g. http://localhost:11434"))];
+}
+
+impl_client_trait!(
+    OllamaClient,
+    (
+        prepare_chat_completions,
+        chat_completions,
+        chat_completions_streaming
+    ),
+    (prepare_embeddings, embeddings),
+    (noop_prepare_rerank, noop_rerank),
+);
+
+async fn prepare_chat_completions(
+    self_: &OllamaClient,
+    data: ChatCompletionsData,
+) -> Result<RequestData> {
+    let api_key = self_.get_api_key().ok();
+    let api_base = self_
+        .get_api_base()
+        .unwrap_or_else(|_| API_BASE.to_string());
+
+    let url = format!("{}/api/chat", api_base.trim_end_matches('/'));
+
+    let mut body = build_chat_completions_body(data, &self_.model)?;
+    set_runtime_options(&mut body, &self_.config);
+
+    let mut request_data = RequestData::new(url, body);
+
+    if let Some(api_key) = api_key {
+        request_data.bearer_auth(api_key);
+    }
+
+    Ok(request_data)
+}
+
+async fn prepare_embeddings(self_: &OllamaClient, data: &EmbeddingsData) -> Result<RequestData> {
+    let api_key = self_.get_api_key().ok();
+    let api_base = self_
+        .get_api_base()
+        .unwrap_or_else(|_| API_BASE.to_string());
+
+    let url = format!("{}/api/embed", api_base.trim_end_matches('/'));
+
+    let mut body = json!({
+        "model": self_.model.real_name(),
+        "input": data.texts,
+    });
+    set_runtime_options(&mut body, &self_.config);
+
+    let mut request_data = RequestData::new(url, body);
+
+    if let Some(api_key) = api_key {
+        request_data.bearer_auth(api_key);
+    }
+
+    Ok(request_data)
+}
+
+async fn chat_completions(
+    builder: RequestBuilder,
+    _model: &Model,
+) -> Result<ChatCompletionsOutput> {
+    let res = send_request(builder).await?;
+    let data: Value = res.json().await?;
+    debug!("non-stream-data: {data}");
+    extract_chat_completions(&data)
+}
+
+async fn chat_completions_streaming(
+    builder: RequestBuilder,
+    handler: &mut SseHandler,
+    _model: &Model,
+) -> Result<()> {
+    let res = send_request(builder).await?;
+    let handle = |message: &str| -> Result<()> { handle_stream_message(handler, message) };
+    json_stream(res.bytes_stream(), handle).await?;
+    Ok(())
+}
+
+fn handle_stream_message(handler: &mut SseHandler, message: &str) -> Result<()> {
+    let data: Value = serde_json::from_str(message)?;
+    debug!("stream-data: {data}");
+    if let Some(error) = data["error"].as_str() {
+        bail!("{error}");
+    }
+    if !data["done"].is_boolean() {
+        bail!("Invalid response data: {data}");
+    }
+    if let Some(text) = data["message"]["thinking"]
+        .as_str()
+        .filter(|v| !v.is_empty())
+    {
+        handler.reasoning(text)?;
+    }
+    if let Some(text) = data["message"]["content"]
+        .as_str()
+        .filter(|v| !v.is_empty())
+    {
+        handler.text(text)?;
+    }
+    if let Some(calls) = data["message"]["tool_calls"].as_array() {
+        for call in extract_tool_calls(calls) {
+            handler.tool_call(call)?;
+        }
+    }
+    if data["done"].as_bool() == Some(true) {
+        handler.update_usage(StreamUsage {
+            input_tokens: data["prompt_eval_count"].as_u64(),
+            output_tokens: data["eval_count"].as_u64(),
+            ..Default::default()
+        });
+    }
+    Ok(())
+}
+
+async fn embeddings(builder: RequestBuilder, _model: &Model) -> Result<EmbeddingsOutput> {
+    let res = send_request(builder).await?;
+    let data: Value = res.json().await?;
+    let res_body: EmbeddingsResBody =
+        serde_json::from_value(data).context("Invalid embeddings data")?;
+    Ok(res_body.embeddings)
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsResBody {
+    embeddings: Vec<Vec<f32>>,
+}
+
+fn build_chat_completions_body(data: ChatCompletionsData, model: &Model) -> Result<Value> {
+    let ChatCompletionsData {
+        messages,
+        temperature,
+        top_p,
+        functions,
+        stream,
+        schema,
+        reasoning,
+    } = data;
+
+    let mut network_image_urls = vec![];
+
+    let messages_len = messages.len();
+    let messages: Vec<Value> = messages
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, message)| {
+            let Message { role, content, .. } = message;
+            match content {
+                MessageContent::Text(text) if role.is_assistant() && i != messages_len - 1 => {
+                    vec![json!({ "role": role, "content": strip_think_tag(&text) })]
+                }
+                MessageContent::Text(text) => vec![json!({
+                    "role": role,
+                    "content": text,
+                })],
+                MessageContent::Array(list) => {
+                    let mut content = vec![];
+                    let mut images = vec![];
+                    for item in list {
+                        match item {
+                            MessageContentPart::Text { text } => {
+                                content.push(text);
+                            }
+                            MessageContentPart::ImageUrl {
+                                image_url: ImageUrl { url },
+                            } => {
+                                if let Some((_, data)) = url
+                                    .strip_prefix("data:")
+                                    .and_then(|v| v.split_once(";base64,"))
+                                {
+                                    images.push(data.to_string());
+                                } else {
+                                    network_image_urls.push(url.clone());
+                                }
+                            }
+                        }
+                    }
+                    let content = content.join("\n\n");
+                    vec![json!({ "role": role, "content": content, "images": images })]
+                }
+                MessageContent::ToolCalls(MessageContentToolCalls { tool_results, .. }) => {
+                    let tool_calls: Vec<_> = tool_results
+                        .iter()
+                        .map(|tool_result| {
+                            json!({
+                                "function": {
+                                    "name": tool_result.call.name,
+                                    "arguments": tool_result.call.arguments,
+                                },
+                            })
+                        })
+                        .collect();
+                    let mut messages = vec![json!({
+                        "role": MessageRole::Assistant,
+                        "content": "",
+                        "tool_calls": tool_calls,
+                    })];
+                    for tool_result in tool_results {
+                        messages.push(json!({
+                            "role": "tool",
+                            "content": tool_result.output.to_string(),
+                            "tool_name": tool_result.call.name,
+                        }));
+                    }
+                    messages
+                }
+            }
+        })
+        .collect();
+
+    if !network_image_urls.is_empty() {
+        bail!(
+            "The model does not support network images: {:?}",
+            network_image_urls
+        );
+    }
+
+    let mut body = json!({
+        "model": &model.real_name(),
+        "messages": messages,
+        "stream": stream,
+        "options": {},
+    });
+
+    if let Some(v) = model.max_tokens_param() {
+        body["options"]["num_predict"] = v.into();
+    }
+    if let Some(v) = temperature {
+        body["options"]["temperature"] = v.into();
+    }
+    if let Some(v) = top_p {
+        body["options"]["top_p"] = v.into();
+    }
+    if let Some(functions) = functions {
+        body["tools"] = functions
+            .iter()
+            .map(|v| {
+                json!({
+                    "type": "function",
+                    "function": v,
+                })
+            })
+            .collect();
+    }
+    if let Some(reasoning) = reasoning {
+        body["think"] = (!reasoning.is_off()).into();
+    }
+    if let Some(schema) = schema {
+        body["format"] = schema;
+    }
+
+    Ok(body)
+}
+
+fn set_runtime_options(body: &mut Value, config: &OllamaConfig) {
+    if let Some(v) = config.num_ctx {
+        body["options"]["num_ctx"] = v.into();
+    }
+    if let Some(v) = &config.keep_alive {
+        body["keep_alive"] = v.clone();
+    }
+}
+
+fn extract_chat_completions(data: &Value) -> Result<ChatCompletionsOutput> {
+    let text = data["message"]["content"].as_str().unwrap_or_default();
+    let reasoning = data["message"]["thinking"]
+        .as_str()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string());
+
+    let tool_calls = data["message"]["tool_calls"]
+        .as_array()
+        .map(|calls| extract_tool_calls(calls))
+        .unwrap_or_default();
+
+    if text.is_empty() && tool_calls.is_empty() {
+        bail!("Invalid response data: {data}");
+    }
+    let output = ChatCompletionsOutput {
+        text: text.to_string(),
+        reasoning,
+        tool_calls,
+        id: None,
+        input_tokens: data["prompt_eval_count"].as_u64(),
+        output_tokens: data["eval_count"].as_u64(),
+        cache_creation_input_tokens: None,
+        cache_read_input_tokens: None,
+    };
+    Ok(output)
+}
+
+fn extract_tool_calls(calls: &[Value]) -> Vec<ToolCall> {
+    calls
+        .iter()
+        .filter_map(|call| {
+            let name = call["function"]["name"].as_str()?;
+            let arguments = match &call["function"]["arguments"] {
+                Value::Null => json!({}),
+                Value::String(v) => v.parse().unwrap_or_else(|_| json!({})),
+                v => v.clone(),
+            };
+            Some(ToolCall::new(name.to_string(), arguments, None))
+        })
+        .collect()
+}
+
+pub async fn fetch_ollama_models(api_base: &str, api_key: Option<&str>) -> Result<Vec<String>> {
+    let client = reqwest::Client::new();
+    let mut builder = client.get(format!("{}/api/tags", api_base.trim_end_matches('/')));
+    if let Some(api_key) = api_key {
+        builder = builder.bearer_auth(api_key);
+    }
+    let res_body: Value = builder.send().await?.json().await?;
+    let mut result: Vec<String> = res_body["models"]
+        .as_array()
+        .map(|v| {
+            v.iter()
+                .filter_map(|v| v["name"].as_str().map(|v| v.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    if result.is_empty() {
+        bail!("No valid models")
+    }
+    result.sort_unstable();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::ToolResult;
+    use crate::utils::create_abort_signal;
+
+    #[test]
+    fn test_build_chat_completions_body() {
+        let mut model = Model::new("ollama", "qwen3");
+        model.set_max_tokens(Some(1024), true);
+        let call = ToolCall::new("get_weather".into(), json!({ "city": "Paris" }), None);
+        let messages = vec![
+            Message::new(
+                MessageRole::User,
+                MessageContent::Array(vec![
+                    MessageContentPart::Text {
+                        text: "What is this?".into(),
+                    },
+                    MessageContentPart::ImageUrl {
+                        image_url: ImageUrl {
+                            url: "data:image/png;base64,aGVsbG8=".into(),
+                        },
+                    },
+                ]),
+            ),
+            Message::new(
+                MessageRole::Assistant,
+                MessageContent::Text("<think>Hmm</think>A cat".into()),
+            ),
+            Message::new(
+                MessageRole::Assistant,
+                MessageContent::ToolCalls(MessageContentToolCalls::new(
+                    vec![ToolResult::new(call, json!({ "temperature": 20 }))],
+                    String::new(),
+                )),
+            ),
+        ];
+        let functions = serde_json::from_value(json!([{
+            "name": "get_weather",
+            "description": "Get the weather",
+            "parameters": { "type": "object", "properties": {} },
+        }]))
+        .unwrap();
+        let data = ChatCompletionsData {
+            messages,
+            temperature: Some(0.5),
+            top_p: None,
+            functions: Some(functions),
+            stream: true,
+            schema: Some(json!({ "type": "object" })),
+            reasoning: Some(Reasoning::Off),
+        };
+        let body = build_chat_completions_body(data, &model).unwrap();
+        assert_eq!(
+            body["messages"],
+            json!([
+                { "role": "user", "content": "What is this?", "images": ["aGVsbG8="] },
+                { "role": "assistant", "content": "A cat" },
+                {
+                    "role": "assistant",
+                    "content": "",
+                    "tool_calls": [{
+                        "function": { "name": "get_weather", "arguments": { "city": "Paris" } },
+                    }],
+                },
+                { "role": "tool", "content": "{\"temperature\":20}", "tool_name": "get_weather" },
+            ])
+        );
+        assert_eq!(
+            body["options"],
+            json!({ "num_predict": 1024, "temperature": 0.5 })
+        );
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(body["tools"][0]["function"]["name"], "get_weather");
+        assert_eq!(body["think"], false);
+        assert_eq!(body["format"], json!({ "type": "object" }));
+        assert_eq!(body["stream"], true);
+    }
+
+    #[test]
+    fn test_build_chat_completions_body_rejects_network_images() {
+        let data = ChatCompletionsData {
+            messages: vec![Message::new(
+                MessageRole::User,
+                MessageContent::Array(vec![MessageContentPart::ImageUrl {
+                    image_url: ImageUrl {
+                        url: "https://example.com/cat.png".into(),
+                    },
+                }]),
+            )],
+            temperature: None,
+            top_p: None,
+            functions: None,
+            stream: false,
+            schema: None,
+            reasoning: None,
+        };
+        let model = Model::new("ollama", "llava");
+        assert!(build_chat_completions_body(data, &model).is_err());
+    }
+
+    #[test]
+    fn test_set_runtime_options() {
+        let config: OllamaConfig = serde_yaml::from_str("num_ctx: 32768\nkeep_alive: 10m").unwrap();
+        let mut body = json!({ "model": "qwen3", "options": { "temperature": 0.5 } });
+        set_runtime_options(&mut body, &config);
+        assert_eq!(
+            body["options"],
+            json!({ "temperature": 0.5, "num_ctx": 32768 })
+        );
+        assert_eq!(body["keep_alive"], "10m");
+
+        let mut body = json!({ "model": "nomic-embed-text", "input": ["hello"] });
+        set_runtime_options(&mut body, &OllamaConfig::default());
+        assert_eq!(
+            body,
+            json!({ "model": "nomic-embed-text", "input": ["hello"] })
+        );
+    }
+
+    #[test]
+    fn test_handle_stream_message() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut handler = SseHandler::new(tx, create_abort_signal());
+        let messages = [
+            r#"{"model":"qwen3","message":{"role":"assistant","content":"","thinking":"Let me"},"done":false}"#,
+            r#"{"model":"qwen3","message":{"role":"assistant","content":"","thinking":" check."},"done":false}"#,
+            r#"{"model":"qwen3","message":{"role":"assistant","content":"","tool_calls":[{"function":{"name":"get_weather","arguments":{"city":"Paris"}}}]},"done":false}"#,
+            r#"{"model":"qwen3","message":{"role":"assistant","content":"It is"},"done":false}"#,
+            r#"{"model":"qwen3","message":{"role":"assistant","content":" sunny."},"done":true,"prompt_eval_count":12,"eval_count":5}"#,
+        ];
+        for message in messages {
+            handle_stream_message(&mut handler, message).unwrap();
+        }
+        assert_eq!(handler.reasoning_buffer(), "Let me check.");
+        assert_eq!(handler.buffer(), "It is sunny.");
+        assert_eq!(handler.tool_calls().len(), 1);
+        assert_eq!(handler.tool_calls()[0].name, "get_weather");
+        assert_eq!(
+            handler.tool_calls()[0].arguments,
+            json!({ "city": "Paris" })
+        );
+        assert_eq!(handler.usage().input_tokens, Some(12));
+        assert_eq!(handler.usage().output_tokens, Some(5));
+        assert!(matches!(rx.try_recv(), Ok(SseEvent::Reasoning(v)) if v == "Let me"));
+
+        let err = handle_stream_message(&mut handler, r#"{"error":"model not found"}"#);
+        assert_eq!(err.unwrap_err().to_string(), "model not found");
+        assert!(handle_stream_message(&mut handler, r#"{"message":{}}"#).is_err());
+    }
+
+    #[test]
+    fn test_extract_tool_calls() {
+        let calls = json!([
+            { "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" } },
+            { "function": { "name": "get_time", "arguments": "not json" } },
+            { "function": { "name": "list_files" } },
+            { "function": { "arguments": {} } },
+        ]);
+        let calls = extract_tool_calls(calls.as_array().unwrap());
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0].arguments, json!({ "city": "Paris" }));
+        assert_eq!(calls[1].arguments, json!({}));
+        assert_eq!(calls[2].name, "list_files");
+        assert_eq!(calls[2].arguments, json!({}));
+    }
+}
@@ -13,6 +13,8 @@ const API_BASE: &str = "https://api.openai.com/v1";
 pub struct OpenAIConfig {
     pub name: Option<String>,
     pub api_key: Option<String>,
+    #[serde(default)]
+    pub api_keys: Vec<String>,
     pub api_base: Option<String>,
     pub organization_id: Option<String>,
     #[serde(default)]
@@ -22,7 +24,7 @@ pub struct OpenAIConfig {
 }
 
 impl OpenAIClient {
-    config_get_fn!(api_key, get_api_key);
+    config_get_api_key_fn!(get_api_key);
     config_get_fn!(api_base, get_api_base);
 
     pub const PROMPTS: [PromptAction<'static>; 1] = [("api_key", "API Key", None)];
@@ -50,7 +52,11 @@ async fn prepare_chat_completions(
 
     let url = format!("{}/chat/completions", api_base.trim_end_matches('/'));
 
-    let body = openai_build_chat_completions_body(data, &self_.model);
+    let stream = data.stream;
+    let mut body = openai_build_chat_completions_body(data, &self_.model);
+    if stream {
+        body["stream_options"] = json!({ "include_usage": true });
+    }
 
     let mut request_data = RequestData::new(url, body);
 
@@ -86,12 +92,8 @@ pub async fn openai_chat_completions(
     builder: RequestBuilder,
     _model: &Model,
 ) -> Result<ChatCompletionsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
 
     debug!("non-stream-data: {data}");
     openai_extract_chat_completions(&data)
@@ -106,7 +108,6 @@ pub async fn openai_chat_completions_streaming(
     let mut function_name = String::new();
     let mut function_arguments = String::new();
     let mut function_id = String::new();
-    let mut reasoning_state = 0;
     let handle = |message: SseMmessage| -> Result<bool> {
         if message.data == "[DONE]" {
             if !function_name.is_empty() {
@@ -126,25 +127,24 @@ pub async fn openai_chat_completions_streaming(
         }
         let data: Value = serde_json::from_str(&message.data)?;
         debug!("stream-data: {data}");
+        if let Some(usage) = data["usage"].as_object() {
+            handler.update_usage(StreamUsage {
+                input_tokens: usage.get("prompt_tokens").and_then(|v| v.as_u64()),
+                output_tokens: usage.get("completion_tokens").and_then(|v| v.as_u64()),
+                ..Default::default()
+            });
+        }
         if let Some(text) = data["choices"][0]["delta"]["content"]
             .as_str()
             .filter(|v| !v.is_empty())
         {
-            if reasoning_state == 1 {
-                handler.text("\n</think>\n\n")?;
-                reasoning_state = 0;
-            }
             handler.text(text)?;
         } else if let Some(text) = data["choices"][0]["delta"]["reasoning_content"]
             .as_str()
             .or_else(|| data["choices"][0]["delta"]["reasoning"].as_str())
             .filter(|v| !v.is_empty())
         {
-            if reasoning_state == 0 {
-                handler.text("<think>\n")?;
-                reasoning_state = 1;
-            }
-            handler.text(text)?;
+            handler.reasoning(text)?;
         }
         if let (Some(function), index, id) = (
             data["choices"][0]["delta"]["tool_calls"][0]["function"].as_object(),
@@ -153,10 +153,6 @@ pub async fn openai_chat_completions_streaming(
                 .as_str()
                 .filter(|v| !v.is_empty()),
         ) {
-            if reasoning_state == 1 {
-                handler.text("\n</think>\n\n")?;
-                reasoning_state = 0;
-            }
             let maybe_call_id = format!("{}/{}", id.unwrap_or_default(), index.unwrap_or_default());
             if maybe_call_id != call_id && maybe_call_id.len() >= call_id.len() {
                 if !function_name.is_empty() {
@@ -201,12 +197,8 @@ pub async fn openai_embeddings(
     builder: RequestBuilder,
     _model: &Model,
 ) -> Result<EmbeddingsOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
     let res_body: EmbeddingsResBody =
         serde_json::from_value(data).context("Invalid embeddings data")?;
     let output = res_body.data.into_iter().map(|v| v.embedding).collect();
@@ -230,6 +222,8 @@ pub fn openai_build_chat_completions_body(data: ChatCompletionsData, model: &Mod
         top_p,
         functions,
         stream,
+        schema,
+        reasoning,
     } = data;
 
     let messages_len = messages.len();
@@ -237,7 +231,7 @@ pub fn openai_build_chat_completions_body(data: ChatCompletionsData, model: &Mod
         .into_iter()
         .enumerate()
         .flat_map(|(i, message)| {
-            let Message { role, content } = message;
+            let Message { role, content, .. } = message;
             match content {
                 MessageContent::ToolCalls(MessageContentToolCalls {
                     tool_results,
@@ -326,6 +320,9 @@ pub fn openai_build_chat_completions_body(data: ChatCompletionsData, model: &Mod
     if let Some(v) = top_p {
         body["top_p"] = v.into();
     }
+    if let Some(v) = reasoning.and_then(|v| v.effort()) {
+        body["reasoning_effort"] = v.into();
+    }
     if stream {
         body["stream"] = true.into();
     }
@@ -340,6 +337,15 @@ pub fn openai_build_chat_completions_body(data: ChatCompletionsData, model: &Mod
             })
             .collect();
     }
+    if let Some(schema) = schema {
+        body["response_format"] = json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": "response",
+                "schema": schema,
+            },
+        });
+    }
     body
 }
 
@@ -358,8 +364,9 @@ pub fn openai_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
     let reasoning = data["choices"][0]["message"]["reasoning_content"]
         .as_str()
         .or_else(|| data["choices"][0]["message"]["reasoning"].as_str())
-        .unwrap_or_default()
-        .trim();
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string());
 
     let mut tool_calls = vec![];
     if let Some(calls) = data["choices"][0]["message"]["tool_calls"].as_array() {
@@ -384,17 +391,15 @@ pub fn openai_extract_chat_completions(data: &Value) -> Result<ChatCompletionsOu
     if text.is_empty() && tool_calls.is_empty() {
         bail!("Invalid response data: {data}");
     }
-    let text = if !reasoning.is_empty() {
-        format!("<think>\n{reasoning}\n</think>\n\n{text}")
-    } else {
-        text.to_string()
-    };
     let output = ChatCompletionsOutput {
-        text,
+        text: text.to_string(),
+        reasoning,
         tool_calls,
         id: data["id"].as_str().map(|v| v.to_string()),
         input_tokens: data["usage"]["prompt_tokens"].as_u64(),
         output_tokens: data["usage"]["completion_tokens"].as_u64(),
+        cache_creation_input_tokens: None,
+        cache_read_input_tokens: None,
     };
     Ok(output)
 }
@@ -1,3 +1,4 @@
+use super::access_token::prepare_oauth_access_token;
 use super::openai::*;
 use super::*;
 
@@ -6,12 +7,19 @@ use reqwest::RequestBuilder;
 use serde::Deserialize;
 use serde_json::{json, Value};
 
+use crate::auth::{AuthMethod, OAuthConfig};
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct OpenAICompatibleConfig {
     pub name: Option<String>,
     pub api_base: Option<String>,
     pub api_key: Option<String>,
     #[serde(default)]
+    pub api_keys: Vec<String>,
+    #[serde(default)]
+    pub auth: AuthMethod,
+    pub oauth: Option<OAuthConfig>,
+    #[serde(default)]
     pub models: Vec<ModelData>,
     pub patch: Option<RequestPatch>,
     pub extra: Option<ExtraConfig>,
@@ -19,9 +27,28 @@ pub struct OpenAICompatibleConfig {
 
 impl OpenAICompatibleClient {
     config_get_fn!(api_base, get_api_base);
-    config_get_fn!(api_key, get_api_key);
+    config_get_api_key_fn!(get_api_key);
 
     pub const PROMPTS: [PromptAction<'static>; 0] = [];
+
+    /// Sends the OAuth access token from `aichat auth login --client <name>`, or the API key if any.
+    async fn authenticate(&self, request_data: &mut RequestData) -> Result<()> {
+        match self.config.auth {
+            AuthMethod::OAuth => {
+                let oauth = self.config.oauth.clone().with_context(|| {
+                    format!("Missing `oauth` config for client '{}'", self.name())
+                })?;
+                let access_token = prepare_oauth_access_token(self.name(), oauth).await?;
+                request_data.bearer_auth(access_token);
+            }
+            AuthMethod::ApiKey => {
+                if let Ok(api_key) = self.get_api_key() {
+                    request_data.bearer_auth(api_key);
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl_client_trait!(
@@ -39,7 +66,6 @@ async fn prepare_chat_completions(
     self_: &OpenAICompatibleClient,
     data: ChatCompletionsData,
 ) -> Result<RequestData> {
-    let api_key = self_.get_api_key().ok();
     let api_base = get_api_base_ext(self_)?;
 
     let url = format!("{api_base}/chat/completions");
@@ -48,9 +74,7 @@ async fn prepare_chat_completions(
 
     let mut request_data = RequestData::new(url, body);
 
-    if let Some(api_key) = api_key {
-        request_data.bearer_auth(api_key);
-    }
+    self_.authenticate(&mut request_data).await?;
 
     Ok(request_data)
 }
@@ -59,7 +83,6 @@ async fn prepare_embeddings(
     self_: &OpenAICompatibleClient,
     data: &EmbeddingsData,
 ) -> Result<RequestData> {
-    let api_key = self_.get_api_key().ok();
     let api_base = get_api_base_ext(self_)?;
 
     let url = format!("{api_base}/embeddings");
@@ -68,15 +91,12 @@ async fn prepare_embeddings(
 
     let mut request_data = RequestData::new(url, body);
 
-    if let Some(api_key) = api_key {
-        request_data.bearer_auth(api_key);
-    }
+    self_.authenticate(&mut request_data).await?;
 
     Ok(request_data)
 }
 
-fn prepare_rerank(self_: &OpenAICompatibleClient, data: &RerankData) -> Result<RequestData> {
-    let api_key = self_.get_api_key().ok();
+async fn prepare_rerank(self_: &OpenAICompatibleClient, data: &RerankData) -> Result<RequestData> {
     let api_base = get_api_base_ext(self_)?;
 
     let url = if self_.name().starts_with("ernie") {
@@ -89,9 +109,7 @@ fn prepare_rerank(self_: &OpenAICompatibleClient, data: &RerankData) -> Result<R
 
     let mut request_data = RequestData::new(url, body);
 
-    if let Some(api_key) = api_key {
-        request_data.bearer_auth(api_key);
-    }
+    self_.authenticate(&mut request_data).await?;
 
     Ok(request_data)
 }
@@ -118,12 +136,8 @@ fn get_api_base_ext(self_: &OpenAICompatibleClient) -> Result<String> {
 }
 
 pub async fn generic_rerank(builder: RequestBuilder, _model: &Model) -> Result<RerankOutput> {
-    let res = builder.send().await?;
-    let status = res.status();
+    let res = send_request(builder).await?;
     let mut data: Value = res.json().await?;
-    if !status.is_success() {
-        catch_error(&data, status.as_u16())?;
-    }
     if data.get("results").is_none() && data.get("data").is_some() {
         if let Some(data_obj) = data.as_object_mut() {
             if let Some(value) = data_obj.remove("data") {
@@ -160,3 +174,54 @@ pub fn generic_build_rerank_body(data: &RerankData, model: &Model) -> Value {
     }
     body
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::access_token::set_access_token;
+
+    #[tokio::test]
+    async fn test_oauth_requests() {
+        let name = "test-oauth-gateway";
+        let config: OpenAICompatibleConfig = serde_json::from_value(json!({
+            "name": name,
+            "api_base": "http://127.0.0.1:1/v1",
+            "auth": "oauth",
+            "oauth": { "client_id": "test", "token_url": "http://127.0.0.1:1/token" },
+        }))
+        .unwrap();
+        // A cached token, as loaded from the credentials of `aichat auth login`
+        set_access_token(name, "oauth-token".into(), i64::MAX);
+        let client = OpenAICompatibleClient {
+            global_config: Default::default(),
+            config,
+            model: Model::new(name, "model"),
+        };
+        let authorization =
+            |request_data: RequestData| request_data.headers["authorization"].clone();
+
+        let data = ChatCompletionsData {
+            messages: vec![Message::new(
+                MessageRole::User,
+                MessageContent::Text("Hello".into()),
+            )],
+            temperature: None,
+            top_p: None,
+            functions: None,
+            stream: false,
+            schema: None,
+            reasoning: None,
+        };
+        let request_data = prepare_chat_completions(&client, data).await.unwrap();
+        assert_eq!(authorization(request_data), "Bearer oauth-token");
+
+        let data = EmbeddingsData::new(vec!["Hello".into()], false);
+        let request_data = prepare_embeddings(&client, &data).await.unwrap();
+        assert_eq!(authorization(request_data), "Bearer oauth-token");
+
+        let data = RerankData::new("Hello".into(), vec!["Hello".into()], 1);
+        let request_data = prepare_rerank(&client, &data).await.unwrap();
+        assert_eq!(request_data.url, "http://127.0.0.1:1/v1/rerank");
+        assert_eq!(authorization(request_data), "Bearer oauth-token");
+    }
+}
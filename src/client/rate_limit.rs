@@ -0,0 +1,274 @@
+use super::{ApiError, Client};
+
+use crate::utils::sha256;
+
+use anyhow::{anyhow, Result};
+use indexmap::IndexMap;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+const DEFAULT_API_KEY_COOLDOWN: u64 = 60;
+
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+static API_KEY_POOLS: LazyLock<Mutex<IndexMap<String, ApiKeyPool>>> =
+    LazyLock::new(|| Mutex::new(IndexMap::new()));
+
+static RATE_LIMITERS: LazyLock<Mutex<IndexMap<String, RateLimiter>>> =
+    LazyLock::new(|| Mutex::new(IndexMap::new()));
+
+tokio::task_local! {
+    static REQUEST_SCOPE: RequestScope;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiKeyStrategy {
+    #[default]
+    RoundRobin,
+    LeastRecentlyLimited,
+}
+
+#[derive(Debug, Default)]
+struct ApiKeyPool {
+    cursor: usize,
+    states: IndexMap<String, ApiKeyState>,
+}
+
+#[derive(Debug, Default)]
+struct ApiKeyState {
+    last_used: Option<Instant>,
+    last_limited: Option<Instant>,
+    cooldown_until: Option<Instant>,
+}
+
+impl ApiKeyPool {
+    fn select(&mut self, keys: &[String], strategy: ApiKeyStrategy) -> String {
+        let now = Instant::now();
+        for key in keys {
+            self.states.entry(key.clone()).or_default();
+        }
+        let is_cooling =
+            |state: &ApiKeyState| state.cooldown_until.map(|v| v > now).unwrap_or_default();
+        let available: Vec<usize> = (0..keys.len())
+            .filter(|i| !is_cooling(&self.states[&keys[*i]]))
+            .collect();
+        let index = if available.is_empty() {
+            // Every key is cooling down, so take the one that recovers first.
+            (0..keys.len())
+                .min_by_key(|i| self.states[&keys[*i]].cooldown_until)
+                .unwrap_or_default()
+        } else {
+            match strategy {
+                ApiKeyStrategy::RoundRobin => {
+                    let index = available
+                        .iter()
+                        .copied()
+                        .find(|i| *i >= self.cursor)
+                        .unwrap_or(available[0]);
+                    self.cursor = index + 1;
+                    index
+                }
+                ApiKeyStrategy::LeastRecentlyLimited => available
+                    .iter()
+                    .copied()
+                    .min_by_key(|i| {
+                        let state = &self.states[&keys[*i]];
+                        (state.last_limited, state.last_used)
+                    })
+                    .unwrap_or_default(),
+            }
+        };
+        let key = keys[index].clone();
+        if let Some(state) = self.states.get_mut(&key) {
+            state.last_used = Some(now);
+        }
+        key
+    }
+
+    fn mark_limited(&mut self, key: &str, cooldown: Duration) {
+        let now = Instant::now();
+        let state = self.states.entry(key.to_string()).or_default();
+        state.last_limited = Some(now);
+        state.cooldown_until = Some(now + cooldown);
+    }
+}
+
+/// Picks one of the configured keys of a client according to its strategy.
+pub fn select_api_key(
+    client_name: &str,
+    keys: &[String],
+    strategy: Option<ApiKeyStrategy>,
+) -> Result<String> {
+    let key = match keys {
+        [] => return Err(anyhow!("Miss 'api_key'")),
+        [key] => key.clone(),
+        _ => API_KEY_POOLS
+            .lock()
+            .entry(client_name.to_string())
+            .or_default()
+            .select(keys, strategy.unwrap_or_default()),
+    };
+    let _ = REQUEST_SCOPE.try_with(|scope| *scope.api_key.borrow_mut() = Some(key.clone()));
+    Ok(key)
+}
+
+#[derive(Debug, Default)]
+struct RateLimiter {
+    requests: VecDeque<(Instant, usize)>,
+}
+
+impl RateLimiter {
+    /// Reserves a slot for the request, or returns how long to wait for one.
+    fn try_acquire(
+        &mut self,
+        requests_per_minute: Option<usize>,
+        tokens_per_minute: Option<usize>,
+        tokens: usize,
+    ) -> Option<Duration> {
+        let now = Instant::now();
+        while let Some((time, _)) = self.requests.front() {
+            if now.duration_since(*time) < RATE_LIMIT_WINDOW {
+                break;
+            }
+            self.requests.pop_front();
+        }
+        let used_tokens: usize = self.requests.iter().map(|(_, v)| v).sum();
+        let exceed_requests = requests_per_minute
+            .map(|v| self.requests.len() >= v)
+            .unwrap_or_default();
+        // A single request larger than the whole budget is let through once the window is empty.
+        let exceed_tokens = tokens_per_minute
+            .map(|v| !self.requests.is_empty() && used_tokens + tokens > v)
+            .unwrap_or_default();
+        if exceed_requests || exceed_tokens {
+            let (time, _) = self.requests.front()?;
+            return Some((*time + RATE_LIMIT_WINDOW).saturating_duration_since(now));
+        }
+        self.requests.push_back((now, tokens));
+        None
+    }
+}
+
+struct RequestScope {
+    client_name: String,
+    tokens: usize,
+    requests_per_minute: Option<usize>,
+    tokens_per_minute: Option<usize>,
+    api_key: RefCell<Option<String>>,
+}
+
+/// Tracks which API key a request used so that a 429 puts it on cooldown, and
+/// applies the client-side rate limits in `send_request`.
+pub async fn with_rate_limit<C, T, F>(client: &C, tokens: usize, future: F) -> Result<T>
+where
+    C: Client + ?Sized,
+    F: Future<Output = Result<T>>,
+{
+    let extra = client.extra_config();
+    let scope = RequestScope {
+        client_name: client.name().to_string(),
+        tokens,
+        requests_per_minute: extra.and_then(|v| v.requests_per_minute),
+        tokens_per_minute: extra.and_then(|v| v.tokens_per_minute),
+        api_key: RefCell::new(None),
+    };
+    let (ret, api_key) = REQUEST_SCOPE
+        .scope(scope, async {
+            let ret = future.await;
+            let api_key = REQUEST_SCOPE.with(|v| v.api_key.borrow_mut().take());
+            (ret, api_key)
+        })
+        .await;
+    if let (Err(err), Some(api_key)) = (&ret, api_key) {
+        if let Some(err) = err
+            .chain()
+            .find_map(|v| v.downcast_ref::<ApiError>())
+            .filter(|v| v.status == 429)
+        {
+            let cooldown = err.retry_after.unwrap_or_else(|| {
+                Duration::from_secs(
+                    extra
+                        .and_then(|v| v.api_key_cooldown)
+                        .unwrap_or(DEFAULT_API_KEY_COOLDOWN),
+                )
+            });
+            debug!("Cool down api key of {} for {cooldown:?}", client.name());
+            if let Some(pool) = API_KEY_POOLS.lock().get_mut(client.name()) {
+                pool.mark_limited(&api_key, cooldown);
+            }
+        }
+    }
+    ret
+}
+
+/// Waits until the request fits the client-side rate limits of the current scope.
+pub async fn throttle_request() {
+    let limits = REQUEST_SCOPE.try_with(|scope| {
+        // Each key has its own quota, so limits apply per key.
+        let limiter_key = match scope.api_key.borrow().as_ref() {
+            Some(api_key) => format!("{}:{}", scope.client_name, sha256(api_key)),
+            None => scope.client_name.clone(),
+        };
+        (
+            limiter_key,
+            scope.requests_per_minute,
+            scope.tokens_per_minute,
+            scope.tokens,
+        )
+    });
+    let (limiter_key, requests_per_minute, tokens_per_minute, tokens) = match limits {
+        Ok(v) if v.1.is_some() || v.2.is_some() => v,
+        _ => return,
+    };
+    loop {
+        let wait = RATE_LIMITERS
+            .lock()
+            .entry(limiter_key.clone())
+            .or_default()
+            .try_acquire(requests_per_minute, tokens_per_minute, tokens);
+        match wait {
+            Some(wait) => {
+                debug!("Rate limit reached for {limiter_key}, wait {wait:?}");
+                tokio::time::sleep(wait).await;
+            }
+            None => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_key_pool() {
+        let keys: Vec<String> = vec!["a".into(), "b".into(), "c".into()];
+        let mut pool = ApiKeyPool::default();
+        let select = |pool: &mut ApiKeyPool| pool.select(&keys, ApiKeyStrategy::RoundRobin);
+        assert_eq!(select(&mut pool), "a");
+        assert_eq!(select(&mut pool), "b");
+        pool.mark_limited("c", Duration::from_secs(60));
+        assert_eq!(select(&mut pool), "a");
+        assert_eq!(select(&mut pool), "b");
+        pool.mark_limited("a", Duration::from_secs(60));
+        pool.mark_limited("b", Duration::from_secs(30));
+        assert_eq!(select(&mut pool), "b");
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::default();
+        assert!(limiter.try_acquire(Some(2), None, 10).is_none());
+        assert!(limiter.try_acquire(Some(2), None, 10).is_none());
+        assert!(limiter.try_acquire(Some(2), None, 10).is_some());
+        let mut limiter = RateLimiter::default();
+        assert!(limiter.try_acquire(None, Some(100), 500).is_none());
+        assert!(limiter.try_acquire(None, Some(100), 1).is_some());
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{anyhow, Result};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+/// Provider-neutral reasoning setting, mapped by each client to its native request field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reasoning {
+    Off,
+    Low,
+    Medium,
+    High,
+    Budget(u64),
+}
+
+impl Reasoning {
+    pub fn is_off(&self) -> bool {
+        matches!(self, Reasoning::Off | Reasoning::Budget(0))
+    }
+
+    /// Effort level for providers that only accept low/medium/high.
+    pub fn effort(&self) -> Option<&'static str> {
+        match self {
+            Reasoning::Off | Reasoning::Budget(0) => None,
+            Reasoning::Low => Some("low"),
+            Reasoning::Medium => Some("medium"),
+            Reasoning::High => Some("high"),
+            Reasoning::Budget(v) if *v <= 2048 => Some("low"),
+            Reasoning::Budget(v) if *v <= 16384 => Some("medium"),
+            Reasoning::Budget(_) => Some("high"),
+        }
+    }
+
+    /// Thinking token budget for providers that take an explicit number.
+    pub fn budget_tokens(&self) -> u64 {
+        match self {
+            Reasoning::Off => 0,
+            Reasoning::Low => 1024,
+            Reasoning::Medium => 8192,
+            Reasoning::High => 24576,
+            Reasoning::Budget(v) => *v,
+        }
+    }
+}
+
+impl FromStr for Reasoning {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "off" | "none" | "false" => Ok(Reasoning::Off),
+            "low" => Ok(Reasoning::Low),
+            "medium" => Ok(Reasoning::Medium),
+            "high" => Ok(Reasoning::High),
+            v => v.parse::<u64>().map(Reasoning::Budget).map_err(|_| {
+                anyhow!(
+                    "Invalid reasoning '{s}', expected off, low, medium, high or a token budget"
+                )
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Reasoning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reasoning::Off => write!(f, "off"),
+            Reasoning::Low => write!(f, "low"),
+            Reasoning::Medium => write!(f, "medium"),
+            Reasoning::High => write!(f, "high"),
+            Reasoning::Budget(v) => write!(f, "{v}"),
+        }
+    }
+}
+
+impl Serialize for Reasoning {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Reasoning::Budget(v) => serializer.serialize_u64(*v),
+            _ => serializer.serialize_str(&self.to_string()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Reasoning {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Budget(u64),
+            Flag(bool),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Budget(v) => Ok(Reasoning::Budget(v)),
+            Raw::Flag(true) => Ok(Reasoning::Medium),
+            Raw::Flag(false) => Ok(Reasoning::Off),
+            Raw::Text(v) => v.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reasoning() {
+        assert_eq!("high".parse::<Reasoning>().unwrap(), Reasoning::High);
+        assert_eq!(
+            "4096".parse::<Reasoning>().unwrap(),
+            Reasoning::Budget(4096)
+        );
+        assert!("max".parse::<Reasoning>().is_err());
+        assert_eq!(Reasoning::Budget(4096).effort(), Some("medium"));
+        assert_eq!(Reasoning::Off.effort(), None);
+        assert_eq!(Reasoning::Low.budget_tokens(), 1024);
+        let value: Reasoning = serde_yaml::from_str("2000").unwrap();
+        assert_eq!(value, Reasoning::Budget(2000));
+        let value: Reasoning = serde_yaml::from_str("off").unwrap();
+        assert_eq!(value, Reasoning::Off);
+        assert_eq!(serde_json::to_string(&Reasoning::Low).unwrap(), "\"low\"");
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::Result;
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde::Deserialize;
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+
+const DEFAULT_RETRY_STATUSES: [u16; 6] = [408, 429, 500, 502, 503, 504];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    pub max_attempts: usize,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub multiplier: f64,
+    pub jitter: bool,
+    pub statuses: Vec<u16>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_delay_ms: 1000,
+            max_delay_ms: 30000,
+            multiplier: 2.0,
+            jitter: true,
+            statuses: DEFAULT_RETRY_STATUSES.to_vec(),
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn is_retryable(&self, err: &anyhow::Error) -> bool {
+        match err.chain().find_map(|v| v.downcast_ref::<ApiError>()) {
+            Some(err) => self.statuses.contains(&err.status),
+            None => err
+                .chain()
+                .filter_map(|v| v.downcast_ref::<reqwest::Error>())
+                .any(|v| v.is_connect() || v.is_timeout()),
+        }
+    }
+
+    /// Returns how long to wait before the next attempt, or `None` if the error is final.
+    pub fn next_delay(&self, attempt: usize, err: &anyhow::Error) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        if !self.is_retryable(err) {
+            return None;
+        }
+        let max_delay = Duration::from_millis(self.max_delay_ms);
+        if let Some(retry_after) = err
+            .chain()
+            .find_map(|v| v.downcast_ref::<ApiError>())
+            .and_then(|v| v.retry_after)
+        {
+            // Waiting longer than allowed is pointless, e.g. for a daily quota.
+            return (retry_after <= max_delay).then_some(retry_after);
+        }
+        let exp = self.multiplier.max(1.0).powi(attempt as i32 - 1);
+        let delay = Duration::from_millis(self.initial_delay_ms)
+            .mul_f64(exp)
+            .min(max_delay);
+        if self.jitter {
+            Some(delay.mul_f64(rand::rng().random_range(0.5..=1.0)))
+        } else {
+            Some(delay)
+        }
+    }
+}
+
+/// Runs the request until it succeeds or the retry policy gives up.
+pub async fn with_retry<T, F, Fut>(
+    retry: Option<&RetryConfig>,
+    api: &str,
+    mut request: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Ok(v) => return Ok(v),
+            Err(err) => match retry.and_then(|v| v.next_delay(attempt, &err)) {
+                Some(delay) => {
+                    log_retry(api, attempt, delay, &err);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => return Err(err),
+            },
+        }
+    }
+}
+
+pub fn log_retry(api: &str, attempt: usize, delay: Duration, err: &anyhow::Error) {
+    warn!(
+        "Retrying {api} api in {}ms after attempt {attempt} failed: {err}",
+        delay.as_millis()
+    );
+}
+
+/// An error response returned by the provider.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: u16,
+    pub message: String,
+    pub retry_after: Option<Duration>,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let date = SystemTime::from(date);
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_error(status: u16, retry_after: Option<Duration>) -> anyhow::Error {
+        ApiError {
+            status,
+            message: "error".into(),
+            retry_after,
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_next_delay() {
+        let retry = RetryConfig {
+            jitter: false,
+            ..Default::default()
+        };
+        let err = api_error(429, None);
+        assert_eq!(retry.next_delay(1, &err), Some(Duration::from_secs(1)));
+        assert_eq!(retry.next_delay(2, &err), Some(Duration::from_secs(2)));
+        assert_eq!(retry.next_delay(3, &err), None);
+        assert_eq!(retry.next_delay(1, &api_error(400, None)), None);
+        let err = api_error(503, Some(Duration::from_secs(7)));
+        assert_eq!(retry.next_delay(1, &err), Some(Duration::from_secs(7)));
+        let err = api_error(503, Some(Duration::from_secs(3600)));
+        assert_eq!(retry.next_delay(1, &err), None);
+        let err = api_error(500, None).context("Failed to call api");
+        assert!(retry.next_delay(1, &err).is_some());
+        assert_eq!(retry.next_delay(1, &anyhow::anyhow!("other")), None);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(120)));
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+    }
+}
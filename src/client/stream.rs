@@ -1,18 +1,21 @@
-use super::{catch_error, ToolCall};
+use super::{catch_response_error, throttle_request, CassetteFrame, ToolCall};
 use crate::utils::AbortSignal;
 
 use anyhow::{anyhow, bail, Context, Result};
 use futures_util::{Stream, StreamExt};
 use reqwest::RequestBuilder;
 use reqwest_eventsource::{Error as EventSourceError, Event, RequestBuilderExt};
-use serde_json::Value;
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::UnboundedSender;
 
 pub struct SseHandler {
     sender: UnboundedSender<SseEvent>,
     abort_signal: AbortSignal,
     buffer: String,
+    reasoning: String,
     tool_calls: Vec<ToolCall>,
+    usage: StreamUsage,
+    recorded_frames: Option<Vec<CassetteFrame>>,
 }
 
 impl SseHandler {
@@ -21,7 +24,10 @@ impl SseHandler {
             sender,
             abort_signal,
             buffer: String::new(),
+            reasoning: String::new(),
             tool_calls: Vec::new(),
+            usage: StreamUsage::default(),
+            recorded_frames: None,
         }
     }
 
@@ -31,6 +37,9 @@ impl SseHandler {
             return Ok(());
         }
         self.buffer.push_str(text);
+        if let Some(frames) = self.recorded_frames.as_mut() {
+            frames.push(CassetteFrame::Text(text.to_string()));
+        }
         let ret = self
             .sender
             .send(SseEvent::Text(text.to_string()))
@@ -44,6 +53,27 @@ impl SseHandler {
         Ok(())
     }
 
+    pub fn reasoning(&mut self, text: &str) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.reasoning.push_str(text);
+        if let Some(frames) = self.recorded_frames.as_mut() {
+            frames.push(CassetteFrame::Reasoning(text.to_string()));
+        }
+        let ret = self
+            .sender
+            .send(SseEvent::Reasoning(text.to_string()))
+            .with_context(|| "Failed to send SseEvent:Reasoning");
+        if let Err(err) = ret {
+            if self.abort_signal.aborted() {
+                return Ok(());
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
     pub fn done(&mut self) {
         // debug!("HandleDone");
         let ret = self.sender.send(SseEvent::Done);
@@ -57,18 +87,64 @@ impl SseHandler {
 
     pub fn tool_call(&mut self, call: ToolCall) -> Result<()> {
         // debug!("HandleCall: {:?}", call);
+        if let Some(frames) = self.recorded_frames.as_mut() {
+            frames.push(CassetteFrame::ToolCall(call.clone()));
+        }
         self.tool_calls.push(call);
         Ok(())
     }
 
+    /// Keeps the token counts the provider reported, later events overriding earlier ones.
+    pub fn update_usage(&mut self, usage: StreamUsage) {
+        if let Some(frames) = self.recorded_frames.as_mut() {
+            frames.push(CassetteFrame::Usage(usage.clone()));
+        }
+        let StreamUsage {
+            input_tokens,
+            output_tokens,
+            cache_creation_input_tokens,
+            cache_read_input_tokens,
+        } = usage;
+        self.usage.input_tokens = input_tokens.or(self.usage.input_tokens);
+        self.usage.output_tokens = output_tokens.or(self.usage.output_tokens);
+        self.usage.cache_creation_input_tokens =
+            cache_creation_input_tokens.or(self.usage.cache_creation_input_tokens);
+        self.usage.cache_read_input_tokens =
+            cache_read_input_tokens.or(self.usage.cache_read_input_tokens);
+    }
+
     pub fn abort(&self) -> AbortSignal {
         self.abort_signal.clone()
     }
 
+    pub fn buffer(&self) -> &str {
+        &self.buffer
+    }
+
+    pub fn reasoning_buffer(&self) -> &str {
+        &self.reasoning
+    }
+
     pub fn tool_calls(&self) -> &[ToolCall] {
         &self.tool_calls
     }
 
+    pub fn usage(&self) -> &StreamUsage {
+        &self.usage
+    }
+
+    pub fn is_untouched(&self) -> bool {
+        self.buffer.is_empty() && self.reasoning.is_empty() && self.tool_calls.is_empty()
+    }
+
+    pub fn start_recording(&mut self) {
+        self.recorded_frames = Some(vec![]);
+    }
+
+    pub fn take_recorded_frames(&mut self) -> Vec<CassetteFrame> {
+        self.recorded_frames.take().unwrap_or_default()
+    }
+
     pub fn take(self) -> (String, Vec<ToolCall>) {
         let Self {
             buffer, tool_calls, ..
@@ -77,9 +153,23 @@ impl SseHandler {
     }
 }
 
+/// Token counts a provider reports while streaming, usually in the first or last event.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StreamUsage {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u64>,
+}
+
 #[derive(Debug)]
 pub enum SseEvent {
     Text(String),
+    Reasoning(String),
     Done,
 }
 
@@ -94,6 +184,7 @@ pub async fn sse_stream<F>(builder: RequestBuilder, mut handle: F) -> Result<()>
 where
     F: FnMut(SseMmessage) -> Result<bool>,
 {
+    throttle_request().await;
     let mut es = builder.eventsource()?;
     while let Some(event) = es.next().await {
         match event {
@@ -110,18 +201,8 @@ where
             Err(err) => {
                 match err {
                     EventSourceError::StreamEnded => {}
-                    EventSourceError::InvalidStatusCode(status, res) => {
-                        let text = res.text().await?;
-                        let data: Value = match text.parse() {
-                            Ok(data) => data,
-                            Err(_) => {
-                                bail!(
-                                    "Invalid response data: {text} (status: {})",
-                                    status.as_u16()
-                                );
-                            }
-                        };
-                        catch_error(&data, status.as_u16())?;
+                    EventSourceError::InvalidStatusCode(_, res) => {
+                        return Err(catch_response_error(res).await);
                     }
                     EventSourceError::InvalidContentType(header_value, res) => {
                         let text = res.text().await?;
@@ -212,10 +293,8 @@ impl JsonStreamParser {
                     }
                     self.balances.push(ch);
                 }
-                '[' => {
-                    if self.start.is_some() {
-                        self.balances.push(ch);
-                    }
+                '[' if self.start.is_some() => {
+                    self.balances.push(ch);
                 }
                 '}' => {
                     self.balances.pop();
@@ -274,6 +353,32 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_sse_handler_usage() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut handler = SseHandler::new(tx, crate::utils::create_abort_signal());
+        handler.start_recording();
+        handler.update_usage(StreamUsage {
+            input_tokens: Some(10),
+            cache_read_input_tokens: Some(2000),
+            ..Default::default()
+        });
+        handler.update_usage(StreamUsage {
+            output_tokens: Some(42),
+            ..Default::default()
+        });
+        assert_eq!(
+            handler.usage(),
+            &StreamUsage {
+                input_tokens: Some(10),
+                output_tokens: Some(42),
+                cache_creation_input_tokens: None,
+                cache_read_input_tokens: Some(2000),
+            }
+        );
+        assert_eq!(handler.take_recorded_frames().len(), 2);
+    }
+
     #[tokio::test]
     async fn test_json_stream_ndjson() {
         let data = r#"{"key": "value"}
@@ -1,8 +1,9 @@
 use super::*;
 
 use crate::{
-    client::Model,
-    function::{run_llm_function, Functions},
+    client::{Model, Reasoning},
+    function::{run_llm_function, Functions, SandboxConfig, ToolPolicy},
+    mcp::{McpClients, McpServerConfig},
 };
 
 use anyhow::{Context, Result};
@@ -25,6 +26,7 @@ pub struct Agent {
     shared_dynamic_instructions: Option<String>,
     session_dynamic_instructions: Option<String>,
     functions: Functions,
+    mcp_clients: McpClients,
     rag: Option<Arc<Rag>>,
     model: Model,
 }
@@ -49,14 +51,21 @@ impl Agent {
             AgentConfig::new(&config.read())
         };
         let mut definition = AgentDefinition::load(&definition_file_path)?;
-        let functions = if functions_file_path.exists() {
+        let mut functions = if functions_file_path.exists() {
             Functions::init(&functions_file_path)?
         } else {
             Functions::default()
         };
+        let can_start_mcp_servers = config.read().can_start_mcp_servers();
+        let mcp_clients = if can_start_mcp_servers {
+            McpClients::start(&definition.mcp_servers).await
+        } else {
+            McpClients::default()
+        };
+        functions.extend(mcp_clients.declarations());
         definition.replace_tools_placeholder(&functions);
 
-        agent_config.load_envs(&definition.name);
+        agent_config.load_envs(&definition.name)?;
 
         let model = {
             let config = config.read();
@@ -69,6 +78,9 @@ impl Agent {
                     if agent_config.top_p.is_none() {
                         agent_config.top_p = config.top_p;
                     }
+                    if agent_config.reasoning.is_none() {
+                        agent_config.reasoning = config.reasoning;
+                    }
                     config.current_model().clone()
                 }
             }
@@ -113,6 +125,7 @@ impl Agent {
             shared_dynamic_instructions: None,
             session_dynamic_instructions: None,
             functions,
+            mcp_clients,
             rag,
             model,
         })
@@ -219,6 +232,18 @@ impl Agent {
         &self.functions
     }
 
+    pub fn tool_policies(&self) -> &IndexMap<String, ToolPolicy> {
+        &self.definition.tool_policies
+    }
+
+    pub fn tool_sandboxes(&self) -> &IndexMap<String, SandboxConfig> {
+        &self.definition.tool_sandboxes
+    }
+
+    pub fn mcp_clients(&self) -> &McpClients {
+        &self.mcp_clients
+    }
+
     pub fn rag(&self) -> Option<Arc<Rag>> {
         self.rag.clone()
     }
@@ -241,6 +266,15 @@ impl Agent {
         output
     }
 
+    pub fn fallback_models(&self) -> &[String] {
+        &self.config.fallback_models
+    }
+
+    /// Limits the agent's spending of the current month.
+    pub fn budget(&self) -> Option<Budget> {
+        self.config.budget
+    }
+
     pub fn agent_prelude(&self) -> Option<&str> {
         self.config.agent_prelude.as_deref()
     }
@@ -316,6 +350,9 @@ impl Agent {
             self.name().to_string(),
             vec!["_instructions".into(), "{}".into()],
             self.variable_envs(),
+            &Config::functions_dir(),
+            false,
+            None,
         )?;
         match value {
             Some(v) => Ok(v),
@@ -344,6 +381,10 @@ impl RoleLike for Agent {
         self.config.top_p
     }
 
+    fn reasoning(&self) -> Option<Reasoning> {
+        self.config.reasoning
+    }
+
     fn use_tools(&self) -> Option<String> {
         self.config.use_tools.clone()
     }
@@ -361,6 +402,10 @@ impl RoleLike for Agent {
         self.config.top_p = value;
     }
 
+    fn set_reasoning(&mut self, value: Option<Reasoning>) {
+        self.config.reasoning = value;
+    }
+
     fn set_use_tools(&mut self, value: Option<String>) {
         self.config.use_tools = value;
     }
@@ -375,9 +420,15 @@ pub struct AgentConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<Reasoning>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_models: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub use_tools: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub agent_prelude: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget: Option<Budget>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub instructions: Option<String>,
     #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
@@ -401,32 +452,42 @@ impl AgentConfig {
         Ok(config)
     }
 
-    fn load_envs(&mut self, name: &str) {
+    fn load_envs(&mut self, name: &str) -> Result<()> {
         let with_prefix = |v: &str| normalize_env_name(&format!("{name}_{v}"));
 
-        if let Some(v) = read_env_value::<String>(&with_prefix("model")) {
+        if let Some(v) = read_env_value::<String>(&with_prefix("model"))? {
             self.model_id = v;
         }
-        if let Some(v) = read_env_value::<f64>(&with_prefix("temperature")) {
+        if let Some(v) = read_env_value::<f64>(&with_prefix("temperature"))? {
             self.temperature = v;
         }
-        if let Some(v) = read_env_value::<f64>(&with_prefix("top_p")) {
+        if let Some(v) = read_env_value::<f64>(&with_prefix("top_p"))? {
             self.top_p = v;
         }
-        if let Some(v) = read_env_value::<String>(&with_prefix("use_tools")) {
+        if let Some(v) = read_env_value::<Reasoning>(&with_prefix("reasoning"))? {
+            self.reasoning = v;
+        }
+        if let Some(v) = read_env(&with_prefix("fallback_models"))? {
+            self.fallback_models = split_model_ids(&v);
+        }
+        if let Some(v) = read_env_value::<String>(&with_prefix("use_tools"))? {
             self.use_tools = v;
         }
-        if let Some(v) = read_env_value::<String>(&with_prefix("agent_prelude")) {
+        if let Some(v) = read_env_value::<String>(&with_prefix("agent_prelude"))? {
             self.agent_prelude = v;
         }
-        if let Some(v) = read_env_value::<String>(&with_prefix("instructions")) {
+        if let Some(v) = read_env_value::<Budget>(&with_prefix("budget"))? {
+            self.budget = v;
+        }
+        if let Some(v) = read_env_value::<String>(&with_prefix("instructions"))? {
             self.instructions = v;
         }
-        if let Ok(v) = env::var(with_prefix("variables")) {
+        if let Some(v) = read_env(&with_prefix("variables"))? {
             if let Ok(v) = serde_json::from_str(&v) {
                 self.variables = v;
             }
         }
+        Ok(())
     }
 }
 
@@ -447,6 +508,12 @@ pub struct AgentDefinition {
     pub conversation_starters: Vec<String>,
     #[serde(default)]
     pub documents: Vec<String>,
+    #[serde(default)]
+    pub mcp_servers: Vec<McpServerConfig>,
+    #[serde(default)]
+    pub tool_policies: IndexMap<String, ToolPolicy>,
+    #[serde(default)]
+    pub tool_sandboxes: IndexMap<String, SandboxConfig>,
 }
 
 impl AgentDefinition {
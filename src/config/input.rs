@@ -9,6 +9,7 @@ use crate::utils::{base64_encode, is_loader_protocol, sha256, AbortSignal};
 
 use anyhow::{bail, Context, Result};
 use indexmap::IndexSet;
+use serde_json::Value;
 use std::{collections::HashMap, fs::File, io::Read};
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
@@ -31,6 +32,10 @@ pub struct Input {
     rag_name: Option<String>,
     with_session: bool,
     with_agent: bool,
+    usage: Option<(u64, u64)>,
+    cache_usage: Option<(u64, u64)>,
+    reasoning: Option<String>,
+    schema_feedback: Option<(String, String)>,
 }
 
 impl Input {
@@ -51,6 +56,10 @@ impl Input {
             rag_name: None,
             with_session,
             with_agent,
+            usage: None,
+            cache_usage: None,
+            reasoning: None,
+            schema_feedback: None,
         }
     }
 
@@ -123,6 +132,10 @@ impl Input {
             rag_name: None,
             with_session,
             with_agent,
+            usage: None,
+            cache_usage: None,
+            reasoning: None,
+            schema_feedback: None,
         })
     }
 
@@ -223,13 +236,12 @@ impl Input {
         self
     }
 
-    pub async fn create_client(&self) -> Result<Box<dyn Client>> {
-        let authenticator = self.config.read().get_authenticator().await?;
-        init_client(&self.config, Some(self.role().model().clone()), authenticator)
+    pub fn create_client(&self) -> Result<Box<dyn Client>> {
+        init_client(&self.config, Some(self.role().model().clone()))
     }
 
     pub async fn fetch_chat_text(&self) -> Result<String> {
-        let client = self.create_client().await?;
+        let client = self.create_client()?;
         let text = client.chat_completions(self.clone()).await?.text;
         let text = strip_think_tag(&text).to_string();
         Ok(text)
@@ -245,12 +257,24 @@ impl Input {
         model.guard_max_input_tokens(&messages)?;
         let (temperature, top_p) = (self.role().temperature(), self.role().top_p());
         let functions = self.config.read().select_functions(self.role());
+        let reasoning = match self.role().reasoning() {
+            Some(v) if !model.supports_reasoning() => {
+                debug!(
+                    "Skip reasoning '{v}', the model '{}' does not support it",
+                    model.id()
+                );
+                None
+            }
+            v => v,
+        };
         Ok(ChatCompletionsData {
             messages,
             temperature,
             top_p,
             functions,
             stream,
+            schema: self.role().schema().cloned(),
+            reasoning,
         })
     }
 
@@ -260,6 +284,18 @@ impl Input {
         } else {
             self.role().build_messages(self)
         };
+        if let Some((output, error)) = &self.schema_feedback {
+            messages.push(Message::new(
+                MessageRole::Assistant,
+                MessageContent::Text(output.clone()),
+            ));
+            messages.push(Message::new(
+                MessageRole::User,
+                MessageContent::Text(format!(
+                    "The response does not match the JSON schema: {error}\nReply with the corrected JSON only."
+                )),
+            ));
+        }
         if let Some(tool_calls) = &self.tool_calls {
             messages.push(Message::new(
                 MessageRole::Assistant,
@@ -280,6 +316,77 @@ impl Input {
         &self.role
     }
 
+    pub fn set_model(&mut self, model: Model) {
+        self.role.set_model(model);
+    }
+
+    pub fn set_schema(&mut self, schema: Value) {
+        self.role.set_schema(Some(schema));
+    }
+
+    /// Feeds the rejected output and its validation error back on the next request.
+    pub fn set_schema_feedback(&mut self, output: &str, error: &str) {
+        self.schema_feedback = Some((output.to_string(), error.to_string()));
+    }
+
+    /// Models to try in order when the current one fails, preferring the role's list,
+    /// then the agent's, then the global one.
+    pub fn fallback_models(&self) -> Vec<String> {
+        let config = self.config.read();
+        let agent_fallback_models = config
+            .agent
+            .as_ref()
+            .filter(|_| self.with_agent)
+            .map(|v| v.fallback_models());
+        select_fallback_models(
+            self.role.fallback_models(),
+            agent_fallback_models,
+            &config.fallback_models,
+        )
+    }
+
+    /// Checks the spending budgets against the estimated cost of sending the input.
+    pub fn guard_budgets(&self) -> Result<()> {
+        let model = self.role().model();
+        let input_tokens = model.total_tokens(&self.build_messages()?) as u64;
+        // Assume the worst case, a reply as long as `max_output_tokens` allows
+        let output_tokens = model.max_output_tokens().unwrap_or_default().max(0) as u64;
+        let cost = calculate_cost(model, input_tokens, output_tokens).unwrap_or_default();
+        self.config.read().guard_budgets(Some(self), cost)
+    }
+
+    /// Input and output tokens of the last completion.
+    pub fn usage(&self) -> Option<(u64, u64)> {
+        self.usage
+    }
+
+    pub fn set_usage(&mut self, input_tokens: u64, output_tokens: u64) {
+        self.usage = Some((input_tokens, output_tokens));
+    }
+
+    /// Cache creation and cache read input tokens of the last completion.
+    pub fn cache_usage(&self) -> Option<(u64, u64)> {
+        self.cache_usage
+    }
+
+    pub fn set_cache_usage(&mut self, creation_tokens: Option<u64>, read_tokens: Option<u64>) {
+        self.cache_usage = match (creation_tokens, read_tokens) {
+            (None, None) => None,
+            (creation_tokens, read_tokens) => Some((
+                creation_tokens.unwrap_or_default(),
+                read_tokens.unwrap_or_default(),
+            )),
+        };
+    }
+
+    pub fn reasoning(&self) -> Option<&str> {
+        self.reasoning.as_deref()
+    }
+
+    pub fn set_reasoning(&mut self, reasoning: Option<String>) {
+        self.reasoning = reasoning.filter(|v| !v.is_empty());
+    }
+
     pub fn session<'a>(&self, session: &'a Option<Session>) -> Option<&'a Session> {
         if self.with_session {
             session.as_ref()
@@ -379,6 +486,20 @@ impl Input {
     }
 }
 
+/// Returns the first non-empty list among the role's, the agent's and the global fallback models.
+fn select_fallback_models(
+    role: &[String],
+    agent: Option<&[String]>,
+    global: &[String],
+) -> Vec<String> {
+    [Some(role), agent, Some(global)]
+        .into_iter()
+        .flatten()
+        .find(|v| !v.is_empty())
+        .unwrap_or_default()
+        .to_vec()
+}
+
 fn resolve_role(config: &Config, role: Option<Role>) -> (Role, bool, bool) {
     match role {
         Some(v) => (v, false, false),
@@ -542,4 +663,22 @@ fn read_media_to_data_url(image_path: &str) -> Result<String> {
     let data_url = format!("data:{mime_type};base64,{encoded_image}");
 
     Ok(data_url)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_fallback_models() {
+        let ids = |v: &[&str]| v.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        let role = ids(&["openai:gpt-4o-mini"]);
+        let agent = ids(&["claude:claude-3-5-haiku-latest"]);
+        let global = ids(&["gemini:gemini-2.0-flash"]);
+        assert_eq!(select_fallback_models(&role, Some(&agent), &global), role);
+        assert_eq!(select_fallback_models(&[], Some(&agent), &global), agent);
+        assert_eq!(select_fallback_models(&[], Some(&[]), &global), global);
+        assert_eq!(select_fallback_models(&[], None, &global), global);
+        assert!(select_fallback_models(&[], None, &[]).is_empty());
+    }
+}
@@ -1,26 +1,36 @@
 mod agent;
 mod input;
 mod role;
+mod secret;
 mod session;
+mod usage;
 
-pub use self::agent::{complete_agent_variables, list_agents, Agent, AgentVariables};
+pub use self::agent::{
+    complete_agent_variables, list_agents, Agent, AgentDefinition, AgentVariables,
+};
 pub use self::input::Input;
 pub use self::role::{
     Role, RoleLike, CODE_ROLE, CREATE_TITLE_ROLE, EXPLAIN_SHELL_ROLE, SHELL_ROLE,
 };
+pub use self::secret::{mask_secrets, resolve_field, resolve_ref, resolve_secret};
 use self::session::Session;
+pub use self::usage::{
+    calculate_cost, load_usage, render_usage_report, Budget, Spending, UsageGroup, UsageRecord,
+    USAGE_GROUPS,
+};
 
 use crate::client::{
-    create_client_config, list_client_types, list_models, ClientConfig, MessageContentToolCalls,
-    Model, ModelType, ProviderModels, OPENAI_COMPATIBLE_PROVIDERS,
+    create_client_config, list_client_types, list_models, CassetteMode, ClientConfig,
+    MessageContentToolCalls, Model, ModelType, ProviderModels, Reasoning,
+    OPENAI_COMPATIBLE_PROVIDERS,
 };
-use crate::function::{FunctionDeclaration, Functions, ToolResult};
+use crate::function::{FunctionDeclaration, Functions, SandboxConfig, ToolPolicy, ToolResult};
+use crate::mcp::{McpClients, McpServerConfig};
 use crate::rag::Rag;
-use crate::render::{MarkdownRender, RenderOptions};
+use crate::render::{MarkdownRender, ReasoningDisplay, RenderOptions};
 use crate::repl::{run_repl_command, split_args_text};
 use crate::utils::*;
 
-use crate::auth::Authenticator;
 use anyhow::{anyhow, bail, Context, Result};
 use indexmap::IndexMap;
 use inquire::{list_option::ListOption, validator::Validation, Confirm, MultiSelect, Select, Text};
@@ -55,11 +65,12 @@ const ROLES_DIR_NAME: &str = "roles";
 const MACROS_DIR_NAME: &str = "macros";
 const ENV_FILE_NAME: &str = ".env";
 const MESSAGES_FILE_NAME: &str = "messages.md";
+const USAGE_FILE_NAME: &str = "usage.jsonl";
 const SESSIONS_DIR_NAME: &str = "sessions";
 const RAGS_DIR_NAME: &str = "rags";
 const FUNCTIONS_DIR_NAME: &str = "functions";
 const FUNCTIONS_FILE_NAME: &str = "functions.json";
-const FUNCTIONS_BIN_DIR_NAME: &str = "bin";
+pub const FUNCTIONS_BIN_DIR_NAME: &str = "bin";
 const AGENTS_DIR_NAME: &str = "agents";
 
 const CLIENTS_FIELD: &str = "clients";
@@ -93,7 +104,7 @@ __INPUT__
 </user_query>"#;
 
 const LEFT_PROMPT: &str = "{color.green}{?session {?agent {agent}>}{session}{?role /}}{!session {?agent {agent}>}}{role}{?rag @{rag}}{color.cyan}{?session )}{!session >}{color.reset} ";
-const RIGHT_PROMPT: &str = "{color.purple}{?session {?consume_tokens {consume_tokens}({consume_percent}%)}{!consume_tokens {consume_tokens}}}{color.reset}";
+const RIGHT_PROMPT: &str = "{color.purple}{?session {?session_cost ${session_cost} }{?consume_tokens {consume_tokens}({consume_percent}%)}{!consume_tokens {consume_tokens}}}{color.reset}";
 
 static EDITOR: OnceLock<Option<String>> = OnceLock::new();
 
@@ -105,6 +116,8 @@ pub struct Config {
     pub model_id: String,
     pub temperature: Option<f64>,
     pub top_p: Option<f64>,
+    pub reasoning: Option<Reasoning>,
+    pub fallback_models: Vec<String>,
 
     pub dry_run: bool,
     pub stream: bool,
@@ -117,6 +130,10 @@ pub struct Config {
     pub function_calling: bool,
     pub mapping_tools: IndexMap<String, String>,
     pub use_tools: Option<String>,
+    pub tool_call_concurrency: usize,
+    pub tool_policies: IndexMap<String, ToolPolicy>,
+    pub tool_sandboxes: IndexMap<String, SandboxConfig>,
+    pub mcp_servers: Vec<McpServerConfig>,
 
     pub repl_prelude: Option<String>,
     pub cmd_prelude: Option<String>,
@@ -127,10 +144,14 @@ pub struct Config {
     pub summarize_prompt: Option<String>,
     pub summary_prompt: Option<String>,
 
+    pub daily_budget: Option<Budget>,
+    pub monthly_budget: Option<Budget>,
+
     pub rag_embedding_model: Option<String>,
     pub rag_reranker_model: Option<String>,
     pub rag_top_k: usize,
     pub rag_chunk_size: Option<usize>,
+    pub rag_chunk_tokens: Option<usize>,
     pub rag_chunk_overlap: Option<usize>,
     pub rag_template: Option<String>,
 
@@ -139,6 +160,7 @@ pub struct Config {
 
     pub highlight: bool,
     pub theme: Option<String>,
+    pub reasoning_display: ReasoningDisplay,
     pub left_prompt: Option<String>,
     pub right_prompt: Option<String>,
 
@@ -146,6 +168,8 @@ pub struct Config {
     pub user_agent: Option<String>,
     pub save_shell_history: bool,
     pub sync_models_url: Option<String>,
+    pub cassette_mode: Option<CassetteMode>,
+    pub cassette_file: Option<String>,
 
     pub clients: Vec<ClientConfig>,
 
@@ -154,6 +178,8 @@ pub struct Config {
     #[serde(skip)]
     pub info_flag: bool,
     #[serde(skip)]
+    pub count_tokens_flag: bool,
+    #[serde(skip)]
     pub agent_variables: Option<AgentVariables>,
 
     #[serde(skip)]
@@ -161,6 +187,11 @@ pub struct Config {
     #[serde(skip)]
     pub functions: Functions,
     #[serde(skip)]
+    pub mcp_clients: McpClients,
+    /// Tools the user allowed to run for the rest of the process
+    #[serde(skip)]
+    pub allowed_tools: HashSet<String>,
+    #[serde(skip)]
     pub working_mode: WorkingMode,
     #[serde(skip)]
     pub last_message: Option<LastMessage>,
@@ -181,6 +212,8 @@ impl Default for Config {
             model_id: Default::default(),
             temperature: None,
             top_p: None,
+            reasoning: None,
+            fallback_models: vec![],
 
             dry_run: false,
             stream: true,
@@ -193,6 +226,10 @@ impl Default for Config {
             function_calling: true,
             mapping_tools: Default::default(),
             use_tools: None,
+            tool_call_concurrency: 4,
+            tool_policies: Default::default(),
+            tool_sandboxes: Default::default(),
+            mcp_servers: vec![],
 
             repl_prelude: None,
             cmd_prelude: None,
@@ -203,10 +240,14 @@ impl Default for Config {
             summarize_prompt: None,
             summary_prompt: None,
 
+            daily_budget: None,
+            monthly_budget: None,
+
             rag_embedding_model: None,
             rag_reranker_model: None,
             rag_top_k: 5,
             rag_chunk_size: None,
+            rag_chunk_tokens: None,
             rag_chunk_overlap: None,
             rag_template: None,
 
@@ -214,6 +255,7 @@ impl Default for Config {
 
             highlight: true,
             theme: None,
+            reasoning_display: Default::default(),
             left_prompt: None,
             right_prompt: None,
 
@@ -221,15 +263,20 @@ impl Default for Config {
             user_agent: None,
             save_shell_history: true,
             sync_models_url: None,
+            cassette_mode: None,
+            cassette_file: None,
 
             clients: vec![],
 
             macro_flag: false,
             info_flag: false,
+            count_tokens_flag: false,
             agent_variables: None,
 
             model: Default::default(),
             functions: Default::default(),
+            mcp_clients: Default::default(),
+            allowed_tools: Default::default(),
             working_mode: WorkingMode::Cmd,
             last_message: None,
 
@@ -267,7 +314,7 @@ impl Config {
         config.info_flag = info_flag;
 
         let setup = |config: &mut Self| -> Result<()> {
-            config.load_envs();
+            config.load_envs()?;
 
             if let Some(wrap) = config.wrap.clone() {
                 config.set_wrap(&wrap)?;
@@ -348,6 +395,13 @@ impl Config {
         }
     }
 
+    pub fn usage_file() -> PathBuf {
+        match env::var(get_env_name("usage_file")) {
+            Ok(value) => PathBuf::from(value),
+            Err(_) => Self::local_path(USAGE_FILE_NAME),
+        }
+    }
+
     pub fn sessions_dir(&self) -> PathBuf {
         match &self.agent {
             None => match env::var(get_env_name("sessions_dir")) {
@@ -376,10 +430,6 @@ impl Config {
         Self::functions_dir().join(FUNCTIONS_FILE_NAME)
     }
 
-    pub fn functions_bin_dir() -> PathBuf {
-        Self::functions_dir().join(FUNCTIONS_BIN_DIR_NAME)
-    }
-
     pub fn session_file(&self, name: &str) -> PathBuf {
         match name.split_once("/") {
             Some((dir, name)) => self.sessions_dir().join(dir).join(format!("{name}.yaml")),
@@ -537,14 +587,27 @@ impl Config {
                 &self.model,
                 self.temperature,
                 self.top_p,
+                self.reasoning,
                 self.use_tools.clone(),
             );
             role
         }
     }
 
+    pub fn usage_report(group: Option<&str>) -> Result<String> {
+        let group = match group {
+            Some(v) => v.parse()?,
+            None => UsageGroup::Day,
+        };
+        let records = load_usage(&Self::usage_file())?;
+        if records.is_empty() {
+            bail!("No usage recorded yet");
+        }
+        Ok(render_usage_report(&records, group))
+    }
+
     pub fn info(&self) -> Result<String> {
-        if let Some(agent) = &self.agent {
+        let output = if let Some(agent) = &self.agent {
             let output = agent.export()?;
             if let Some(session) = &self.session {
                 let session = session
@@ -553,19 +616,20 @@ impl Config {
                     .map(|v| format!("  {v}"))
                     .collect::<Vec<_>>()
                     .join("\n");
-                Ok(format!("{output}session:\n{session}"))
+                format!("{output}session:\n{session}")
             } else {
-                Ok(output)
+                output
             }
         } else if let Some(session) = &self.session {
-            session.export()
+            session.export()?
         } else if let Some(role) = &self.role {
-            Ok(role.export())
+            role.export()
         } else if let Some(rag) = &self.rag {
-            rag.export()
+            rag.export()?
         } else {
-            self.sysinfo()
-        }
+            self.sysinfo()?
+        };
+        Ok(mask_secrets(&output).into_owned())
     }
 
     pub fn sysinfo(&self) -> Result<String> {
@@ -583,6 +647,7 @@ impl Config {
             ("model", role.model().id()),
             ("temperature", format_option_value(&role.temperature())),
             ("top_p", format_option_value(&role.top_p())),
+            ("reasoning", format_option_value(&role.reasoning())),
             ("use_tools", format_option_value(&role.use_tools())),
             (
                 "max_output_tokens",
@@ -593,6 +658,8 @@ impl Config {
             ),
             ("save_session", format_option_value(&self.save_session)),
             ("compress_threshold", self.compress_threshold.to_string()),
+            ("daily_budget", format_option_value(&self.daily_budget)),
+            ("monthly_budget", format_option_value(&self.monthly_budget)),
             (
                 "rag_reranker_model",
                 format_option_value(&rag_reranker_model),
@@ -607,6 +674,7 @@ impl Config {
             ("wrap_code", self.wrap_code.to_string()),
             ("highlight", self.highlight.to_string()),
             ("theme", format_option_value(&self.theme)),
+            ("reasoning_display", self.reasoning_display.to_string()),
             ("config_file", display_path(&Self::config_file())),
             ("env_file", display_path(&Self::env_file())),
             ("roles_dir", display_path(&Self::roles_dir())),
@@ -615,6 +683,7 @@ impl Config {
             ("macros_dir", display_path(&Self::macros_dir())),
             ("functions_dir", display_path(&Self::functions_dir())),
             ("messages_file", display_path(&self.messages_file())),
+            ("usage_file", display_path(&Self::usage_file())),
         ];
         if let Ok((_, Some(log_path))) = Self::log_config(self.working_mode.is_serve()) {
             items.push(("log_path", display_path(&log_path)));
@@ -624,7 +693,7 @@ impl Config {
             .map(|(name, value)| format!("{name:<24}{value}\n"))
             .collect::<Vec<String>>()
             .join("");
-        Ok(output)
+        Ok(mask_secrets(&output).into_owned())
     }
 
     pub fn update(config: &GlobalConfig, data: &str) -> Result<()> {
@@ -643,6 +712,10 @@ impl Config {
                 let value = parse_value(value)?;
                 config.write().set_top_p(value);
             }
+            "reasoning" => {
+                let value = parse_value(value)?;
+                config.write().set_reasoning(value);
+            }
             "use_tools" => {
                 let value = parse_value(value)?;
                 config.write().set_use_tools(value);
@@ -659,6 +732,10 @@ impl Config {
                 let value = parse_value(value)?;
                 config.write().set_compress_threshold(value);
             }
+            "budget" => {
+                let value = parse_value(value)?;
+                config.write().set_budget(value)?;
+            }
             "rag_reranker_model" => {
                 let value = parse_value(value)?;
                 Self::set_rag_reranker_model(config, value)?;
@@ -690,6 +767,10 @@ impl Config {
                 let value = value.parse().with_context(|| "Invalid value")?;
                 config.write().highlight = value;
             }
+            "reasoning_display" => {
+                let value = value.parse()?;
+                config.write().reasoning_display = value;
+            }
             _ => bail!("Unknown key '{key}'"),
         }
         Ok(())
@@ -778,6 +859,13 @@ impl Config {
         }
     }
 
+    pub fn set_reasoning(&mut self, value: Option<Reasoning>) {
+        match self.role_like_mut() {
+            Some(role_like) => role_like.set_reasoning(value),
+            None => self.reasoning = value,
+        }
+    }
+
     pub fn set_use_tools(&mut self, value: Option<String>) {
         match self.role_like_mut() {
             Some(role_like) => role_like.set_use_tools(value),
@@ -801,6 +889,55 @@ impl Config {
         }
     }
 
+    pub fn set_budget(&mut self, value: Option<Budget>) -> Result<()> {
+        match self.session.as_mut() {
+            Some(session) => session.set_budget(value),
+            None => bail!("No session"),
+        }
+        Ok(())
+    }
+
+    /// Refuses a request that would exceed a hard budget and warns past a soft one.
+    pub fn guard_budgets(&self, input: Option<&Input>, cost: f64) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        let mut checks = vec![];
+        if let Some(session) = input.and_then(|v| v.session(&self.session)) {
+            if let Some(budget) = session.budget() {
+                checks.push((
+                    format!("session '{}'", session.name()),
+                    budget,
+                    session.cost(),
+                ));
+            }
+        }
+        let agent = self
+            .agent
+            .as_ref()
+            .filter(|_| input.map(|v| v.with_agent()).unwrap_or_default())
+            .and_then(|v| v.budget().map(|budget| (v.name(), budget)));
+        if agent.is_some() || self.daily_budget.is_some() || self.monthly_budget.is_some() {
+            let spending = Spending::current(&Self::usage_file())?;
+            if let Some((name, budget)) = agent {
+                let spent = spending.agent(name);
+                checks.push((format!("agent '{name}' this month"), budget, spent));
+            }
+            if let Some(budget) = self.daily_budget {
+                checks.push(("today".to_string(), budget, spending.today));
+            }
+            if let Some(budget) = self.monthly_budget {
+                checks.push(("this month".to_string(), budget, spending.this_month));
+            }
+        }
+        for (scope, budget, spent) in checks {
+            if let Some(warning) = budget.check(&scope, spent, cost)? {
+                eprintln!("{}", warning_text(&format!("⚠ {warning}")));
+            }
+        }
+        Ok(())
+    }
+
     pub fn set_rag_reranker_model(config: &GlobalConfig, value: Option<String>) -> Result<()> {
         if let Some(id) = &value {
             Model::retrieve_model(&config.read(), id, ModelType::Reranker)?;
@@ -942,6 +1079,9 @@ impl Config {
                 if role.top_p().is_none() {
                     role.set_top_p(self.top_p);
                 }
+                if role.reasoning().is_none() {
+                    role.set_reasoning(self.reasoning);
+                }
             }
         }
         Ok(role)
@@ -1650,6 +1790,32 @@ impl Config {
         Ok(())
     }
 
+    /// The policies of the agent come first, then those of the config, `*` matching any tool.
+    pub fn tool_policy(&self, name: &str) -> ToolPolicy {
+        let mut policies = vec![];
+        if let Some(agent) = &self.agent {
+            policies.push(agent.tool_policies());
+        }
+        policies.push(&self.tool_policies);
+        [name, "*"]
+            .iter()
+            .find_map(|key| policies.iter().find_map(|v| v.get(*key)))
+            .copied()
+            .unwrap_or(ToolPolicy::Allow)
+    }
+
+    /// Resolved like `tool_policy`, tools without a sandbox run unrestricted.
+    pub fn tool_sandbox(&self, name: &str) -> Option<&SandboxConfig> {
+        let mut sandboxes = vec![];
+        if let Some(agent) = &self.agent {
+            sandboxes.push(agent.tool_sandboxes());
+        }
+        sandboxes.push(&self.tool_sandboxes);
+        [name, "*"]
+            .iter()
+            .find_map(|key| sandboxes.iter().find_map(|v| v.get(*key)))
+    }
+
     pub fn select_functions(&self, role: &Role) -> Option<Vec<FunctionDeclaration>> {
         let mut functions = vec![];
         if self.function_calling {
@@ -1767,6 +1933,7 @@ impl Config {
                 ".rag" => map_completion_values(Self::list_rags()),
                 ".agent" => map_completion_values(list_agents()),
                 ".macro" => map_completion_values(Self::list_macros()),
+                ".usage" => map_completion_values(USAGE_GROUPS.to_vec()),
                 ".starter" => match &self.agent {
                     Some(agent) => agent
                         .conversation_staters()
@@ -1780,9 +1947,11 @@ impl Config {
                     let mut values = vec![
                         "temperature",
                         "top_p",
+                        "reasoning",
                         "use_tools",
                         "save_session",
                         "compress_threshold",
+                        "budget",
                         "rag_reranker_model",
                         "rag_top_k",
                         "max_output_tokens",
@@ -1791,6 +1960,7 @@ impl Config {
                         "stream",
                         "save",
                         "highlight",
+                        "reasoning_display",
                     ];
                     values.sort_unstable();
                     values
@@ -1809,6 +1979,10 @@ impl Config {
                     Some(v) => vec![v.to_string()],
                     None => vec![],
                 },
+                "reasoning" => ["off", "low", "medium", "high"]
+                    .into_iter()
+                    .map(|v| v.to_string())
+                    .collect(),
                 "dry_run" => complete_bool(self.dry_run),
                 "stream" => complete_bool(self.stream),
                 "save" => complete_bool(self.save),
@@ -1845,6 +2019,10 @@ impl Config {
                     .map(|v| v.id())
                     .collect(),
                 "highlight" => complete_bool(self.highlight),
+                "reasoning_display" => ["show", "collapse", "hide"]
+                    .into_iter()
+                    .map(|v| v.to_string())
+                    .collect(),
                 _ => vec![],
             };
             values = candidates.into_iter().map(|v| (v, None)).collect();
@@ -1987,6 +2165,9 @@ impl Config {
                 output.insert("top_p", top_p.to_string());
             }
         }
+        if let Some(reasoning) = role.reasoning() {
+            output.insert("reasoning", reasoning.to_string());
+        }
         if self.dry_run {
             output.insert("dry_run", "true".to_string());
         }
@@ -2014,6 +2195,9 @@ impl Config {
             output.insert("consume_tokens", tokens.to_string());
             output.insert("consume_percent", percent.to_string());
             output.insert("user_messages_len", session.user_messages_len().to_string());
+            if session.cost() > 0.0 {
+                output.insert("session_cost", format!("{:.4}", session.cost()));
+            }
         }
         if let Some(rag) = &self.rag {
             output.insert("rag", rag.name().to_string());
@@ -2058,6 +2242,9 @@ impl Config {
         output: &str,
         tool_results: &[ToolResult],
     ) -> Result<()> {
+        if !self.dry_run {
+            self.record_usage(input);
+        }
         if !tool_results.is_empty() {
             return Ok(());
         }
@@ -2068,6 +2255,35 @@ impl Config {
         Ok(())
     }
 
+    pub fn record_usage(&mut self, input: &Input) {
+        let (input_tokens, output_tokens) = match input.usage() {
+            Some(v) => v,
+            None => return,
+        };
+        let role = input.role();
+        let mut record = UsageRecord::new("chat", role.model(), input_tokens, output_tokens);
+        if let Some((creation_tokens, read_tokens)) = input.cache_usage() {
+            record.set_cache_tokens(role.model(), creation_tokens, read_tokens);
+        }
+        let agent = self
+            .agent
+            .as_ref()
+            .filter(|_| input.with_agent())
+            .map(|v| v.name().to_string());
+        let session = input.session_mut(&mut self.session).map(|session| {
+            if let Some(cost) = record.cost {
+                session.add_cost(cost);
+            }
+            session.name().to_string()
+        });
+        record.set_scope(
+            (!role.is_derived()).then(|| role.name()),
+            session.as_deref(),
+            agent.as_deref(),
+        );
+        record.save();
+    }
+
     fn discontinuous_last_message(&mut self) {
         if let Some(last_message) = self.last_message.as_mut() {
             last_message.continuous = false;
@@ -2202,7 +2418,17 @@ impl Config {
     fn load_from_file(config_path: &Path) -> Result<Self> {
         let err = || format!("Failed to load config at '{}'", config_path.display());
         let content = read_to_string(config_path).with_context(err)?;
-        let config: Self = serde_yaml::from_str(&content)
+        let mut value: serde_yaml::Value = serde_yaml::from_str(&content).with_context(err)?;
+        let resolved = match value.get_mut(CLIENTS_FIELD) {
+            Some(clients) => secret::resolve_secret_refs(clients)?,
+            None => false,
+        };
+        let config: serde_yaml::Result<Self> = if resolved {
+            serde_yaml::from_value(value)
+        } else {
+            serde_yaml::from_str(&content)
+        };
+        let config: Self = config
             .map_err(|err| {
                 let err_msg = err.to_string();
                 let err_msg = if err_msg.starts_with(&format!("{CLIENTS_FIELD}: ")) {
@@ -2246,109 +2472,128 @@ impl Config {
         Ok(config)
     }
 
-    fn load_envs(&mut self) {
-        if let Ok(v) = env::var(get_env_name("model")) {
+    fn load_envs(&mut self) -> Result<()> {
+        if let Some(v) = read_env(&get_env_name("model"))? {
             self.model_id = v;
         }
-        if let Some(v) = read_env_value::<f64>(&get_env_name("temperature")) {
+        if let Some(v) = read_env_value::<f64>(&get_env_name("temperature"))? {
             self.temperature = v;
         }
-        if let Some(v) = read_env_value::<f64>(&get_env_name("top_p")) {
+        if let Some(v) = read_env_value::<f64>(&get_env_name("top_p"))? {
             self.top_p = v;
         }
+        if let Some(v) = read_env_value::<Reasoning>(&get_env_name("reasoning"))? {
+            self.reasoning = v;
+        }
+        if let Some(v) = read_env(&get_env_name("fallback_models"))? {
+            self.fallback_models = split_model_ids(&v);
+        }
 
-        if let Some(Some(v)) = read_env_bool(&get_env_name("dry_run")) {
+        if let Some(Some(v)) = read_env_bool(&get_env_name("dry_run"))? {
             self.dry_run = v;
         }
-        if let Some(Some(v)) = read_env_bool(&get_env_name("stream")) {
+        if let Some(Some(v)) = read_env_bool(&get_env_name("stream"))? {
             self.stream = v;
         }
-        if let Some(Some(v)) = read_env_bool(&get_env_name("save")) {
+        if let Some(Some(v)) = read_env_bool(&get_env_name("save"))? {
             self.save = v;
         }
-        if let Ok(v) = env::var(get_env_name("keybindings")) {
+        if let Some(v) = read_env(&get_env_name("keybindings"))? {
             if v == "vi" {
                 self.keybindings = v;
             }
         }
-        if let Some(v) = read_env_value::<String>(&get_env_name("editor")) {
+        if let Some(v) = read_env_value::<String>(&get_env_name("editor"))? {
             self.editor = v;
         }
-        if let Some(v) = read_env_value::<String>(&get_env_name("wrap")) {
+        if let Some(v) = read_env_value::<String>(&get_env_name("wrap"))? {
             self.wrap = v;
         }
-        if let Some(Some(v)) = read_env_bool(&get_env_name("wrap_code")) {
+        if let Some(Some(v)) = read_env_bool(&get_env_name("wrap_code"))? {
             self.wrap_code = v;
         }
 
-        if let Some(Some(v)) = read_env_bool(&get_env_name("function_calling")) {
+        if let Some(Some(v)) = read_env_bool(&get_env_name("function_calling"))? {
             self.function_calling = v;
         }
-        if let Ok(v) = env::var(get_env_name("mapping_tools")) {
+        if let Some(v) = read_env(&get_env_name("mapping_tools"))? {
             if let Ok(v) = serde_json::from_str(&v) {
                 self.mapping_tools = v;
             }
         }
-        if let Some(v) = read_env_value::<String>(&get_env_name("use_tools")) {
+        if let Some(v) = read_env_value::<String>(&get_env_name("use_tools"))? {
             self.use_tools = v;
         }
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("tool_call_concurrency"))? {
+            self.tool_call_concurrency = v;
+        }
 
-        if let Some(v) = read_env_value::<String>(&get_env_name("repl_prelude")) {
+        if let Some(v) = read_env_value::<String>(&get_env_name("repl_prelude"))? {
             self.repl_prelude = v;
         }
-        if let Some(v) = read_env_value::<String>(&get_env_name("cmd_prelude")) {
+        if let Some(v) = read_env_value::<String>(&get_env_name("cmd_prelude"))? {
             self.cmd_prelude = v;
         }
-        if let Some(v) = read_env_value::<String>(&get_env_name("agent_prelude")) {
+        if let Some(v) = read_env_value::<String>(&get_env_name("agent_prelude"))? {
             self.agent_prelude = v;
         }
 
-        if let Some(v) = read_env_bool(&get_env_name("save_session")) {
+        if let Some(v) = read_env_bool(&get_env_name("save_session"))? {
             self.save_session = v;
         }
-        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("compress_threshold")) {
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("compress_threshold"))? {
             self.compress_threshold = v;
         }
-        if let Some(v) = read_env_value::<String>(&get_env_name("summarize_prompt")) {
+        if let Some(v) = read_env_value::<String>(&get_env_name("summarize_prompt"))? {
             self.summarize_prompt = v;
         }
-        if let Some(v) = read_env_value::<String>(&get_env_name("summary_prompt")) {
+        if let Some(v) = read_env_value::<String>(&get_env_name("summary_prompt"))? {
             self.summary_prompt = v;
         }
 
-        if let Some(v) = read_env_value::<String>(&get_env_name("rag_embedding_model")) {
+        if let Some(v) = read_env_value::<Budget>(&get_env_name("daily_budget"))? {
+            self.daily_budget = v;
+        }
+        if let Some(v) = read_env_value::<Budget>(&get_env_name("monthly_budget"))? {
+            self.monthly_budget = v;
+        }
+
+        if let Some(v) = read_env_value::<String>(&get_env_name("rag_embedding_model"))? {
             self.rag_embedding_model = v;
         }
-        if let Some(v) = read_env_value::<String>(&get_env_name("rag_reranker_model")) {
+        if let Some(v) = read_env_value::<String>(&get_env_name("rag_reranker_model"))? {
             self.rag_reranker_model = v;
         }
-        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("rag_top_k")) {
+        if let Some(Some(v)) = read_env_value::<usize>(&get_env_name("rag_top_k"))? {
             self.rag_top_k = v;
         }
-        if let Some(v) = read_env_value::<usize>(&get_env_name("rag_chunk_size")) {
+        if let Some(v) = read_env_value::<usize>(&get_env_name("rag_chunk_size"))? {
             self.rag_chunk_size = v;
         }
-        if let Some(v) = read_env_value::<usize>(&get_env_name("rag_chunk_overlap")) {
+        if let Some(v) = read_env_value::<usize>(&get_env_name("rag_chunk_tokens"))? {
+            self.rag_chunk_tokens = v;
+        }
+        if let Some(v) = read_env_value::<usize>(&get_env_name("rag_chunk_overlap"))? {
             self.rag_chunk_overlap = v;
         }
-        if let Some(v) = read_env_value::<String>(&get_env_name("rag_template")) {
+        if let Some(v) = read_env_value::<String>(&get_env_name("rag_template"))? {
             self.rag_template = v;
         }
 
-        if let Ok(v) = env::var(get_env_name("document_loaders")) {
+        if let Some(v) = read_env(&get_env_name("document_loaders"))? {
             if let Ok(v) = serde_json::from_str(&v) {
                 self.document_loaders = v;
             }
         }
 
-        if let Some(Some(v)) = read_env_bool(&get_env_name("highlight")) {
+        if let Some(Some(v)) = read_env_bool(&get_env_name("highlight"))? {
             self.highlight = v;
         }
         if *NO_COLOR {
             self.highlight = false;
         }
         if self.highlight && self.theme.is_none() {
-            if let Some(v) = read_env_value::<String>(&get_env_name("theme")) {
+            if let Some(v) = read_env_value::<String>(&get_env_name("theme"))? {
                 self.theme = v;
             } else if *IS_STDOUT_TERMINAL {
                 if let Ok(color_scheme) = color_scheme(QueryOptions::default()) {
@@ -2360,25 +2605,38 @@ impl Config {
                 }
             }
         }
-        if let Some(v) = read_env_value::<String>(&get_env_name("left_prompt")) {
+        if let Some(Some(v)) =
+            read_env_value::<ReasoningDisplay>(&get_env_name("reasoning_display"))?
+        {
+            self.reasoning_display = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("left_prompt"))? {
             self.left_prompt = v;
         }
-        if let Some(v) = read_env_value::<String>(&get_env_name("right_prompt")) {
+        if let Some(v) = read_env_value::<String>(&get_env_name("right_prompt"))? {
             self.right_prompt = v;
         }
 
-        if let Some(v) = read_env_value::<String>(&get_env_name("serve_addr")) {
+        if let Some(v) = read_env_value::<String>(&get_env_name("serve_addr"))? {
             self.serve_addr = v;
         }
-        if let Some(v) = read_env_value::<String>(&get_env_name("user_agent")) {
+        if let Some(v) = read_env_value::<String>(&get_env_name("user_agent"))? {
             self.user_agent = v;
         }
-        if let Some(Some(v)) = read_env_bool(&get_env_name("save_shell_history")) {
+        if let Some(Some(v)) = read_env_bool(&get_env_name("save_shell_history"))? {
             self.save_shell_history = v;
         }
-        if let Some(v) = read_env_value::<String>(&get_env_name("sync_models_url")) {
+        if let Some(v) = read_env_value::<String>(&get_env_name("sync_models_url"))? {
             self.sync_models_url = v;
         }
+        if let Some(v) = read_env_value::<CassetteMode>(&get_env_name("cassette_mode"))? {
+            self.cassette_mode = v;
+        }
+        if let Some(v) = read_env_value::<String>(&get_env_name("cassette_file"))? {
+            self.cassette_file = v;
+        }
+
+        Ok(())
     }
 
     fn load_functions(&mut self) -> Result<()> {
@@ -2386,6 +2644,26 @@ impl Config {
         Ok(())
     }
 
+    /// MCP servers are only started to chat with tools, not to show info, count tokens or dry run.
+    pub fn can_start_mcp_servers(&self) -> bool {
+        self.function_calling && !self.dry_run && !self.info_flag && !self.count_tokens_flag
+    }
+
+    /// Starts the MCP servers, left to the modes that chat with tools so the others don't spawn them.
+    pub async fn load_mcp_servers(config: &GlobalConfig) {
+        let mcp_servers = {
+            let config = config.read();
+            if !config.can_start_mcp_servers() {
+                return;
+            }
+            config.mcp_servers.clone()
+        };
+        let mcp_clients = McpClients::start(&mcp_servers).await;
+        let mut config = config.write();
+        config.functions.extend(mcp_clients.declarations());
+        config.mcp_clients = mcp_clients;
+    }
+
     fn setup_model(&mut self) -> Result<()> {
         let mut model_id = self.model_id.clone();
         if model_id.is_empty() {
@@ -2477,6 +2755,7 @@ pub async fn macro_execute(
     let mut config = config.read().clone();
     config.temperature = role.temperature();
     config.top_p = role.top_p();
+    config.reasoning = role.reasoning();
     config.use_tools = role.use_tools().clone();
     config.macro_flag = true;
     config.model = role.model().clone();
@@ -2675,13 +2954,33 @@ pub(crate) fn ensure_parent_exists(path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn read_env_value<T>(key: &str) -> Option<Option<T>>
+pub fn split_model_ids(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+        .collect()
+}
+
+/// Reads an env var, resolving `cmd:`, `file:`, `env:` and `vault:` references like the config file.
+fn read_env(key: &str) -> Result<Option<String>> {
+    match env::var(key) {
+        Ok(value) => resolve_ref(&value)
+            .map(Some)
+            .with_context(|| format!("Invalid env {key}")),
+        Err(_) => Ok(None),
+    }
+}
+
+fn read_env_value<T>(key: &str) -> Result<Option<Option<T>>>
 where
     T: std::str::FromStr,
 {
-    let value = env::var(key).ok()?;
-    let value = parse_value(&value).ok()?;
-    Some(value)
+    let Some(value) = read_env(key)? else {
+        return Ok(None);
+    };
+    Ok(parse_value(&value).ok())
 }
 
 fn parse_value<T>(value: &str) -> Result<Option<T>>
@@ -2700,9 +2999,8 @@ where
     Ok(value)
 }
 
-fn read_env_bool(key: &str) -> Option<Option<bool>> {
-    let value = env::var(key).ok()?;
-    Some(parse_bool(&value))
+fn read_env_bool(key: &str) -> Result<Option<Option<bool>>> {
+    Ok(read_env(key)?.map(|value| parse_bool(&value)))
 }
 
 fn complete_bool(value: bool) -> Vec<String> {
@@ -1,6 +1,6 @@
 use super::*;
 
-use crate::client::{Message, MessageContent, MessageRole, Model};
+use crate::client::{Message, MessageContent, MessageRole, Model, Reasoning};
 
 use anyhow::Result;
 use fancy_regex::Regex;
@@ -28,10 +28,12 @@ pub trait RoleLike {
     fn model(&self) -> &Model;
     fn temperature(&self) -> Option<f64>;
     fn top_p(&self) -> Option<f64>;
+    fn reasoning(&self) -> Option<Reasoning>;
     fn use_tools(&self) -> Option<String>;
     fn set_model(&mut self, model: Model);
     fn set_temperature(&mut self, value: Option<f64>);
     fn set_top_p(&mut self, value: Option<f64>);
+    fn set_reasoning(&mut self, value: Option<Reasoning>);
     fn set_use_tools(&mut self, value: Option<String>);
 }
 
@@ -50,7 +52,13 @@ pub struct Role {
     #[serde(skip_serializing_if = "Option::is_none")]
     top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning: Option<Reasoning>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     use_tools: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    fallback_models: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schema: Option<Value>,
 
     #[serde(skip)]
     model: Model,
@@ -81,7 +89,21 @@ impl Role {
                             "model" => role.model_id = value.as_str().map(|v| v.to_string()),
                             "temperature" => role.temperature = value.as_f64(),
                             "top_p" => role.top_p = value.as_f64(),
+                            "reasoning" => {
+                                role.reasoning = serde_json::from_value(value.clone()).ok()
+                            }
                             "use_tools" => role.use_tools = value.as_str().map(|v| v.to_string()),
+                            "fallback_models" => {
+                                role.fallback_models = match value {
+                                    Value::String(v) => split_model_ids(v),
+                                    Value::Array(list) => list
+                                        .iter()
+                                        .filter_map(|v| v.as_str().map(|v| v.to_string()))
+                                        .collect(),
+                                    _ => vec![],
+                                }
+                            }
+                            "schema" => role.schema = value.is_object().then(|| value.clone()),
                             _ => (),
                         }
                     }
@@ -105,8 +127,9 @@ impl Role {
     }
 
     pub fn list_builtin_roles() -> Vec<Self> {
-        RolesAsset::iter()
-            .filter_map(|v| Role::builtin(&v).ok())
+        Self::list_builtin_role_names()
+            .iter()
+            .filter_map(|v| Role::builtin(v).ok())
             .collect()
     }
 
@@ -125,9 +148,21 @@ impl Role {
         if let Some(top_p) = self.top_p() {
             metadata.push(format!("top_p: {top_p}"));
         }
+        if let Some(reasoning) = self.reasoning() {
+            metadata.push(format!("reasoning: {reasoning}"));
+        }
         if let Some(use_tools) = self.use_tools() {
             metadata.push(format!("use_tools: {use_tools}"));
         }
+        if !self.fallback_models.is_empty() {
+            metadata.push(format!(
+                "fallback_models: {}",
+                self.fallback_models.join(",")
+            ));
+        }
+        if let Some(schema) = &self.schema {
+            metadata.push(format!("schema: {schema}"));
+        }
         if metadata.is_empty() {
             format!("{}\n", self.prompt)
         } else if self.prompt.is_empty() {
@@ -164,8 +199,9 @@ impl Role {
         let model = role_like.model();
         let temperature = role_like.temperature();
         let top_p = role_like.top_p();
+        let reasoning = role_like.reasoning();
         let use_tools = role_like.use_tools();
-        self.batch_set(model, temperature, top_p, use_tools);
+        self.batch_set(model, temperature, top_p, reasoning, use_tools);
     }
 
     pub fn batch_set(
@@ -173,6 +209,7 @@ impl Role {
         model: &Model,
         temperature: Option<f64>,
         top_p: Option<f64>,
+        reasoning: Option<Reasoning>,
         use_tools: Option<String>,
     ) {
         self.set_model(model.clone());
@@ -182,6 +219,9 @@ impl Role {
         if top_p.is_some() {
             self.set_top_p(top_p);
         }
+        if reasoning.is_some() {
+            self.set_reasoning(reasoning);
+        }
         if use_tools.is_some() {
             self.set_use_tools(use_tools);
         }
@@ -199,6 +239,18 @@ impl Role {
         self.model_id.as_deref()
     }
 
+    pub fn fallback_models(&self) -> &[String] {
+        &self.fallback_models
+    }
+
+    pub fn schema(&self) -> Option<&Value> {
+        self.schema.as_ref()
+    }
+
+    pub fn set_schema(&mut self, schema: Option<Value>) {
+        self.schema = schema;
+    }
+
     pub fn prompt(&self) -> &str {
         &self.prompt
     }
@@ -276,6 +328,10 @@ impl RoleLike for Role {
         self.top_p
     }
 
+    fn reasoning(&self) -> Option<Reasoning> {
+        self.reasoning
+    }
+
     fn use_tools(&self) -> Option<String> {
         self.use_tools.clone()
     }
@@ -295,6 +351,10 @@ impl RoleLike for Role {
         self.top_p = value;
     }
 
+    fn set_reasoning(&mut self, value: Option<Reasoning>) {
+        self.reasoning = value;
+    }
+
     fn set_use_tools(&mut self, value: Option<String>) {
         self.use_tools = value;
     }
@@ -390,4 +450,22 @@ Input 1
 "#;
         assert_eq!(parse_structure_prompt(prompt), (prompt, vec![]));
     }
+
+    #[test]
+    fn test_role_schema() {
+        let content = r#"---
+schema:
+  type: object
+  properties:
+    name:
+      type: string
+---
+Extract the name"#;
+        let role = Role::new("test", content);
+        let schema = role.schema().unwrap();
+        assert_eq!(schema["properties"]["name"]["type"], "string");
+        let role = Role::new("test", &role.export());
+        assert_eq!(role.schema(), Some(schema));
+        assert_eq!(role.prompt(), "Extract the name");
+    }
 }
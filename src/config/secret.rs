@@ -0,0 +1,186 @@
+use crate::auth::vault::{resolve_vault_ref, VAULT_PREFIX};
+use crate::utils::{resolve_home_dir, run_command_with_output, SHELL};
+
+use anyhow::{bail, Context, Result};
+use indexmap::{IndexMap, IndexSet};
+use parking_lot::RwLock;
+use serde_yaml::Value;
+use std::borrow::Cow;
+use std::sync::LazyLock;
+use std::{env, fs};
+
+const CMD_PREFIX: &str = "cmd:";
+const FILE_PREFIX: &str = "file:";
+const ENV_PREFIX: &str = "env:";
+const MASK: &str = "***";
+
+/// Last words of the names of fields holding secrets, e.g. `api_key` or `session_token`
+const SECRET_NAME_WORDS: [&str; 5] = ["key", "keys", "token", "secret", "password"];
+
+// Reference -> resolved value, so each command runs once
+static RESOLVED_REFS: LazyLock<RwLock<IndexMap<String, String>>> = LazyLock::new(Default::default);
+// Resolved values of secrets, masked in what gets displayed or logged
+static SECRETS: LazyLock<RwLock<IndexSet<String>>> = LazyLock::new(Default::default);
+
+/// Resolves a reference like `resolve_ref` and masks the value as a secret from then on.
+pub fn resolve_secret(value: &str) -> Result<String> {
+    let resolved = resolve_ref(value)?;
+    if resolved != value {
+        SECRETS.write().insert(resolved.clone());
+    }
+    Ok(resolved)
+}
+
+/// Resolves the value of a config field, masking it when the field holds a secret.
+pub fn resolve_field(name: &str, value: &str) -> Result<String> {
+    match is_secret_name(name) {
+        true => resolve_secret(value),
+        false => resolve_ref(value),
+    }
+}
+
+/// Resolves a `cmd:<shell command>`, `file:<path>`, `env:<VAR>` or `vault:<name>` reference,
+/// returning other values unchanged.
+pub fn resolve_ref(value: &str) -> Result<String> {
+    if let Some(v) = RESOLVED_REFS.read().get(value) {
+        return Ok(v.clone());
+    }
+    let resolved = if let Some(cmd) = value.strip_prefix(CMD_PREFIX) {
+        let (success, stdout, stderr) =
+            run_command_with_output(&SHELL.cmd, &[&SHELL.arg, cmd.trim()], None)
+                .with_context(|| format!("Failed to run `{}`", cmd.trim()))?;
+        if !success {
+            let reason = match stderr.trim() {
+                "" => "the command failed",
+                v => v,
+            };
+            bail!("Failed to resolve '{value}': {reason}");
+        }
+        stdout.trim_end().to_string()
+    } else if let Some(path) = value.strip_prefix(FILE_PREFIX) {
+        let path = resolve_home_dir(path.trim());
+        fs::read_to_string(&path)
+            .with_context(|| format!("Failed to resolve '{value}'"))?
+            .trim_end()
+            .to_string()
+    } else if let Some(name) = value.strip_prefix(ENV_PREFIX) {
+        env::var(name.trim())
+            .with_context(|| format!("Failed to resolve '{value}', '{}' is not set", name.trim()))?
+    } else if value.starts_with(VAULT_PREFIX) {
+        resolve_vault_ref(value)?
+    } else {
+        return Ok(value.to_string());
+    };
+    if resolved.is_empty() {
+        bail!("Failed to resolve '{value}', got an empty value");
+    }
+    RESOLVED_REFS
+        .write()
+        .insert(value.to_string(), resolved.clone());
+    Ok(resolved)
+}
+
+fn is_secret_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    let last_word = name.rsplit('_').next().unwrap_or_default();
+    SECRET_NAME_WORDS.contains(&last_word)
+}
+
+/// Resolves the `cmd:`, `file:` and `env:` references among the strings of a config tree,
+/// masking those of the fields holding secrets (see `resolve_field`).
+/// Vault references are left for `resolve_secret` so the vault is only unlocked when needed.
+pub fn resolve_secret_refs(value: &mut Value) -> Result<bool> {
+    resolve_refs(value, false)
+}
+
+fn resolve_refs(value: &mut Value, secret: bool) -> Result<bool> {
+    let mut changed = false;
+    match value {
+        Value::String(v)
+            if [CMD_PREFIX, FILE_PREFIX, ENV_PREFIX]
+                .iter()
+                .any(|prefix| v.starts_with(prefix)) =>
+        {
+            *v = match secret {
+                true => resolve_secret(v)?,
+                false => resolve_ref(v)?,
+            };
+            changed = true;
+        }
+        Value::Sequence(list) => {
+            for item in list {
+                changed |= resolve_refs(item, secret)?;
+            }
+        }
+        Value::Mapping(map) => {
+            for (key, item) in map.iter_mut() {
+                let secret = secret || key.as_str().is_some_and(is_secret_name);
+                changed |= resolve_refs(item, secret)?;
+            }
+        }
+        Value::Tagged(tagged) => {
+            changed |= resolve_refs(&mut tagged.value, secret)?;
+        }
+        _ => {}
+    }
+    Ok(changed)
+}
+
+/// Hides every resolved secret in text about to be displayed or logged.
+pub fn mask_secrets(text: &str) -> Cow<'_, str> {
+    let secrets = SECRETS.read();
+    let mut output = Cow::Borrowed(text);
+    for secret in secrets.iter() {
+        if output.contains(secret.as_str()) {
+            output = Cow::Owned(output.replace(secret.as_str(), MASK));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_secret_refs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let key_file = temp_dir.path().join("key");
+        fs::write(&key_file, "file-secret\n").unwrap();
+        env::set_var("AICHAT_TEST_SECRET_REF", "env-secret");
+        env::set_var("AICHAT_TEST_PROXY_REF", "socks5://127.0.0.1:1080");
+
+        let mut value: Value = serde_yaml::from_str(&format!(
+            r#"
+- type: openai
+  api_key: cmd:echo cmd-secret
+  api_keys: ["file:{}", "env:AICHAT_TEST_SECRET_REF"]
+  api_base: https://api.openai.com/v1
+  extra:
+    proxy: env:AICHAT_TEST_PROXY_REF
+"#,
+            key_file.display()
+        ))
+        .unwrap();
+        assert!(resolve_secret_refs(&mut value).unwrap());
+        assert_eq!(value[0]["api_key"].as_str(), Some("cmd-secret"));
+        assert_eq!(value[0]["api_keys"][0].as_str(), Some("file-secret"));
+        assert_eq!(value[0]["api_keys"][1].as_str(), Some("env-secret"));
+        assert_eq!(
+            value[0]["api_base"].as_str(),
+            Some("https://api.openai.com/v1")
+        );
+
+        assert_eq!(
+            value[0]["extra"]["proxy"].as_str(),
+            Some("socks5://127.0.0.1:1080")
+        );
+
+        assert_eq!(
+            mask_secrets("Bearer cmd-secret, key=env-secret, proxy=socks5://127.0.0.1:1080"),
+            "Bearer ***, key=***, proxy=socks5://127.0.0.1:1080"
+        );
+        assert!(resolve_secret("env:AICHAT_TEST_SECRET_MISSING").is_err());
+        assert!(resolve_secret("cmd:exit 1").is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use super::input::*;
 use super::*;
 
-use crate::client::{Message, MessageContent, MessageRole};
+use crate::client::{Message, MessageContent, MessageRole, Reasoning};
 use crate::render::MarkdownRender;
 
 use anyhow::{bail, Context, Result};
@@ -25,11 +25,15 @@ pub struct Session {
     #[serde(skip_serializing_if = "Option::is_none")]
     top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning: Option<Reasoning>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     use_tools: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     save_session: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     compress_threshold: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    budget: Option<Budget>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     role_name: Option<String>,
@@ -43,6 +47,8 @@ pub struct Session {
     messages: Vec<Message>,
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     data_urls: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cost: Option<f64>,
 
     #[serde(skip)]
     model: Model,
@@ -154,6 +160,9 @@ impl Session {
         if let Some(top_p) = self.top_p() {
             data["top_p"] = top_p.into();
         }
+        if let Some(reasoning) = self.reasoning() {
+            data["reasoning"] = json!(reasoning);
+        }
         if let Some(use_tools) = self.use_tools() {
             data["use_tools"] = use_tools.into();
         }
@@ -168,6 +177,9 @@ impl Session {
         if percent != 0.0 {
             data["total/max"] = format!("{percent}%").into();
         }
+        if let Some(cost) = self.cost {
+            data["cost"] = format!("${cost:.4}").into();
+        }
         data["messages"] = json!(self.messages);
 
         let output = serde_yaml::to_string(&data)
@@ -198,6 +210,9 @@ impl Session {
         if let Some(top_p) = self.top_p() {
             items.push(("top_p", top_p.to_string()));
         }
+        if let Some(reasoning) = self.reasoning() {
+            items.push(("reasoning", reasoning.to_string()));
+        }
 
         if let Some(use_tools) = self.use_tools() {
             items.push(("use_tools", use_tools));
@@ -211,6 +226,14 @@ impl Session {
             items.push(("compress_threshold", compress_threshold.to_string()));
         }
 
+        if let Some(budget) = self.budget {
+            items.push(("budget", budget.to_string()));
+        }
+
+        if let Some(cost) = self.cost {
+            items.push(("cost", format!("${cost:.4}")));
+        }
+
         if let Some(max_input_tokens) = self.model().max_input_tokens() {
             items.push(("max_input_tokens", max_input_tokens.to_string()));
         }
@@ -267,10 +290,21 @@ impl Session {
         (tokens, percent)
     }
 
+    /// Accumulated cost of the session in USD.
+    pub fn cost(&self) -> f64 {
+        self.cost.unwrap_or_default()
+    }
+
+    pub fn add_cost(&mut self, cost: f64) {
+        self.cost = Some(self.cost() + cost);
+        self.dirty = true;
+    }
+
     pub fn set_role(&mut self, role: Role) {
         self.model_id = role.model().id();
         self.temperature = role.temperature();
         self.top_p = role.top_p();
+        self.reasoning = role.reasoning();
         self.use_tools = role.use_tools();
         self.model = role.model().clone();
         self.role_name = convert_option_string(role.name());
@@ -317,6 +351,17 @@ impl Session {
         }
     }
 
+    pub fn budget(&self) -> Option<Budget> {
+        self.budget
+    }
+
+    pub fn set_budget(&mut self, value: Option<Budget>) {
+        if self.budget != value {
+            self.budget = value;
+            self.dirty = true;
+        }
+    }
+
     pub fn need_compress(&self, global_compress_threshold: usize) -> bool {
         if self.compressing {
             return false;
@@ -474,9 +519,13 @@ impl Session {
                 }
             }
         } else if input.regenerate() {
+            let model_id = input.role().model().id();
+            let model = (model_id != self.model.id()).then_some(model_id);
             if let Some(message) = self.messages.last_mut() {
                 if let MessageContent::Text(text) = &mut message.content {
                     *text = output.to_string();
+                    message.model = model;
+                    message.reasoning = input.reasoning().map(|v| v.to_string());
                 }
             }
         } else {
@@ -498,10 +547,14 @@ impl Session {
                     MessageContent::ToolCalls(tool_calls.clone()),
                 ))
             }
-            self.messages.push(Message::new(
+            let mut message = Message::new(
                 MessageRole::Assistant,
                 MessageContent::Text(output.to_string()),
-            ));
+            );
+            let model_id = input.role().model().id();
+            message.model = (model_id != self.model.id()).then_some(model_id);
+            message.reasoning = input.reasoning().map(|v| v.to_string());
+            self.messages.push(message);
         }
         self.dirty = true;
         self.update_tokens();
@@ -577,6 +630,10 @@ impl RoleLike for Session {
         self.top_p
     }
 
+    fn reasoning(&self) -> Option<Reasoning> {
+        self.reasoning
+    }
+
     fn use_tools(&self) -> Option<String> {
         self.use_tools.clone()
     }
@@ -604,6 +661,13 @@ impl RoleLike for Session {
         }
     }
 
+    fn set_reasoning(&mut self, value: Option<Reasoning>) {
+        if self.reasoning != value {
+            self.reasoning = value;
+            self.dirty = true;
+        }
+    }
+
     fn set_use_tools(&mut self, value: Option<String>) {
         if self.use_tools != value {
             self.use_tools = value;
@@ -0,0 +1,522 @@
+use super::Config;
+
+use crate::client::Model;
+use crate::utils::now;
+
+use anyhow::{anyhow, bail, Context, Result};
+use indexmap::IndexMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{read_to_string, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+pub const USAGE_GROUPS: [&str; 5] = ["day", "model", "role", "session", "agent"];
+
+/// Running totals of the ledger, loaded once and then kept up to date as records are saved.
+static SPENDING: LazyLock<Mutex<Option<Spending>>> = LazyLock::new(Default::default);
+
+/// One billed API call, appended as a line of the usage ledger.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct UsageRecord {
+    pub time: String,
+    pub api: String,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+impl UsageRecord {
+    pub fn new(api: &str, model: &Model, input_tokens: u64, output_tokens: u64) -> Self {
+        Self {
+            time: now(),
+            api: api.to_string(),
+            model: model.id(),
+            input_tokens,
+            output_tokens,
+            cost: calculate_cost(model, input_tokens, output_tokens),
+            ..Default::default()
+        }
+    }
+
+    /// Adds prompt caching tokens, which the providers report apart from the input tokens.
+    pub fn set_cache_tokens(
+        &mut self,
+        model: &Model,
+        creation_tokens: u64,
+        read_tokens: u64,
+    ) -> &mut Self {
+        self.cache_creation_input_tokens = Some(creation_tokens);
+        self.cache_read_input_tokens = Some(read_tokens);
+        if let Some(cache_cost) = calculate_cache_cost(model, creation_tokens, read_tokens) {
+            self.cost = Some(self.cost.unwrap_or_default() + cache_cost);
+        }
+        self
+    }
+
+    pub fn set_scope(
+        &mut self,
+        role: Option<&str>,
+        session: Option<&str>,
+        agent: Option<&str>,
+    ) -> &mut Self {
+        self.role = role.map(|v| v.to_string());
+        self.session = session.map(|v| v.to_string());
+        self.agent = agent.map(|v| v.to_string());
+        self
+    }
+
+    /// Appends the record to the ledger. A failing ledger must never fail the request.
+    pub fn save(&self) {
+        let path = Config::usage_file();
+        if let Err(err) = append_usage(&path, self) {
+            warn!("Failed to record usage: {err}");
+        }
+        if let Some(spending) = SPENDING.lock().as_mut().filter(|v| v.path == path) {
+            spending.add(self);
+        }
+    }
+
+    fn group_key(&self, group: UsageGroup) -> &str {
+        let value = match group {
+            UsageGroup::Day => self.time.get(..10),
+            UsageGroup::Model => Some(self.model.as_str()),
+            UsageGroup::Role => self.role.as_deref(),
+            UsageGroup::Session => self.session.as_deref(),
+            UsageGroup::Agent => self.agent.as_deref(),
+        };
+        value.unwrap_or("-")
+    }
+}
+
+/// Prices in models.yaml are per 1M tokens.
+pub fn calculate_cost(model: &Model, input_tokens: u64, output_tokens: u64) -> Option<f64> {
+    let data = model.data();
+    if data.input_price.is_none() && data.output_price.is_none() {
+        return None;
+    }
+    let input_cost = data.input_price.unwrap_or_default() * input_tokens as f64;
+    let output_cost = data.output_price.unwrap_or_default() * output_tokens as f64;
+    Some((input_cost + output_cost) / 1_000_000.0)
+}
+
+/// Cache writes and reads are billed relative to the input price.
+const CACHE_CREATION_PRICE_RATIO: f64 = 1.25;
+const CACHE_READ_PRICE_RATIO: f64 = 0.1;
+
+pub fn calculate_cache_cost(model: &Model, creation_tokens: u64, read_tokens: u64) -> Option<f64> {
+    let input_price = model.data().input_price?;
+    let tokens = creation_tokens as f64 * CACHE_CREATION_PRICE_RATIO
+        + read_tokens as f64 * CACHE_READ_PRICE_RATIO;
+    Some(input_price * tokens / 1_000_000.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageGroup {
+    Day,
+    Model,
+    Role,
+    Session,
+    Agent,
+}
+
+impl FromStr for UsageGroup {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "day" => Ok(Self::Day),
+            "model" => Ok(Self::Model),
+            "role" => Ok(Self::Role),
+            "session" => Ok(Self::Session),
+            "agent" => Ok(Self::Agent),
+            _ => bail!(
+                "Invalid usage group '{s}', expected one of {}",
+                USAGE_GROUPS.join(", ")
+            ),
+        }
+    }
+}
+
+impl UsageGroup {
+    fn name(self) -> &'static str {
+        USAGE_GROUPS[self as usize]
+    }
+}
+
+/// Spending limits in USD. Crossing `soft` warns, exceeding `hard` refuses the request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub struct Budget {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub soft: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hard: Option<f64>,
+}
+
+impl Budget {
+    /// Returns a warning if the soft limit is crossed, or an error if the hard one would be exceeded.
+    pub fn check(&self, scope: &str, spent: f64, cost: f64) -> Result<Option<String>> {
+        let total = spent + cost;
+        if let Some(hard) = self.hard {
+            if total > hard {
+                bail!(
+                    "Hard budget of {scope} would be exceeded (spent ${spent:.4}, ${total:.4} with this request, limit ${hard:.4})"
+                );
+            }
+        }
+        match self.soft {
+            Some(soft) if total > soft => Ok(Some(format!(
+                "Soft budget of {scope} exceeded (spent ${spent:.4}, ${total:.4} with this request, limit ${soft:.4})"
+            ))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl FromStr for Budget {
+    type Err = anyhow::Error;
+
+    /// Accepts `<hard>` or a comma-separated list like `soft=1,hard=5`.
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(hard) = s.parse() {
+            return Ok(Self {
+                soft: None,
+                hard: Some(hard),
+            });
+        }
+        let mut budget = Self::default();
+        for part in s.split(',') {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid budget '{s}', expected e.g. 'soft=1,hard=5'"))?;
+            let value = value
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid budget amount '{value}'"))?;
+            match key.trim() {
+                "soft" => budget.soft = Some(value),
+                "hard" => budget.hard = Some(value),
+                _ => bail!("Invalid budget key '{key}', expected 'soft' or 'hard'"),
+            }
+        }
+        Ok(budget)
+    }
+}
+
+impl std::fmt::Display for Budget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts: Vec<String> = [("soft", self.soft), ("hard", self.hard)]
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|v| format!("{key}={v}")))
+            .collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+/// What has been spent today and this month, in total and per agent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Spending {
+    path: PathBuf,
+    day: String,
+    month: String,
+    pub today: f64,
+    pub this_month: f64,
+    agents: HashMap<String, f64>,
+}
+
+impl Spending {
+    /// Returns the spending of the ledger at `path`, reading the ledger only the first time.
+    pub fn current(path: &Path) -> Result<Self> {
+        let mut spending = SPENDING.lock();
+        let spending = match spending.as_mut().filter(|v| v.path == path) {
+            Some(v) => v,
+            None => spending.insert(Self::load(path)?),
+        };
+        spending.roll(&now());
+        Ok(spending.clone())
+    }
+
+    pub fn agent(&self, name: &str) -> f64 {
+        self.agents.get(name).copied().unwrap_or_default()
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let mut spending = Self {
+            path: path.to_path_buf(),
+            ..Default::default()
+        };
+        spending.roll(&now());
+        for record in load_usage(path)? {
+            spending.add(&record);
+        }
+        Ok(spending)
+    }
+
+    /// Starts over when the day or the month changes.
+    fn roll(&mut self, time: &str) {
+        let (day, month) = (&time[..10], &time[..7]);
+        if self.month != month {
+            self.month = month.to_string();
+            self.this_month = 0.0;
+            self.agents.clear();
+        }
+        if self.day != day {
+            self.day = day.to_string();
+            self.today = 0.0;
+        }
+    }
+
+    fn add(&mut self, record: &UsageRecord) {
+        let cost = match record.cost {
+            Some(v) => v,
+            None => return,
+        };
+        if record.time.get(..10) > Some(self.day.as_str()) {
+            self.roll(&record.time);
+        }
+        if record.time.starts_with(&self.month) {
+            self.this_month += cost;
+            if let Some(agent) = &record.agent {
+                *self.agents.entry(agent.clone()).or_default() += cost;
+            }
+        }
+        if record.time.starts_with(&self.day) {
+            self.today += cost;
+        }
+    }
+}
+
+pub fn append_usage(path: &Path, record: &UsageRecord) -> Result<()> {
+    let line = serde_json::to_string(record)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open usage ledger at '{}'", path.display()))?;
+    writeln!(file, "{line}")
+        .with_context(|| format!("Failed to write usage ledger at '{}'", path.display()))?;
+    Ok(())
+}
+
+pub fn load_usage(path: &Path) -> Result<Vec<UsageRecord>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let content = read_to_string(path)
+        .with_context(|| format!("Failed to load usage ledger at '{}'", path.display()))?;
+    let mut records = vec![];
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(record) => records.push(record),
+            Err(err) => warn!(
+                "Skip invalid usage record at '{}:{}': {err}",
+                path.display(),
+                i + 1
+            ),
+        }
+    }
+    Ok(records)
+}
+
+#[derive(Debug, Default)]
+struct UsageSummary {
+    requests: usize,
+    input_tokens: u64,
+    output_tokens: u64,
+    cost: Option<f64>,
+}
+
+impl UsageSummary {
+    fn add(&mut self, record: &UsageRecord) {
+        self.requests += 1;
+        self.input_tokens += record.input_tokens;
+        self.output_tokens += record.output_tokens;
+        if let Some(cost) = record.cost {
+            self.cost = Some(self.cost.unwrap_or_default() + cost);
+        }
+    }
+
+    fn render(&self, key: &str, key_width: usize) -> String {
+        let cost = match self.cost {
+            Some(cost) => format!("${cost:.4}"),
+            None => "-".to_string(),
+        };
+        format!(
+            "{key:<key_width$}  {:>8}  {:>12}  {:>13}  {cost:>10}",
+            self.requests, self.input_tokens, self.output_tokens
+        )
+    }
+}
+
+pub fn render_usage_report(records: &[UsageRecord], group: UsageGroup) -> String {
+    let mut groups: IndexMap<&str, UsageSummary> = IndexMap::new();
+    let mut total = UsageSummary::default();
+    for record in records {
+        groups
+            .entry(record.group_key(group))
+            .or_default()
+            .add(record);
+        total.add(record);
+    }
+    if group == UsageGroup::Day {
+        groups.sort_keys();
+    } else {
+        groups.sort_by(|_, a, _, b| {
+            b.cost
+                .unwrap_or_default()
+                .total_cmp(&a.cost.unwrap_or_default())
+        });
+    }
+    let key_width = groups
+        .keys()
+        .map(|v| v.len())
+        .chain([group.name().len(), "total".len()])
+        .max()
+        .unwrap_or_default();
+    let mut lines = vec![format!(
+        "{:<key_width$}  {:>8}  {:>12}  {:>13}  {:>10}",
+        group.name(),
+        "requests",
+        "input_tokens",
+        "output_tokens",
+        "cost"
+    )];
+    for (key, summary) in &groups {
+        lines.push(summary.render(key, key_width));
+    }
+    lines.push(total.render("total", key_width));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(time: &str, model: &str, role: Option<&str>, cost: Option<f64>) -> UsageRecord {
+        UsageRecord {
+            time: time.into(),
+            api: "chat".into(),
+            model: model.into(),
+            role: role.map(|v| v.into()),
+            input_tokens: 100,
+            output_tokens: 10,
+            cost,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_calculate_cost() {
+        let mut model = Model::new("test", "model");
+        assert_eq!(calculate_cost(&model, 1000, 1000), None);
+        model.data_mut().input_price = Some(2.0);
+        model.data_mut().output_price = Some(8.0);
+        assert_eq!(calculate_cost(&model, 500_000, 250_000), Some(3.0));
+        let mut record = UsageRecord::new("chat", &model, 0, 0);
+        record.set_cache_tokens(&model, 400_000, 1_000_000);
+        assert_eq!(record.cost, Some(1.2));
+    }
+
+    #[test]
+    fn test_budget() {
+        let budget: Budget = "soft=1,hard=5".parse().unwrap();
+        assert_eq!(budget.soft, Some(1.0));
+        assert_eq!(budget.hard, Some(5.0));
+        assert_eq!(budget.to_string(), "soft=1,hard=5");
+        assert_eq!("2.5".parse::<Budget>().unwrap().hard, Some(2.5));
+        assert!("daily=1".parse::<Budget>().is_err());
+        assert_eq!(budget.check("session 'a'", 0.5, 0.1).unwrap(), None);
+        assert_eq!(
+            budget.check("session 'a'", 0.9, 0.2).unwrap().unwrap(),
+            "Soft budget of session 'a' exceeded (spent $0.9000, $1.1000 with this request, limit $1.0000)"
+        );
+        assert_eq!(
+            budget.check("session 'a'", 4.9, 0.2).unwrap_err().to_string(),
+            "Hard budget of session 'a' would be exceeded (spent $4.9000, $5.1000 with this request, limit $5.0000)"
+        );
+    }
+
+    #[test]
+    fn test_spending() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("usage.jsonl");
+        let today = now();
+        let mut agent_record = record(&today, "openai:gpt-4o", None, Some(0.5));
+        agent_record.agent = Some("coder".into());
+        for record in [
+            record(
+                "2000-01-01T10:00:00+00:00",
+                "openai:gpt-4o",
+                None,
+                Some(9.0),
+            ),
+            agent_record,
+            record(&today, "ollama:llama3", None, None),
+        ] {
+            append_usage(&path, &record).unwrap();
+        }
+        let mut spending = Spending::load(&path).unwrap();
+        assert_eq!((spending.today, spending.this_month), (0.5, 0.5));
+        assert_eq!(spending.agent("coder"), 0.5);
+        spending.add(&record(&today, "openai:gpt-4o", None, Some(0.25)));
+        assert_eq!((spending.today, spending.this_month), (0.75, 0.75));
+        spending.add(&record(
+            "2999-01-01T10:00:00+00:00",
+            "openai:gpt-4o",
+            None,
+            Some(1.0),
+        ));
+        assert_eq!((spending.today, spending.this_month), (1.0, 1.0));
+        assert_eq!(spending.agent("coder"), 0.0);
+    }
+
+    #[test]
+    fn test_render_usage_report() {
+        let records = vec![
+            record(
+                "2026-10-02T10:00:00+00:00",
+                "openai:gpt-4o",
+                Some("coder"),
+                Some(0.5),
+            ),
+            record("2026-10-01T10:00:00+00:00", "ollama:llama3", None, None),
+            record(
+                "2026-10-02T11:00:00+00:00",
+                "openai:gpt-4o",
+                None,
+                Some(0.25),
+            ),
+        ];
+        let report = render_usage_report(&records, UsageGroup::Day);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("2026-10-01"));
+        assert!(lines[1].ends_with(" -"));
+        assert!(lines[2].starts_with("2026-10-02"));
+        assert!(lines[2].ends_with("$0.7500"));
+        assert!(lines[3].starts_with("total"));
+        let report = render_usage_report(&records, UsageGroup::Role);
+        let lines: Vec<&str> = report.lines().collect();
+        assert!(lines[1].starts_with("coder "));
+        assert!(lines[2].starts_with("- "));
+        assert_eq!(lines[2].split_whitespace().nth(1), Some("2"));
+    }
+}
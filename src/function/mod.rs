@@ -0,0 +1,660 @@
+mod sandbox;
+
+pub use self::sandbox::SandboxConfig;
+
+use crate::{
+    config::{Agent, Config, GlobalConfig, FUNCTIONS_BIN_DIR_NAME},
+    mcp::McpClient,
+    utils::*,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures_util::{stream, StreamExt};
+use indexmap::IndexMap;
+use inquire::Select;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::IsTerminal,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+const CONFIRM_ALLOW_ONCE: &str = "Allow once";
+const CONFIRM_ALLOW_ALWAYS: &str = "Allow always";
+const CONFIRM_DENY: &str = "Deny";
+const CONFIRM_EDIT: &str = "Edit arguments";
+const CONFIRM_OPTIONS: [&str; 4] = [
+    CONFIRM_ALLOW_ONCE,
+    CONFIRM_ALLOW_ALWAYS,
+    CONFIRM_DENY,
+    CONFIRM_EDIT,
+];
+
+#[cfg(windows)]
+const PATH_SEP: &str = ";";
+#[cfg(not(windows))]
+const PATH_SEP: &str = ":";
+
+pub async fn eval_tool_calls(
+    config: &GlobalConfig,
+    mut calls: Vec<ToolCall>,
+) -> Result<Vec<ToolResult>> {
+    let mut output = vec![];
+    if calls.is_empty() {
+        return Ok(output);
+    }
+    calls = ToolCall::dedup(calls);
+    if calls.is_empty() {
+        bail!("The request was aborted because an infinite loop of function calls was detected.")
+    }
+    // Confirm every call first so that no prompt shows up among concurrent calls
+    let mut results = Vec::with_capacity(calls.len());
+    for call in calls.iter_mut() {
+        results.push(call.confirm(config)?);
+    }
+    let pending: Vec<usize> = (0..calls.len()).filter(|&i| results[i].is_none()).collect();
+    let concurrency = config.read().tool_call_concurrency.max(1);
+    let sequential: Vec<bool> = pending
+        .iter()
+        .map(|&i| calls[i].is_sequential(config))
+        .collect();
+    for batch in tool_call_batches(&sequential) {
+        let batch = &pending[batch];
+        let concurrent = concurrency > 1 && batch.len() > 1;
+        let evals: Vec<_> = batch
+            .iter()
+            .map(|&i| calls[i].eval(config, concurrent))
+            .collect();
+        let outputs: Vec<Result<Value>> = stream::iter(evals).buffered(concurrency).collect().await;
+        for (&i, output) in batch.iter().zip(outputs) {
+            results[i] = Some(output?);
+        }
+    }
+    let mut is_all_null = true;
+    for (call, result) in calls.into_iter().zip(results) {
+        let mut result = result.unwrap_or_default();
+        if result.is_null() {
+            result = json!("DONE");
+        } else {
+            is_all_null = false;
+        }
+        output.push(ToolResult::new(call, result));
+    }
+    if is_all_null {
+        output = vec![];
+    }
+    Ok(output)
+}
+
+/// Splits the calls into batches run one after another, the calls of a batch running
+/// concurrently. Sequential calls get a batch of their own.
+fn tool_call_batches(sequential: &[bool]) -> Vec<Range<usize>> {
+    let mut batches: Vec<Range<usize>> = vec![];
+    for (i, &is_sequential) in sequential.iter().enumerate() {
+        match batches.last_mut() {
+            Some(batch) if !is_sequential && !sequential[batch.start] => batch.end = i + 1,
+            _ => batches.push(i..i + 1),
+        }
+    }
+    batches
+}
+
+/// Whether a tool runs right away, once the user confirms it, or never.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolPolicy {
+    Allow,
+    Ask,
+    Deny,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ToolResult {
+    pub call: ToolCall,
+    pub output: Value,
+}
+
+impl ToolResult {
+    pub fn new(call: ToolCall, output: Value) -> Self {
+        Self { call, output }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Functions {
+    declarations: Vec<FunctionDeclaration>,
+    /// The directory of the declarations file, holding the tools in its `bin` directory
+    dir: Option<PathBuf>,
+}
+
+impl Functions {
+    pub fn init(declarations_path: &Path) -> Result<Self> {
+        let declarations: Vec<FunctionDeclaration> = if declarations_path.exists() {
+            let ctx = || {
+                format!(
+                    "Failed to load functions at {}",
+                    declarations_path.display()
+                )
+            };
+            let content = fs::read_to_string(declarations_path).with_context(ctx)?;
+            serde_json::from_str(&content).with_context(ctx)?
+        } else {
+            vec![]
+        };
+
+        Ok(Self {
+            declarations,
+            dir: declarations_path.parent().map(|v| v.to_path_buf()),
+        })
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&FunctionDeclaration> {
+        self.declarations.iter().find(|v| v.name == name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.declarations.iter().any(|v| v.name == name)
+    }
+
+    pub fn declarations(&self) -> &[FunctionDeclaration] {
+        &self.declarations
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.declarations.is_empty()
+    }
+
+    pub fn extend(&mut self, declarations: Vec<FunctionDeclaration>) {
+        self.declarations.extend(declarations);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    pub description: String,
+    pub parameters: JsonSchema,
+    #[serde(skip_serializing, default)]
+    pub agent: bool,
+    /// Never run the tool concurrently with other calls, e.g. when it prompts the user
+    #[serde(skip_serializing, default)]
+    pub sequential: bool,
+    /// The MCP server providing the tool
+    #[serde(skip)]
+    pub mcp_server: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonSchema {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub type_value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<IndexMap<String, JsonSchema>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items: Option<Box<JsonSchema>>,
+    #[serde(rename = "anyOf", skip_serializing_if = "Option::is_none")]
+    pub any_of: Option<Vec<JsonSchema>>,
+    #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
+    pub enum_value: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required: Option<Vec<String>>,
+}
+
+impl JsonSchema {
+    pub fn is_empty_properties(&self) -> bool {
+        match &self.properties {
+            Some(v) => v.is_empty(),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ToolCall {
+    pub name: String,
+    pub arguments: Value,
+    pub id: Option<String>,
+}
+
+type CallConfig = (String, String, Vec<String>, HashMap<String, String>);
+
+impl ToolCall {
+    pub fn dedup(calls: Vec<Self>) -> Vec<Self> {
+        let mut new_calls = vec![];
+        let mut seen_ids = HashSet::new();
+
+        for call in calls.into_iter().rev() {
+            if let Some(id) = &call.id {
+                if !seen_ids.contains(id) {
+                    seen_ids.insert(id.clone());
+                    new_calls.push(call);
+                }
+            } else {
+                new_calls.push(call);
+            }
+        }
+
+        new_calls.reverse();
+        new_calls
+    }
+
+    pub fn new(name: String, arguments: Value, id: Option<String>) -> Self {
+        Self {
+            name,
+            arguments,
+            id,
+        }
+    }
+
+    pub async fn eval(&self, config: &GlobalConfig, concurrent: bool) -> Result<Value> {
+        if let Some(client) = self.find_mcp_client(config) {
+            let json_data = self.json_arguments(&self.name)?;
+            return client.call_tool(&self.name, json_data).await;
+        }
+
+        let (call_name, cmd_name, mut cmd_args, envs) = match &config.read().agent {
+            Some(agent) => self.extract_call_config_from_agent(config, agent)?,
+            None => self.extract_call_config_from_config(config)?,
+        };
+        let sandbox = config.read().tool_sandbox(&self.name).cloned();
+        let functions_dir = match config.read().functions.dir() {
+            Some(dir) => dir.to_path_buf(),
+            None => Config::functions_dir(),
+        };
+
+        let json_data = self.json_arguments(&call_name)?;
+
+        cmd_args.push(json_data.to_string());
+
+        let output = tokio::task::spawn_blocking(move || {
+            run_llm_function(
+                cmd_name,
+                cmd_args,
+                envs,
+                &functions_dir,
+                concurrent,
+                sandbox.as_ref(),
+            )
+        })
+        .await??;
+        let output = match output {
+            Some(contents) => serde_json::from_str(&contents)
+                .ok()
+                .unwrap_or_else(|| json!({"output": contents})),
+            None => Value::Null,
+        };
+
+        Ok(output)
+    }
+
+    fn json_arguments(&self, call_name: &str) -> Result<Value> {
+        if self.arguments.is_object() {
+            Ok(self.arguments.clone())
+        } else if let Some(arguments) = self.arguments.as_str() {
+            let arguments: Value = serde_json::from_str(arguments).map_err(|_| {
+                anyhow!("The call '{call_name}' has invalid arguments: {arguments}")
+            })?;
+            Ok(arguments)
+        } else {
+            bail!(
+                "The call '{call_name}' has invalid arguments: {}",
+                self.arguments
+            );
+        }
+    }
+
+    /// Applies the policy of the tool, showing the call and asking the user when it says so.
+    /// Returns the error given back to the model when the call is denied.
+    pub fn confirm(&mut self, config: &GlobalConfig) -> Result<Option<Value>> {
+        let (policy, interactive) = {
+            let config = config.read();
+            let policy = match config.allowed_tools.contains(&self.name) {
+                true => ToolPolicy::Allow,
+                false => config.tool_policy(&self.name),
+            };
+            let interactive = !config.working_mode.is_serve()
+                && *IS_STDOUT_TERMINAL
+                && std::io::stdin().is_terminal();
+            (policy, interactive)
+        };
+        let denied = |reason: &str| Ok(Some(json!({ "error": reason })));
+        match policy {
+            ToolPolicy::Allow => return Ok(None),
+            ToolPolicy::Deny => return denied("The tool is not allowed to run"),
+            ToolPolicy::Ask if !interactive => {
+                return denied("The tool needs the user's approval, which cannot be asked for")
+            }
+            ToolPolicy::Ask => {}
+        }
+        loop {
+            let arguments = self
+                .json_arguments(&self.name)
+                .unwrap_or_else(|_| self.arguments.clone());
+            println!(
+                "{} {}\n{}",
+                warning_text("⚠ Call"),
+                self.name,
+                serde_json::to_string_pretty(&arguments)?
+            );
+            let ans = Select::new("Run this tool?", CONFIRM_OPTIONS.to_vec()).prompt()?;
+            match ans {
+                CONFIRM_ALLOW_ONCE => return Ok(None),
+                CONFIRM_ALLOW_ALWAYS => {
+                    config.write().allowed_tools.insert(self.name.clone());
+                    return Ok(None);
+                }
+                CONFIRM_DENY => return denied("The user denied the tool call"),
+                _ => {
+                    let editor = config.read().editor()?;
+                    let path = temp_file("-arguments-", ".json");
+                    fs::write(&path, serde_json::to_string_pretty(&arguments)?)?;
+                    edit_file(&editor, &path)?;
+                    let contents = fs::read_to_string(&path)?;
+                    let _ = fs::remove_file(&path);
+                    match serde_json::from_str::<Value>(&contents) {
+                        Ok(v) if v.is_object() => self.arguments = v,
+                        _ => eprintln!(
+                            "{}",
+                            warning_text(
+                                "⚠ The arguments must be a JSON object, ignoring the edit"
+                            )
+                        ),
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_sequential(&self, config: &GlobalConfig) -> bool {
+        let config = config.read();
+        if let Some(agent) = &config.agent {
+            if let Some(function) = agent.functions().find(&self.name) {
+                return function.sequential;
+            }
+        }
+        config
+            .functions
+            .find(&self.name)
+            .is_some_and(|v| v.sequential)
+    }
+
+    /// Agent tools shadow the global ones, as in `extract_call_config_from_agent`.
+    fn find_mcp_client(&self, config: &GlobalConfig) -> Option<Arc<McpClient>> {
+        let config = config.read();
+        if let Some(agent) = &config.agent {
+            if let Some(function) = agent.functions().find(&self.name) {
+                let server = function.mcp_server.as_ref()?;
+                return agent.mcp_clients().get(server);
+            }
+        }
+        let server = config.functions.find(&self.name)?.mcp_server.as_ref()?;
+        config.mcp_clients.get(server)
+    }
+
+    fn extract_call_config_from_agent(
+        &self,
+        config: &GlobalConfig,
+        agent: &Agent,
+    ) -> Result<CallConfig> {
+        let function_name = self.name.clone();
+        match agent.functions().find(&function_name) {
+            Some(function) => {
+                let agent_name = agent.name().to_string();
+                if function.agent {
+                    Ok((
+                        format!("{agent_name}-{function_name}"),
+                        agent_name,
+                        vec![function_name],
+                        agent.variable_envs(),
+                    ))
+                } else {
+                    Ok((
+                        function_name.clone(),
+                        function_name,
+                        vec![],
+                        Default::default(),
+                    ))
+                }
+            }
+            None => self.extract_call_config_from_config(config),
+        }
+    }
+
+    fn extract_call_config_from_config(&self, config: &GlobalConfig) -> Result<CallConfig> {
+        let function_name = self.name.clone();
+        match config.read().functions.contains(&function_name) {
+            true => Ok((
+                function_name.clone(),
+                function_name,
+                vec![],
+                Default::default(),
+            )),
+            false => bail!("Unexpected call: {function_name} {}", self.arguments),
+        }
+    }
+}
+
+pub fn run_llm_function(
+    cmd_name: String,
+    cmd_args: Vec<String>,
+    mut envs: HashMap<String, String>,
+    functions_dir: &Path,
+    capture_output: bool,
+    sandbox: Option<&SandboxConfig>,
+) -> Result<Option<String>> {
+    let prompt = format!("Call {cmd_name} {}", cmd_args.join(" "));
+
+    let mut bin_dirs: Vec<PathBuf> = vec![];
+    if cmd_args.len() > 1 {
+        let dir = Config::agent_functions_dir(&cmd_name).join("bin");
+        if dir.exists() {
+            bin_dirs.push(dir);
+        }
+    }
+    bin_dirs.push(functions_dir.join(FUNCTIONS_BIN_DIR_NAME));
+    let current_path = std::env::var("PATH").context("No PATH environment variable")?;
+    let prepend_path = bin_dirs
+        .iter()
+        .map(|v| format!("{}{PATH_SEP}", v.display()))
+        .collect::<Vec<_>>()
+        .join("");
+    envs.insert("PATH".into(), format!("{prepend_path}{current_path}"));
+
+    let temp_file = temp_file("-eval-", "");
+    envs.insert("LLM_OUTPUT".into(), temp_file.display().to_string());
+
+    #[cfg(windows)]
+    let cmd_name = polyfill_cmd_name(&cmd_name, &bin_dirs);
+    if *IS_STDOUT_TERMINAL {
+        println!("{}", dimmed_text(&prompt));
+    }
+    if let Some(sandbox) = sandbox {
+        let mut readable = bin_dirs.clone();
+        readable.push(functions_dir.to_path_buf());
+        let ret = sandbox.run(
+            &cmd_name,
+            &cmd_args,
+            envs,
+            &readable,
+            &temp_file,
+            capture_output,
+        )?;
+        if let Some(error) = ret {
+            let _ = fs::remove_file(&temp_file);
+            return Ok(Some(error.to_string()));
+        }
+    } else if capture_output {
+        let (success, stdout, stderr) =
+            run_command_with_output(&cmd_name, &cmd_args, Some(envs))
+                .map_err(|err| anyhow!("Unable to run {cmd_name}, {err}"))?;
+        print_captured_output(&stdout, &stderr);
+        if !success {
+            bail!("Tool call '{cmd_name}' failed");
+        }
+    } else {
+        let exit_code = run_command(&cmd_name, &cmd_args, Some(envs))
+            .map_err(|err| anyhow!("Unable to run {cmd_name}, {err}"))?;
+        if exit_code != 0 {
+            bail!("Tool call exit with {exit_code}");
+        }
+    }
+    let mut output = None;
+    if temp_file.exists() {
+        let contents =
+            fs::read_to_string(temp_file).context("Failed to retrieve tool call output")?;
+        if !contents.is_empty() {
+            output = Some(contents);
+        }
+    };
+    Ok(output)
+}
+
+/// Prints the whole output of a tool at once, so that concurrent calls do not interleave.
+fn print_captured_output(stdout: &str, stderr: &str) {
+    if !stdout.trim_end().is_empty() {
+        println!("{}", stdout.trim_end());
+    }
+    if !stderr.trim_end().is_empty() {
+        eprintln!("{}", stderr.trim_end());
+    }
+}
+
+#[cfg(windows)]
+fn polyfill_cmd_name<T: AsRef<Path>>(cmd_name: &str, bin_dir: &[T]) -> String {
+    let cmd_name = cmd_name.to_string();
+    if let Ok(exts) = std::env::var("PATHEXT") {
+        for name in exts.split(';').map(|ext| format!("{cmd_name}{ext}")) {
+            for dir in bin_dir {
+                let path = dir.as_ref().join(&name);
+                if path.exists() {
+                    return name.to_string();
+                }
+            }
+        }
+    }
+    cmd_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_call_batches() {
+        assert_eq!(tool_call_batches(&[]), Vec::<Range<usize>>::new());
+        assert_eq!(tool_call_batches(&[false, false, false]), vec![0..3]);
+        assert_eq!(
+            tool_call_batches(&[false, false, true, true, false]),
+            vec![0..2, 2..3, 3..4, 4..5]
+        );
+        assert_eq!(tool_call_batches(&[true, false, false]), vec![0..1, 1..3]);
+    }
+
+    #[tokio::test]
+    async fn test_tool_policies() {
+        let mut config = Config::default();
+        assert_eq!(config.tool_policy("fs_rm"), ToolPolicy::Allow);
+        config.tool_policies = serde_yaml::from_str("fs_rm: deny\n'*': ask\nfs_ls: allow").unwrap();
+        assert_eq!(config.tool_policy("fs_rm"), ToolPolicy::Deny);
+        assert_eq!(config.tool_policy("fs_cat"), ToolPolicy::Ask);
+        assert_eq!(config.tool_policy("fs_ls"), ToolPolicy::Allow);
+
+        // In serve mode, `ask` falls back to `deny` and nothing runs
+        config.working_mode = crate::config::WorkingMode::Serve;
+        let config = Arc::new(parking_lot::RwLock::new(config));
+        let calls = vec![
+            ToolCall::new("fs_rm".into(), json!({ "path": "/" }), Some("1".into())),
+            ToolCall::new("fs_cat".into(), json!({ "path": "a" }), Some("2".into())),
+        ];
+        let results = eval_tool_calls(&config, calls).await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|v| v.output["error"].is_string()));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_eval_tool_calls_concurrently() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let bin_dir = temp_dir.path().join("bin");
+        let running_dir = temp_dir.path().join("running");
+        let log_file = temp_dir.path().join("calls.log");
+        fs::create_dir_all(&bin_dir).unwrap();
+        fs::create_dir_all(&running_dir).unwrap();
+        let write_tool = |name: &str, wait: &str| {
+            let script = format!(
+                "#!/bin/sh\necho \"start $1\" >> '{}'\n{wait}\necho \"end $1\" >> '{}'\necho \"$1\" > \"$LLM_OUTPUT\"\n",
+                log_file.display(),
+                log_file.display(),
+            );
+            let path = bin_dir.join(name);
+            fs::write(&path, script).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        // Each call waits until all three are running, failing if they never overlap
+        let barrier = format!(
+            "touch '{0}/'$$\ni=0\nwhile [ $(ls '{0}' | wc -l) -lt 3 ]; do\n  [ $i -ge 100 ] && exit 1\n  sleep 0.05\n  i=$((i+1))\ndone",
+            running_dir.display()
+        );
+        write_tool("slow", &format!("{barrier}\nsleep 0.3"));
+        write_tool("fast", &barrier);
+        let declarations: Vec<_> = ["slow", "fast"]
+            .iter()
+            .map(|name| {
+                json!({
+                    "name": name,
+                    "description": "",
+                    "parameters": { "type": "object", "properties": {} },
+                })
+            })
+            .collect();
+        let functions_file = temp_dir.path().join("functions.json");
+        fs::write(&functions_file, json!(declarations).to_string()).unwrap();
+
+        let config = Config {
+            functions: Functions::init(&functions_file).unwrap(),
+            ..Default::default()
+        };
+        let config = Arc::new(parking_lot::RwLock::new(config));
+        let calls = vec![
+            ToolCall::new("slow".into(), json!({ "n": 1 }), Some("1".into())),
+            ToolCall::new("fast".into(), json!({ "n": 2 }), Some("2".into())),
+            ToolCall::new("slow".into(), json!({ "n": 3 }), Some("3".into())),
+        ];
+        let results = eval_tool_calls(&config, calls).await.unwrap();
+        let outputs: Vec<_> = results.iter().map(|v| v.output["n"].clone()).collect();
+        assert_eq!(outputs, vec![json!(1), json!(2), json!(3)]);
+
+        fs::remove_file(&log_file).unwrap();
+        write_tool("slow", "sleep 0.2");
+        config.write().tool_call_concurrency = 1;
+        let calls = vec![
+            ToolCall::new("slow".into(), json!({ "n": 1 }), Some("1".into())),
+            ToolCall::new("slow".into(), json!({ "n": 2 }), Some("2".into())),
+        ];
+        eval_tool_calls(&config, calls).await.unwrap();
+        let log = fs::read_to_string(&log_file).unwrap();
+        assert_eq!(
+            log.lines().collect::<Vec<_>>(),
+            [
+                r#"start {"n":1}"#,
+                r#"end {"n":1}"#,
+                r#"start {"n":2}"#,
+                r#"end {"n":2}"#,
+            ]
+        );
+    }
+}
@@ -0,0 +1,566 @@
+use crate::utils::{resolve_home_dir, temp_file};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+
+/// Environment variables every sandboxed tool gets, besides those of `env`
+const BASE_ENV: [&str; 5] = ["HOME", "USER", "LANG", "LC_ALL", "TERM"];
+/// Paths every sandboxed tool can read, so that interpreters and their libraries load
+const SYSTEM_READ_ONLY: [&str; 10] = [
+    "/usr",
+    "/bin",
+    "/sbin",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/etc",
+    "/opt",
+    "/nix/store",
+    "/proc",
+];
+const SYSTEM_READ_WRITE: [&str; 5] = [
+    "/dev/null",
+    "/dev/zero",
+    "/dev/random",
+    "/dev/urandom",
+    "/dev/tty",
+];
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Restrictions on the processes of llm-functions tools, only available on Linux.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    /// Environment variables passed through, besides HOME, USER, LANG, LC_ALL and TERM
+    pub env: Vec<String>,
+    /// The directory the tool runs in and can write to, the current one if unset
+    pub cwd: Option<String>,
+    pub read_only: Vec<String>,
+    pub read_write: Vec<String>,
+    pub network: bool,
+    /// Seconds of CPU time
+    pub cpu_time: Option<u64>,
+    /// MiB of address space
+    pub memory: Option<u64>,
+    /// Seconds before the tool gets killed
+    pub timeout: Option<u64>,
+}
+
+impl SandboxConfig {
+    /// Runs the tool in the sandbox, returning a structured tool error when it fails.
+    /// `readable` lists the tool's own files and `output_file` is where it writes its result.
+    pub fn run(
+        &self,
+        cmd_name: &str,
+        cmd_args: &[String],
+        envs: HashMap<String, String>,
+        readable: &[PathBuf],
+        output_file: &Path,
+        capture_output: bool,
+    ) -> Result<Option<Value>> {
+        if !cfg!(target_os = "linux") {
+            let message = "The tool sandbox is only supported on Linux";
+            return Ok(Some(self.error("unsupported", message, json!({}))));
+        }
+        let cwd = match &self.cwd {
+            Some(v) => resolve_path(v),
+            None => std::env::current_dir()?,
+        };
+        if !cwd.is_dir() {
+            let message = format!("The sandbox directory '{}' does not exist", cwd.display());
+            return Ok(Some(self.error("setup", &message, json!({}))));
+        }
+        // A private temp directory, shells need one for here-documents
+        let temp_dir = temp_file("-sandbox-", "");
+        fs::create_dir_all(&temp_dir)?;
+        fs::write(output_file, "")?;
+
+        let mut read_only: Vec<PathBuf> = SYSTEM_READ_ONLY.iter().map(PathBuf::from).collect();
+        read_only.extend(readable.iter().cloned());
+        read_only.extend(self.read_only.iter().map(|v| resolve_path(v)));
+        let mut read_write: Vec<PathBuf> = SYSTEM_READ_WRITE.iter().map(PathBuf::from).collect();
+        read_write.extend([cwd.clone(), temp_dir.clone(), output_file.to_path_buf()]);
+        read_write.extend(self.read_write.iter().map(|v| resolve_path(v)));
+
+        let mut command = Command::new(cmd_name);
+        command.args(cmd_args).current_dir(&cwd).env_clear();
+        for name in BASE_ENV
+            .iter()
+            .copied()
+            .chain(self.env.iter().map(|v| v.as_str()))
+        {
+            if let Ok(value) = std::env::var(name) {
+                command.env(name, value);
+            }
+        }
+        command.envs(envs).env("TMPDIR", &temp_dir);
+        let stdout_file = temp_dir.join("stdout");
+        let stderr_file = temp_dir.join("stderr");
+        if capture_output {
+            command
+                .stdin(Stdio::null())
+                .stdout(fs::File::create(&stdout_file)?)
+                .stderr(fs::File::create(&stderr_file)?);
+        }
+
+        let ret = self
+            .spawn(command, &read_only, &read_write)
+            .and_then(|mut child| wait_timeout(&mut child, self.timeout.map(Duration::from_secs)));
+        if capture_output {
+            super::print_captured_output(
+                &fs::read_to_string(&stdout_file).unwrap_or_default(),
+                &fs::read_to_string(&stderr_file).unwrap_or_default(),
+            );
+        }
+        let _ = fs::remove_dir_all(&temp_dir);
+
+        let status = match ret {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                let limit = self.timeout.unwrap_or_default();
+                let message = format!("The tool was killed after running for {limit}s");
+                return Ok(Some(self.error(
+                    "timeout",
+                    &message,
+                    json!({ "limit": limit }),
+                )));
+            }
+            Err(err) => {
+                let message = format!("Failed to run '{cmd_name}' in the sandbox, {err}");
+                return Ok(Some(self.error("setup", &message, json!({}))));
+            }
+        };
+        if status.success() {
+            return Ok(None);
+        }
+        let error = match exit_signal(&status) {
+            Some(signal) if self.is_cpu_time_signal(signal) => {
+                let limit = self.cpu_time.unwrap_or_default();
+                let message = format!("The tool exceeded its CPU time limit of {limit}s");
+                self.error("cpu_time", &message, json!({ "limit": limit }))
+            }
+            Some(signal) => {
+                let message = format!("The tool was killed by signal {signal}");
+                let details = json!({ "signal": signal, "memory": self.memory });
+                self.error("signal", &message, details)
+            }
+            None => {
+                // Denied accesses show up as failures, so tell the model what the tool can use
+                let code = status.code().unwrap_or_default();
+                let message = format!("Tool call exit with {code} in the sandbox");
+                let details = json!({
+                    "exit_code": code,
+                    "cwd": cwd,
+                    "read_only": self.read_only,
+                    "read_write": self.read_write,
+                    "network": self.network,
+                });
+                self.error("exit", &message, details)
+            }
+        };
+        Ok(Some(error))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn spawn(
+        &self,
+        mut command: Command,
+        read_only: &[PathBuf],
+        read_write: &[PathBuf],
+    ) -> std::io::Result<Child> {
+        use std::os::{fd::AsRawFd, unix::process::CommandExt};
+
+        let ruleset = linux::create_ruleset(read_only, read_write)?;
+        let restrictions = linux::Restrictions::new(self, ruleset.as_raw_fd());
+        if self.timeout.is_some() {
+            // A group of its own, so that the timeout also kills the processes the tool started
+            command.process_group(0);
+        }
+        // SAFETY: the hook only makes syscalls on data prepared before the fork
+        unsafe {
+            command.pre_exec(move || restrictions.apply());
+        }
+        command.spawn()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn spawn(
+        &self,
+        mut command: Command,
+        _read_only: &[PathBuf],
+        _read_write: &[PathBuf],
+    ) -> std::io::Result<Child> {
+        command.spawn()
+    }
+
+    #[cfg(unix)]
+    fn is_cpu_time_signal(&self, signal: i32) -> bool {
+        // The soft limit sends SIGXCPU, the hard one a second later SIGKILL
+        self.cpu_time.is_some() && (signal == libc::SIGXCPU || signal == libc::SIGKILL)
+    }
+
+    #[cfg(not(unix))]
+    fn is_cpu_time_signal(&self, _signal: i32) -> bool {
+        false
+    }
+
+    fn error(&self, reason: &str, message: &str, details: Value) -> Value {
+        let mut sandbox = json!({ "reason": reason });
+        if let (Some(sandbox), Some(details)) = (sandbox.as_object_mut(), details.as_object()) {
+            sandbox.extend(details.clone());
+        }
+        json!({ "error": message, "sandbox": sandbox })
+    }
+}
+
+fn resolve_path(path: &str) -> PathBuf {
+    PathBuf::from(resolve_home_dir(path))
+}
+
+/// Waits for the child, killing it once the timeout elapses, in which case it returns `None`.
+fn wait_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+) -> std::io::Result<Option<ExitStatus>> {
+    let timeout = match timeout {
+        Some(v) => v,
+        None => return child.wait().map(Some),
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            kill_process_group(child)?;
+            child.wait()?;
+            return Ok(None);
+        }
+        std::thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn kill_process_group(child: &mut Child) -> std::io::Result<()> {
+    // SAFETY: a plain syscall, the child leads its group as `spawn` sets it up with a timeout
+    match unsafe { libc::killpg(child.id() as libc::pid_t, libc::SIGKILL) } {
+        -1 => Err(std::io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn kill_process_group(child: &mut Child) -> std::io::Result<()> {
+    child.kill()
+}
+
+#[cfg(unix)]
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::SandboxConfig;
+
+    use std::{
+        ffi::CStr,
+        fs::File,
+        io,
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+            unix::fs::OpenOptionsExt,
+        },
+        path::PathBuf,
+    };
+
+    const CREATE_RULESET_VERSION: libc::c_uint = 1;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+    const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+    /// Every filesystem access of the first Landlock ABI
+    const ACCESS_FS_V1: u64 = (1 << 13) - 1;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: RawFd,
+    }
+
+    /// Builds a Landlock ruleset granting reads under `read_only` and every access under
+    /// `read_write`, skipping the paths that do not exist.
+    pub fn create_ruleset(read_only: &[PathBuf], read_write: &[PathBuf]) -> io::Result<OwnedFd> {
+        // SAFETY: a version query takes no attributes
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 1 {
+            let err = io::Error::last_os_error();
+            return Err(io::Error::other(format!("Landlock is unavailable, {err}")));
+        }
+        let mut handled = ACCESS_FS_V1;
+        if abi >= 3 {
+            handled |= ACCESS_FS_TRUNCATE;
+        }
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        // SAFETY: `attr` outlives the call and its size is passed along
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr,
+                size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the syscall returned a fresh descriptor
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+
+        let read_access = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+        let file_access =
+            ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_WRITE_FILE | ACCESS_FS_TRUNCATE;
+        let rules = read_only
+            .iter()
+            .map(|v| (v, read_access))
+            .chain(read_write.iter().map(|v| (v, handled)));
+        for (path, access) in rules {
+            let file = match File::options()
+                .read(true)
+                .custom_flags(libc::O_PATH)
+                .open(path)
+            {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            // Rules on files can only grant the accesses that apply to files
+            let mut access = access & handled;
+            if !file.metadata()?.is_dir() {
+                access &= file_access;
+            }
+            let attr = PathBeneathAttr {
+                allowed_access: access,
+                parent_fd: file.as_raw_fd(),
+            };
+            // SAFETY: `attr` outlives the call and both descriptors are open
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset.as_raw_fd(),
+                    RULE_PATH_BENEATH,
+                    &attr,
+                    0,
+                )
+            };
+            if ret < 0 {
+                let err = io::Error::last_os_error();
+                let message = format!("Failed to allow '{}', {err}", path.display());
+                return Err(io::Error::other(message));
+            }
+        }
+        Ok(ruleset)
+    }
+
+    /// What the tool process applies to itself between fork and exec.
+    pub struct Restrictions {
+        ruleset: RawFd,
+        unshare_network: bool,
+        uid_map: Vec<u8>,
+        gid_map: Vec<u8>,
+        cpu_time: Option<libc::rlimit>,
+        memory: Option<libc::rlimit>,
+    }
+
+    impl Restrictions {
+        pub fn new(config: &SandboxConfig, ruleset: RawFd) -> Self {
+            // SAFETY: these calls cannot fail
+            let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+            Self {
+                ruleset,
+                unshare_network: !config.network,
+                uid_map: format!("{uid} {uid} 1").into_bytes(),
+                gid_map: format!("{gid} {gid} 1").into_bytes(),
+                cpu_time: config.cpu_time.map(|v| libc::rlimit {
+                    rlim_cur: v as libc::rlim_t,
+                    rlim_max: (v + 1) as libc::rlim_t,
+                }),
+                memory: config.memory.map(|v| {
+                    let bytes = (v * 1024 * 1024) as libc::rlim_t;
+                    libc::rlimit {
+                        rlim_cur: bytes,
+                        rlim_max: bytes,
+                    }
+                }),
+            }
+        }
+
+        /// Runs in the forked child, so it must not allocate.
+        pub fn apply(&self) -> io::Result<()> {
+            // SAFETY: plain syscalls on memory owned by `self`
+            unsafe {
+                if self.unshare_network {
+                    // A user namespace makes a network namespace available without privileges
+                    check(libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET))?;
+                    write_file(c"/proc/self/setgroups", b"deny")?;
+                    write_file(c"/proc/self/uid_map", &self.uid_map)?;
+                    write_file(c"/proc/self/gid_map", &self.gid_map)?;
+                }
+                if let Some(limit) = &self.cpu_time {
+                    check(libc::setrlimit(libc::RLIMIT_CPU, limit))?;
+                }
+                if let Some(limit) = &self.memory {
+                    check(libc::setrlimit(libc::RLIMIT_AS, limit))?;
+                }
+                check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+                let ret = libc::syscall(libc::SYS_landlock_restrict_self, self.ruleset, 0);
+                check(ret as libc::c_int)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn check(ret: libc::c_int) -> io::Result<()> {
+        match ret {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    unsafe fn write_file(path: &CStr, contents: &[u8]) -> io::Result<()> {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        check(fd)?;
+        let ret = match libc::write(fd, contents.as_ptr().cast(), contents.len()) {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        };
+        libc::close(fd);
+        ret
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sandbox_run() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let work_dir = temp_dir.path().join("work");
+        let secret_file = temp_dir.path().join("secret");
+        fs::create_dir_all(&work_dir).unwrap();
+        fs::write(&secret_file, "secret").unwrap();
+        let output_file = temp_dir.path().join("output");
+        std::env::set_var("AICHAT_TEST_SANDBOX_SECRET", "secret");
+
+        let sandbox = SandboxConfig {
+            cwd: Some(work_dir.display().to_string()),
+            timeout: Some(1),
+            ..Default::default()
+        };
+        let run = |script: &str| {
+            let envs = HashMap::from([
+                ("PATH".into(), std::env::var("PATH").unwrap()),
+                ("LLM_OUTPUT".into(), output_file.display().to_string()),
+            ]);
+            let args = ["-c".to_string(), script.to_string()];
+            sandbox
+                .run("sh", &args, envs, &[], &output_file, true)
+                .unwrap()
+        };
+
+        let ret = run(r#"echo ok > result && echo '{"ok":true}' > "$LLM_OUTPUT""#);
+        if let Some(err) = ret.filter(|v| v["sandbox"]["reason"] == "setup") {
+            eprintln!("skipped, the sandbox is unavailable: {}", err["error"]);
+            return;
+        }
+        assert_eq!(fs::read_to_string(work_dir.join("result")).unwrap(), "ok\n");
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), "{\"ok\":true}\n");
+
+        let ret = run(&format!("cat {}", secret_file.display())).unwrap();
+        assert_eq!(ret["sandbox"]["reason"], "exit");
+        assert_eq!(ret["sandbox"]["network"], false);
+
+        assert!(run(r#"test -z "$AICHAT_TEST_SANDBOX_SECRET""#).is_none());
+        // A fresh network namespace only has the loopback interface
+        assert!(run("test $(wc -l < /proc/self/net/dev) -eq 3").is_none());
+
+        let ret = run("sleep 5").unwrap();
+        assert_eq!(ret["sandbox"]["reason"], "timeout");
+    }
+
+    #[test]
+    fn test_sandbox_timeout_kills_process_group() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let output_file = temp_dir.path().join("output");
+        let sandbox = SandboxConfig {
+            cwd: Some(temp_dir.path().display().to_string()),
+            timeout: Some(1),
+            ..Default::default()
+        };
+        let envs = HashMap::from([("PATH".into(), std::env::var("PATH").unwrap())]);
+        // The grandchild outlives the shell unless the whole group gets killed
+        let args = [
+            "-c".to_string(),
+            "sleep 30 & echo $! > pid; wait".to_string(),
+        ];
+        let ret = sandbox
+            .run("sh", &args, envs, &[], &output_file, true)
+            .unwrap()
+            .unwrap();
+        if ret["sandbox"]["reason"] == "setup" {
+            eprintln!("skipped, the sandbox is unavailable: {}", ret["error"]);
+            return;
+        }
+        assert_eq!(ret["sandbox"]["reason"], "timeout");
+
+        let pid = fs::read_to_string(temp_dir.path().join("pid")).unwrap();
+        let stat_file = PathBuf::from(format!("/proc/{}/stat", pid.trim()));
+        let is_running = || {
+            // Orphans may linger as zombies until they are reaped
+            fs::read_to_string(&stat_file)
+                .map(|v| {
+                    !v.rsplit(')')
+                        .next()
+                        .unwrap_or_default()
+                        .trim()
+                        .starts_with('Z')
+                })
+                .unwrap_or(false)
+        };
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while is_running() && Instant::now() < deadline {
+            std::thread::sleep(WAIT_INTERVAL);
+        }
+        assert!(!is_running());
+    }
+}
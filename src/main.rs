@@ -2,6 +2,7 @@ mod cli;
 mod client;
 mod config;
 mod function;
+mod mcp;
 mod rag;
 mod render;
 mod repl;
@@ -15,34 +16,42 @@ extern crate log;
 
 use crate::cli::Cli;
 use crate::client::{
-    call_chat_completions, call_chat_completions_streaming, list_models, ModelType,
+    call_chat_completions, call_chat_completions_streaming, call_structured_output, list_models,
+    ModelType,
 };
 use crate::config::{
     ensure_parent_exists, list_agents, load_env_file, macro_execute, Config, GlobalConfig, Input,
-    WorkingMode, CODE_ROLE, EXPLAIN_SHELL_ROLE, SHELL_ROLE, TEMP_SESSION_NAME,
+    RoleLike, WorkingMode, CODE_ROLE, EXPLAIN_SHELL_ROLE, SHELL_ROLE, TEMP_SESSION_NAME,
 };
-use crate::render::render_error;
+use crate::mcp::McpServer;
+use crate::render::{render_error, ReasoningDisplay};
 use crate::repl::Repl;
 use crate::utils::*;
-use crate::auth::{Authenticator, ApiKeyAuthenticator};
-use crate::auth::oauth_split::oauth_authenticator_struct::OAuthAuthenticator;
-use crate::auth::oauth_split::oauth_config::OAuthConfig;
-use crate::auth::credential_store::CredentialStore;
+use crate::auth::vault::{read_vault, update_vault};
+use crate::auth::OAuthAuthenticator;
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use inquire::Text;
 use parking_lot::RwLock;
 use simplelog::{format_description, ConfigBuilder, LevelFilter, SimpleLogger, WriteLogger};
+use std::io::{IsTerminal, Read};
 use std::{env, process, sync::Arc};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     load_env_file()?;
     let cli = Cli::parse();
-    let text = cli.text()?;
+    // Subcommands and the MCP server read stdin themselves, e.g. `aichat auth vault set`
+    let text = match cli.command {
+        Some(_) => None,
+        None if cli.mcp => None,
+        None => cli.text()?,
+    };
     let working_mode = if cli.serve.is_some() {
         WorkingMode::Serve
+    } else if cli.mcp {
+        WorkingMode::Cmd
     } else if text.is_none() && cli.file.is_empty() {
         WorkingMode::Repl
     } else {
@@ -55,14 +64,15 @@ async fn main() -> Result<()> {
         || cli.list_agents
         || cli.list_rags
         || cli.list_macros
-        || cli.list_sessions;
+        || cli.list_sessions
+        || cli.usage.is_some();
     setup_logger(working_mode.is_serve())?;
     let config = Arc::new(RwLock::new(Config::init(working_mode, info_flag).await?));
 
     if let Some(command) = cli.command {
         match command {
             cli::Commands::Auth(auth_command) => {
-                handle_auth_command(auth_command).await?;
+                handle_auth_command(&config, auth_command).await?;
             }
         }
         return Ok(());
@@ -75,22 +85,130 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn handle_auth_command(command: cli::AuthCommands) -> Result<()> {
-    match command {
-        cli::AuthCommands::Login => {
-            // TODO: Load client_id and client_secret securely, e.g., from environment variables or a config file.
-            // For now, using placeholders. REPLACE THESE WITH YOUR ACTUAL CREDENTIALS.
-            let oauth_config = OAuthConfig {
-                client_id: "YOUR_CLIENT_ID".to_string(),
-                client_secret: "YOUR_CLIENT_SECRET".to_string(),
+async fn handle_auth_command(config: &GlobalConfig, command: cli::AuthCommands) -> Result<()> {
+    let client_name = command.client;
+    let oauth_authenticator = || {
+        let oauth = client::oauth_client_config(&config.read(), &client_name)
+            .with_context(|| format!("No OAuth config for client '{client_name}'"))?;
+        OAuthAuthenticator::for_client(&client_name, oauth)
+    };
+    match command.command {
+        cli::AuthSubcommands::Login { device } => {
+            let authenticator = oauth_authenticator()?;
+            let credential_store = &authenticator.credential_store;
+            let credentials = if device {
+                authenticator.get_token_from_device_flow().await?
+            } else {
+                authenticator.get_token_from_web_flow().await?
+            };
+            credential_store.write_credentials(&credentials)?;
+            if let Err(err) = authenticator
+                .fetch_and_cache_user_info(&credentials.access_token)
+                .await
+            {
+                warn!("Failed to fetch user info: {err}");
+            }
+            println!("✓ Saved credentials to '{}'.", credential_store.location());
+        }
+        cli::AuthSubcommands::Status => {
+            let credential_store = oauth_authenticator()?.credential_store;
+            let credentials = match credential_store.read_credentials() {
+                Ok(v) => v,
+                Err(_) => {
+                    println!("Not logged in. Run `aichat auth login` first.");
+                    return Ok(());
+                }
+            };
+            let account = credentials
+                .user_info
+                .as_ref()
+                .map(|v| v.email.clone())
+                .unwrap_or_else(|| "-".to_string());
+            let expires_at = credentials
+                .expires_at()
+                .and_then(|v| chrono::DateTime::from_timestamp(v, 0))
+                .map(|v| {
+                    v.with_timezone(&chrono::Local)
+                        .to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+                })
+                .unwrap_or_else(|| "-".to_string());
+            let status = if credentials.is_expired() {
+                "expired"
+            } else {
+                "valid"
             };
-            let credential_store = Arc::new(CredentialStore::new()?);
-            let oauth_authenticator = OAuthAuthenticator::new(oauth_config, credential_store);
+            let refreshable = if credentials.refresh_token.is_some() {
+                "yes"
+            } else {
+                "no"
+            };
+            let items = [
+                ("credentials", credential_store.location()),
+                ("account", account),
+                ("access_token", status.to_string()),
+                ("expires_at", expires_at),
+                ("refreshable", refreshable.to_string()),
+            ];
+            for (name, value) in items {
+                println!("{name:<24}{value}");
+            }
+        }
+        cli::AuthSubcommands::Logout => {
+            let credential_store = oauth_authenticator()?.credential_store;
+            credential_store.clear_credentials()?;
+            println!(
+                "✓ Removed credentials from '{}'.",
+                credential_store.location()
+            );
+        }
+        cli::AuthSubcommands::Vault { command } => handle_vault_command(command)?,
+    }
+    Ok(())
+}
 
-            match oauth_authenticator.authenticate().await {
-                Ok(token) => println!("OAuth Access Token: {}", token),
-                Err(e) => eprintln!("OAuth Error: {}", e),
+fn handle_vault_command(command: cli::VaultSubcommands) -> Result<()> {
+    match command {
+        cli::VaultSubcommands::Set { name } => {
+            let value = if std::io::stdin().is_terminal() {
+                inquire::Password::new(&format!("Value of '{name}':"))
+                    .with_display_mode(inquire::PasswordDisplayMode::Masked)
+                    .without_confirmation()
+                    .prompt()?
+            } else {
+                let mut value = String::new();
+                std::io::stdin().read_to_string(&mut value)?;
+                value.trim_end_matches(['\r', '\n']).to_string()
+            };
+            if value.is_empty() {
+                bail!("Empty value for '{name}'");
             }
+            let path = update_vault(|vault| {
+                vault.set(&name, &value)?;
+                Ok(vault.path().to_path_buf())
+            })?;
+            println!("✓ Saved '{name}' to '{}'.", path.display());
+        }
+        cli::VaultSubcommands::Get { name } => {
+            let value = read_vault(|vault| {
+                vault
+                    .get(&name)
+                    .map(|v| v.to_string())
+                    .with_context(|| format!("No secret '{name}' in the vault"))
+            })?;
+            println!("{value}");
+        }
+        cli::VaultSubcommands::List => {
+            let names = read_vault(|vault| Ok(vault.names().join("\n")))?;
+            if !names.is_empty() {
+                println!("{names}");
+            }
+        }
+        cli::VaultSubcommands::Rm { name } => {
+            if !read_vault(|vault| Ok(vault.get(&name).is_some()))? {
+                bail!("No secret '{name}' in the vault");
+            }
+            update_vault(|vault| vault.remove(&name))?;
+            println!("✓ Removed '{name}' from the vault.");
         }
     }
     Ok(())
@@ -130,10 +248,18 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         println!("{macros}");
         return Ok(());
     }
+    if let Some(group) = &cli.usage {
+        let report = Config::usage_report(group.as_deref())?;
+        println!("{report}");
+        return Ok(());
+    }
 
     if cli.dry_run {
         config.write().dry_run = true;
     }
+    if cli.count_tokens {
+        config.write().count_tokens_flag = true;
+    }
 
     if let Some(agent) = &cli.agent {
         let session = cli.session.as_ref().map(|v| match v {
@@ -182,6 +308,9 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
     if cli.no_stream {
         config.write().stream = false;
     }
+    if cli.code {
+        config.write().reasoning_display = ReasoningDisplay::Hide;
+    }
     if cli.empty_session {
         config.write().empty_session()?;
     }
@@ -196,6 +325,9 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
     if let Some(addr) = cli.serve {
         return serve::run(config, addr).await;
     }
+    if cli.mcp {
+        return McpServer::run(config).await;
+    }
     let is_repl = config.read().working_mode.is_repl();
     if cli.rebuild_rag {
         Config::rebuild_rag(&config, abort_signal.clone()).await?;
@@ -203,6 +335,7 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
             return Ok(());
         }
     }
+    Config::load_mcp_servers(&config).await;
     if let Some(name) = &cli.macro_name {
         macro_execute(&config, name, text.as_deref(), abort_signal.clone()).await?;
         return Ok(());
@@ -213,9 +346,18 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
         return Ok(());
     }
     config.write().apply_prelude()?;
+    if cli.count_tokens {
+        let input = create_input(&config, text, &cli.file, abort_signal).await?;
+        let tokens = input.role().model().total_tokens(&input.build_messages()?);
+        println!("{tokens}");
+        return Ok(());
+    }
     match is_repl {
         false => {
             let mut input = create_input(&config, text, &cli.file, abort_signal.clone()).await?;
+            if let Some(path) = &cli.schema {
+                input.set_schema(load_schema(path)?);
+            }
             input.use_embeddings(abort_signal.clone()).await?;
             start_directive(&config, input, cli.code, abort_signal).await
         }
@@ -231,16 +373,18 @@ async fn run(config: GlobalConfig, cli: Cli, text: Option<String>) -> Result<()>
 #[async_recursion::async_recursion]
 async fn start_directive(
     config: &GlobalConfig,
-    input: Input,
+    mut input: Input,
     code_mode: bool,
     abort_signal: AbortSignal,
 ) -> Result<()> {
     let client = input.create_client()?;
     let extract_code = !*IS_STDOUT_TERMINAL && code_mode;
     config.write().before_chat_completion(&input)?;
-    let (output, tool_results) = if !input.stream() || extract_code {
+    let (output, tool_results) = if input.role().schema().is_some() {
+        call_structured_output(&mut input, client.as_ref(), abort_signal.clone()).await?
+    } else if !input.stream() || extract_code {
         call_chat_completions(
-            &input,
+            &mut input,
             true,
             extract_code,
             client.as_ref(),
@@ -248,7 +392,7 @@ async fn start_directive(
         )
         .await?
     } else {
-        call_chat_completions_streaming(&input, client.as_ref(), abort_signal.clone()).await?
+        call_chat_completions_streaming(&mut input, client.as_ref(), abort_signal.clone()).await?
     };
     config
         .write()
@@ -268,6 +412,17 @@ async fn start_directive(
     Ok(())
 }
 
+fn load_schema(path: &str) -> Result<serde_json::Value> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to load schema at '{path}'"))?;
+    let schema: serde_json::Value =
+        serde_json::from_str(&content).with_context(|| format!("Invalid schema at '{path}'"))?;
+    if !schema.is_object() {
+        bail!("Invalid schema at '{path}', expected a JSON object");
+    }
+    Ok(schema)
+}
+
 async fn start_interactive(config: &GlobalConfig) -> Result<()> {
     let mut repl: Repl = Repl::init(config)?;
     repl.run().await
@@ -283,7 +438,7 @@ async fn shell_execute(
     let client = input.create_client()?;
     config.write().before_chat_completion(&input)?;
     let (eval_str, _) =
-        call_chat_completions(&input, false, true, client.as_ref(), abort_signal.clone()).await?;
+        call_chat_completions(&mut input, false, true, client.as_ref(), abort_signal.clone()).await?;
 
     config
         .write()
@@ -326,17 +481,17 @@ async fn shell_execute(
                 }
                 'd' => {
                     let role = config.read().retrieve_role(EXPLAIN_SHELL_ROLE)?;
-                    let input = Input::from_str(config, &eval_str, Some(role));
+                    let mut input = Input::from_str(config, &eval_str, Some(role));
                     if input.stream() {
                         call_chat_completions_streaming(
-                            &input,
+                            &mut input,
                             client.as_ref(),
                             abort_signal.clone(),
                         )
                         .await?;
                     } else {
                         call_chat_completions(
-                            &input,
+                            &mut input,
                             true,
                             false,
                             client.as_ref(),
@@ -0,0 +1,448 @@
+use super::{McpServerConfig, PROTOCOL_VERSION};
+
+use crate::config::resolve_secret;
+use crate::function::{FunctionDeclaration, JsonSchema};
+use crate::utils::{dimmed_text, IS_STDOUT_TERMINAL};
+
+use anyhow::{anyhow, bail, Context, Result};
+use indexmap::IndexMap;
+use reqwest::{
+    header::{ACCEPT, CONTENT_TYPE},
+    Client as ReqwestClient,
+};
+use serde_json::{json, Value};
+use std::{fmt, process::Stdio, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    sync::Mutex,
+};
+
+const INITIALIZE_TIMEOUT: Duration = Duration::from_secs(30);
+const SESSION_ID_HEADER: &str = "mcp-session-id";
+const PROTOCOL_VERSION_HEADER: &str = "mcp-protocol-version";
+
+pub struct McpClient {
+    config: McpServerConfig,
+    declarations: Vec<FunctionDeclaration>,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl fmt::Debug for McpClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("McpClient")
+            .field("name", &self.config.name)
+            .field("tools", &self.declarations.len())
+            .finish()
+    }
+}
+
+impl McpClient {
+    pub async fn connect(config: McpServerConfig) -> Result<Self> {
+        let mut connection = Connection::open(&config).await?;
+        let mut tools = vec![];
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = connection.request("tools/list", params).await?;
+            if let Some(list) = result["tools"].as_array() {
+                tools.extend(list.iter().cloned());
+            }
+            match result["nextCursor"].as_str() {
+                Some(v) => cursor = Some(v.to_string()),
+                None => break,
+            }
+        }
+
+        let use_tools: Option<Vec<&str>> = config
+            .use_tools
+            .as_deref()
+            .filter(|v| *v != "all")
+            .map(|v| v.split(',').map(|v| v.trim()).collect());
+        let mut declarations = vec![];
+        for tool in &tools {
+            let name = tool["name"].as_str().unwrap_or_default();
+            if use_tools.as_ref().is_some_and(|v| !v.contains(&name)) {
+                continue;
+            }
+            match tool_declaration(&config.name, tool) {
+                Ok(declaration) => declarations.push(declaration),
+                Err(err) => warn!("Skip MCP tool '{name}' of '{}': {err:#}", config.name),
+            }
+        }
+
+        Ok(Self {
+            config,
+            declarations,
+            connection: Mutex::new(Some(connection)),
+        })
+    }
+
+    pub fn declarations(&self) -> &[FunctionDeclaration] {
+        &self.declarations
+    }
+
+    /// Calls a tool by its declaration name, `<server>_<tool>`.
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<Value> {
+        let tool_name = name
+            .strip_prefix(&format!("{}_", self.config.name))
+            .unwrap_or(name);
+        if *IS_STDOUT_TERMINAL {
+            let prompt = format!("Call {}:{tool_name} {arguments}", self.config.name);
+            println!("{}", dimmed_text(&prompt));
+        }
+        let result = self
+            .request(
+                "tools/call",
+                json!({ "name": tool_name, "arguments": arguments }),
+            )
+            .await
+            .with_context(|| format!("Failed to call MCP tool '{name}'"))?;
+        Ok(tool_output(&result))
+    }
+
+    /// Sends a request, starting the server again if it has exited.
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let mut guard = self.connection.lock().await;
+        let alive = guard.as_mut().is_some_and(|v| v.is_alive());
+        let connection = match guard.as_mut() {
+            Some(connection) if alive => connection,
+            _ => guard.insert(Connection::open(&self.config).await?),
+        };
+        connection.request(method, params).await
+    }
+}
+
+fn tool_declaration(server: &str, tool: &Value) -> Result<FunctionDeclaration> {
+    let name = tool["name"]
+        .as_str()
+        .ok_or_else(|| anyhow!("Missing tool name"))?;
+    let parameters: JsonSchema =
+        serde_json::from_value(tool["inputSchema"].clone()).context("Unsupported input schema")?;
+    Ok(FunctionDeclaration {
+        name: format!("{server}_{name}"),
+        description: tool["description"].as_str().unwrap_or_default().to_string(),
+        parameters,
+        agent: false,
+        sequential: false,
+        mcp_server: Some(server.to_string()),
+    })
+}
+
+/// Converts a `tools/call` result the way the output of an llm-functions tool is read.
+fn tool_output(result: &Value) -> Value {
+    let is_error = result["isError"].as_bool().unwrap_or_default();
+    if let Some(structured) = result.get("structuredContent").filter(|_| !is_error) {
+        return structured.clone();
+    }
+    let mut texts = vec![];
+    let mut others = vec![];
+    for item in result["content"].as_array().into_iter().flatten() {
+        match item["text"].as_str() {
+            Some(text) if item["type"] == "text" => texts.push(text),
+            _ => others.push(item.clone()),
+        }
+    }
+    let text = texts.join("\n");
+    if is_error {
+        json!({ "error": text })
+    } else if !others.is_empty() {
+        json!({ "output": text, "content": others })
+    } else if text.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(&text).unwrap_or_else(|_| json!({ "output": text }))
+    }
+}
+
+struct Connection {
+    transport: Transport,
+    next_id: u64,
+}
+
+enum Transport {
+    Stdio {
+        child: Child,
+        stdin: ChildStdin,
+        stdout: BufReader<ChildStdout>,
+    },
+    Http {
+        client: ReqwestClient,
+        url: String,
+        headers: IndexMap<String, String>,
+        session_id: Option<String>,
+    },
+}
+
+impl Connection {
+    async fn open(config: &McpServerConfig) -> Result<Self> {
+        let transport = match (&config.command, &config.url) {
+            (Some(command), _) => {
+                let mut envs = IndexMap::new();
+                for (key, value) in &config.env {
+                    envs.insert(key.clone(), resolve_secret(value)?);
+                }
+                let mut child = Command::new(command)
+                    .args(&config.args)
+                    .envs(envs)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .with_context(|| format!("Failed to run `{command}`"))?;
+                let stdin = child.stdin.take().context("No stdin")?;
+                let stdout = child.stdout.take().context("No stdout")?;
+                if let Some(stderr) = child.stderr.take() {
+                    let name = config.name.clone();
+                    tokio::spawn(async move {
+                        let mut lines = BufReader::new(stderr).lines();
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            debug!("MCP server '{name}': {line}");
+                        }
+                    });
+                }
+                Transport::Stdio {
+                    child,
+                    stdin,
+                    stdout: BufReader::new(stdout),
+                }
+            }
+            (None, Some(url)) => {
+                let mut headers = IndexMap::new();
+                for (key, value) in &config.headers {
+                    headers.insert(key.clone(), resolve_secret(value)?);
+                }
+                Transport::Http {
+                    client: ReqwestClient::new(),
+                    url: url.clone(),
+                    headers,
+                    session_id: None,
+                }
+            }
+            (None, None) => bail!("Missing `command` or `url`"),
+        };
+        let mut connection = Self {
+            transport,
+            next_id: 0,
+        };
+        let params = json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": {
+                "name": env!("CARGO_CRATE_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        });
+        tokio::time::timeout(INITIALIZE_TIMEOUT, connection.request("initialize", params))
+            .await
+            .map_err(|_| anyhow!("Timed out waiting for the server to initialize"))??;
+        connection
+            .transport
+            .exchange(
+                json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+                None,
+            )
+            .await?;
+        Ok(connection)
+    }
+
+    fn is_alive(&mut self) -> bool {
+        match &mut self.transport {
+            Transport::Stdio { child, .. } => matches!(child.try_wait(), Ok(None)),
+            Transport::Http { .. } => true,
+        }
+    }
+
+    async fn request(&mut self, method: &str, params: Value) -> Result<Value> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let response = self
+            .transport
+            .exchange(message, Some(id))
+            .await?
+            .ok_or_else(|| anyhow!("No response to '{method}'"))?;
+        if let Some(error) = response.get("error") {
+            match error["message"].as_str() {
+                Some(message) => bail!("{message}"),
+                None => bail!("{error}"),
+            }
+        }
+        Ok(response["result"].clone())
+    }
+}
+
+impl Transport {
+    /// Sends a JSON-RPC message, waiting for the response if it has an id.
+    async fn exchange(&mut self, message: Value, id: Option<u64>) -> Result<Option<Value>> {
+        match self {
+            Transport::Stdio { stdin, stdout, .. } => {
+                write_line(stdin, &message).await?;
+                let Some(id) = id else {
+                    return Ok(None);
+                };
+                loop {
+                    let mut line = String::new();
+                    if stdout.read_line(&mut line).await? == 0 {
+                        bail!("The server exited");
+                    }
+                    let Ok(message) = serde_json::from_str::<Value>(line.trim()) else {
+                        debug!("Skip invalid MCP message: {line}");
+                        continue;
+                    };
+                    match (message["method"].as_str(), message.get("id")) {
+                        (None, Some(_)) if message["id"].as_u64() == Some(id) => {
+                            return Ok(Some(message))
+                        }
+                        // Requests from the server, only pings are supported
+                        (Some(method), Some(request_id)) => {
+                            let reply = match method {
+                                "ping" => {
+                                    json!({ "jsonrpc": "2.0", "id": request_id, "result": {} })
+                                }
+                                _ => json!({
+                                    "jsonrpc": "2.0",
+                                    "id": request_id,
+                                    "error": { "code": -32601, "message": format!("Method not found: {method}") },
+                                }),
+                            };
+                            write_line(stdin, &reply).await?;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Transport::Http {
+                client,
+                url,
+                headers,
+                session_id,
+            } => {
+                let mut builder = client
+                    .post(url.as_str())
+                    .header(ACCEPT, "application/json, text/event-stream")
+                    .json(&message);
+                for (key, value) in headers.iter() {
+                    builder = builder.header(key, value);
+                }
+                if let Some(session_id) = session_id.as_ref() {
+                    builder = builder
+                        .header(SESSION_ID_HEADER, session_id)
+                        .header(PROTOCOL_VERSION_HEADER, PROTOCOL_VERSION);
+                }
+                let res = builder.send().await?;
+                if let Some(value) = res.headers().get(SESSION_ID_HEADER) {
+                    *session_id = Some(value.to_str()?.to_string());
+                }
+                let status = res.status();
+                let is_event_stream = res
+                    .headers()
+                    .get(CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .is_some_and(|v| v.starts_with("text/event-stream"));
+                let text = res.text().await?;
+                if !status.is_success() {
+                    bail!("{status} {text}");
+                }
+                let Some(id) = id else {
+                    return Ok(None);
+                };
+                if !is_event_stream {
+                    return Ok(Some(serde_json::from_str(&text)?));
+                }
+                for data in text.lines().filter_map(|v| v.strip_prefix("data:")) {
+                    if let Ok(message) = serde_json::from_str::<Value>(data.trim()) {
+                        if message["id"].as_u64() == Some(id) {
+                            return Ok(Some(message));
+                        }
+                    }
+                }
+                bail!("No response in the event stream")
+            }
+        }
+    }
+}
+
+async fn write_line(stdin: &mut ChildStdin, message: &Value) -> Result<()> {
+    stdin.write_all(format!("{message}\n").as_bytes()).await?;
+    stdin.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stdio_server_tools() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let requests_file = temp_dir.path().join("requests");
+        let script = r#"
+read -r line; echo "$line" >> "$REQUESTS"
+echo '{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2025-06-18","capabilities":{"tools":{}},"serverInfo":{"name":"mock","version":"0.1.0"}}}'
+read -r line; echo "$line" >> "$REQUESTS"
+read -r line; echo "$line" >> "$REQUESTS"
+echo '{"jsonrpc":"2.0","method":"notifications/message","params":{"level":"info","data":"listing"}}'
+echo '{"jsonrpc":"2.0","id":2,"result":{"tools":[{"name":"add","description":"Add two numbers","inputSchema":{"type":"object","properties":{"a":{"type":"number"},"b":{"type":"number"}},"required":["a","b"]}},{"name":"rm","inputSchema":{"type":"object"}}]}}'
+read -r line; echo "$line" >> "$REQUESTS"
+echo '{"jsonrpc":"2.0","id":3,"result":{"content":[{"type":"text","text":"3"}],"isError":false}}'
+"#;
+        let config = McpServerConfig {
+            name: "mock".into(),
+            command: Some("sh".into()),
+            args: vec!["-c".into(), script.into()],
+            env: IndexMap::from([("REQUESTS".into(), requests_file.display().to_string())]),
+            url: None,
+            headers: Default::default(),
+            use_tools: Some("add".into()),
+        };
+
+        let client = McpClient::connect(config).await.unwrap();
+        let names: Vec<&str> = client
+            .declarations()
+            .iter()
+            .map(|v| v.name.as_str())
+            .collect();
+        assert_eq!(names, ["mock_add"]);
+        assert_eq!(client.declarations()[0].description, "Add two numbers");
+        assert_eq!(client.declarations()[0].mcp_server.as_deref(), Some("mock"));
+
+        let output = client
+            .call_tool("mock_add", json!({ "a": 1, "b": 2 }))
+            .await
+            .unwrap();
+        assert_eq!(output, json!(3));
+
+        let requests = std::fs::read_to_string(&requests_file).unwrap();
+        let methods: Vec<Value> = requests
+            .lines()
+            .map(|v| serde_json::from_str::<Value>(v).unwrap()["method"].clone())
+            .collect();
+        assert_eq!(
+            methods,
+            [
+                "initialize",
+                "notifications/initialized",
+                "tools/list",
+                "tools/call"
+            ]
+        );
+        let call: Value = serde_json::from_str(requests.lines().last().unwrap()).unwrap();
+        assert_eq!(
+            call["params"],
+            json!({ "name": "add", "arguments": { "a": 1, "b": 2 } })
+        );
+
+        assert_eq!(
+            tool_output(
+                &json!({ "content": [{ "type": "text", "text": "not found" }], "isError": true })
+            ),
+            json!({ "error": "not found" })
+        );
+    }
+}
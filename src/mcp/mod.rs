@@ -0,0 +1,71 @@
+mod client;
+mod server;
+
+pub use self::client::McpClient;
+pub use self::server::McpServer;
+
+use crate::function::FunctionDeclaration;
+use crate::utils::warning_text;
+
+use futures_util::future::join_all;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const PROTOCOL_VERSION: &str = "2025-06-18";
+
+/// A Model Context Protocol server, launched over stdio with `command` or reached at `url`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct McpServerConfig {
+    pub name: String,
+    pub command: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: IndexMap<String, String>,
+    pub url: Option<String>,
+    #[serde(default)]
+    pub headers: IndexMap<String, String>,
+    /// Comma-separated tools of the server to expose, all of them if unset
+    pub use_tools: Option<String>,
+}
+
+/// The running MCP servers of the config or of an agent, stopped once the last clone is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct McpClients {
+    clients: IndexMap<String, Arc<McpClient>>,
+}
+
+impl McpClients {
+    /// Starts the servers concurrently, skipping those that fail with a warning.
+    pub async fn start(configs: &[McpServerConfig]) -> Self {
+        let results = join_all(configs.iter().cloned().map(McpClient::connect)).await;
+        let mut clients = IndexMap::new();
+        for (config, result) in configs.iter().zip(results) {
+            match result {
+                Ok(client) => {
+                    clients.insert(config.name.clone(), Arc::new(client));
+                }
+                Err(err) => eprintln!(
+                    "{}",
+                    warning_text(&format!(
+                        "⚠ Failed to start MCP server '{}': {err:#}",
+                        config.name
+                    ))
+                ),
+            }
+        }
+        Self { clients }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<McpClient>> {
+        self.clients.get(name).cloned()
+    }
+
+    pub fn declarations(&self) -> Vec<FunctionDeclaration> {
+        self.clients
+            .values()
+            .flat_map(|v| v.declarations().iter().cloned())
+            .collect()
+    }
+}
@@ -0,0 +1,376 @@
+use super::PROTOCOL_VERSION;
+
+use crate::client::{call_chat_completions, MessageRole};
+use crate::config::{list_agents, AgentDefinition, Config, GlobalConfig, Input};
+use crate::rag::Rag;
+use crate::utils::{create_abort_signal, IS_STDOUT_TERMINAL};
+
+use anyhow::{bail, Context, Result};
+use parking_lot::RwLock;
+use serde_json::{json, Value};
+use std::{fs, sync::Arc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::mpsc::unbounded_channel,
+};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const RAG_TOOL_PREFIX: &str = "search_";
+const AGENT_TOOL_PREFIX: &str = "agent_";
+const SESSION_URI_PREFIX: &str = "aichat://sessions/";
+const SESSION_MIME_TYPE: &str = "application/yaml";
+
+/// Publishes RAGs and agents as tools, roles as prompts and sessions as resources.
+#[derive(Debug)]
+pub struct McpServer {
+    config: Config,
+}
+
+impl McpServer {
+    pub fn new(config: &GlobalConfig) -> Self {
+        Self {
+            config: config.read().clone(),
+        }
+    }
+
+    /// Answers the JSON-RPC messages read from stdin until it is closed.
+    pub async fn run(config: GlobalConfig) -> Result<()> {
+        // Settle interactivity while stdout is still the client's pipe, so agents never prompt
+        let _ = *IS_STDOUT_TERMINAL;
+        let mut output = protocol_output()?;
+        let server = Arc::new(Self::new(&config));
+        let (tx, mut rx) = unbounded_channel::<Value>();
+        let writer = tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                output.write_all(format!("{message}\n").as_bytes()).await?;
+                output.flush().await?;
+            }
+            anyhow::Ok(())
+        });
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let message: Value = match serde_json::from_str(&line) {
+                Ok(v) => v,
+                Err(err) => {
+                    let _ = tx.send(error_response(Value::Null, PARSE_ERROR, &err.to_string()));
+                    continue;
+                }
+            };
+            debug!("mcp request: {message}");
+            let (server, tx) = (server.clone(), tx.clone());
+            tokio::spawn(async move {
+                if let Some(response) = server.handle(message).await {
+                    let _ = tx.send(response);
+                }
+            });
+        }
+        drop(tx);
+        writer.await?
+    }
+
+    /// Answers a request, or returns `None` for notifications and responses.
+    pub async fn handle(&self, message: Value) -> Option<Value> {
+        let id = message.get("id")?.clone();
+        let method = message["method"].as_str()?;
+        let params = &message["params"];
+        let result = match method {
+            "initialize" => Ok(json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": {
+                    "tools": {},
+                    "prompts": {},
+                    "resources": {},
+                },
+                "serverInfo": {
+                    "name": env!("CARGO_CRATE_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+            })),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": self.list_tools() })),
+            "tools/call" => self.call_tool(params).await,
+            "prompts/list" => Ok(json!({ "prompts": self.list_prompts() })),
+            "prompts/get" => self.get_prompt(params),
+            "resources/list" => Ok(json!({ "resources": self.list_resources() })),
+            "resources/read" => self.read_resource(params),
+            _ => {
+                let message = format!("Method not found: {method}");
+                return Some(error_response(id, METHOD_NOT_FOUND, &message));
+            }
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(err) => error_response(id, INVALID_PARAMS, &format!("{err:#}")),
+        };
+        Some(response)
+    }
+
+    fn list_tools(&self) -> Vec<Value> {
+        let mut tools = vec![];
+        for name in Config::list_rags() {
+            tools.push(json!({
+                "name": tool_name(RAG_TOOL_PREFIX, &name),
+                "description": format!("Search the documents of the RAG '{name}'"),
+                "inputSchema": {
+                    "type": "object",
+                    "properties": {
+                        "query": {
+                            "type": "string",
+                            "description": "The text to search for",
+                        },
+                    },
+                    "required": ["query"],
+                },
+            }));
+        }
+        for name in list_agents() {
+            let index_path = Config::agent_functions_dir(&name).join("index.yaml");
+            let description = match AgentDefinition::load(&index_path) {
+                Ok(v) if !v.description.is_empty() => v.description,
+                _ => format!("Ask the agent '{name}'"),
+            };
+            tools.push(json!({
+                "name": tool_name(AGENT_TOOL_PREFIX, &name),
+                "description": description,
+                "inputSchema": {
+                    "type": "object",
+                    "properties": {
+                        "prompt": {
+                            "type": "string",
+                            "description": "The message to send to the agent",
+                        },
+                        "session": {
+                            "type": "string",
+                            "description": "The session to continue, a one-off conversation if omitted",
+                        },
+                        "variables": {
+                            "type": "object",
+                            "description": "Values of the agent variables",
+                            "additionalProperties": { "type": "string" },
+                        },
+                    },
+                    "required": ["prompt"],
+                },
+            }));
+        }
+        tools
+    }
+
+    async fn call_tool(&self, params: &Value) -> Result<Value> {
+        let name = params["name"].as_str().context("Missing tool name")?;
+        let arguments = &params["arguments"];
+        let find = |prefix: &str, names: Vec<String>| {
+            names.into_iter().find(|v| tool_name(prefix, v) == name)
+        };
+        let ret = if let Some(rag) = find(RAG_TOOL_PREFIX, Config::list_rags()) {
+            let query = arguments["query"].as_str().context("Missing 'query'")?;
+            self.search_rag(&rag, query).await
+        } else if let Some(agent) = find(AGENT_TOOL_PREFIX, list_agents()) {
+            let prompt = arguments["prompt"].as_str().context("Missing 'prompt'")?;
+            let session = arguments["session"].as_str();
+            self.run_agent(&agent, prompt, session, &arguments["variables"])
+                .await
+        } else {
+            bail!("Unknown tool '{name}'");
+        };
+        let (text, is_error) = match ret {
+            Ok(text) => (text, false),
+            Err(err) => (format!("{err:#}"), true),
+        };
+        Ok(json!({
+            "content": [{ "type": "text", "text": text }],
+            "isError": is_error,
+        }))
+    }
+
+    async fn search_rag(&self, name: &str, query: &str) -> Result<String> {
+        let config = Arc::new(RwLock::new(self.config.clone()));
+        let rag_path = config.read().rag_file(name);
+        let rag = Rag::load(&config, name, &rag_path)?;
+        let (reranker_model, top_k) = rag.get_config();
+        let (embeddings, _) = rag
+            .search(
+                query,
+                top_k,
+                reranker_model.as_deref(),
+                create_abort_signal(),
+            )
+            .await?;
+        Ok(embeddings)
+    }
+
+    /// Runs a full agent turn, following tool calls until the model answers.
+    async fn run_agent(
+        &self,
+        name: &str,
+        prompt: &str,
+        session: Option<&str>,
+        variables: &Value,
+    ) -> Result<String> {
+        let config = Arc::new(RwLock::new(self.config.clone()));
+        let abort_signal = create_abort_signal();
+        if let Some(variables) = variables.as_object() {
+            config.write().agent_variables = Some(
+                variables
+                    .iter()
+                    .map(|(k, v)| match v {
+                        Value::String(v) => (k.clone(), v.clone()),
+                        v => (k.clone(), v.to_string()),
+                    })
+                    .collect(),
+            );
+        }
+        Config::use_agent(&config, name, session, abort_signal.clone()).await?;
+        let mut input = Input::from_str(&config, prompt, None);
+        input.use_embeddings(abort_signal.clone()).await?;
+        let output = loop {
+            let client = input.create_client()?;
+            config.write().before_chat_completion(&input)?;
+            let (output, tool_results) = call_chat_completions(
+                &mut input,
+                false,
+                false,
+                client.as_ref(),
+                abort_signal.clone(),
+            )
+            .await?;
+            config
+                .write()
+                .after_chat_completion(&input, &output, &tool_results)?;
+            if tool_results.is_empty() {
+                break output;
+            }
+            input = input.merge_tool_results(output, tool_results);
+        };
+        config.write().exit_session()?;
+        Ok(output)
+    }
+
+    fn list_prompts(&self) -> Vec<Value> {
+        Config::all_roles()
+            .iter()
+            .map(|role| {
+                json!({
+                    "name": role.name(),
+                    "arguments": [{
+                        "name": "input",
+                        "description": "The input to the role",
+                        "required": role.is_embedded_prompt(),
+                    }],
+                })
+            })
+            .collect()
+    }
+
+    fn get_prompt(&self, params: &Value) -> Result<Value> {
+        let name = params["name"].as_str().context("Missing prompt name")?;
+        let role = Config::all_roles()
+            .into_iter()
+            .find(|v| v.name() == name)
+            .with_context(|| format!("Unknown prompt '{name}'"))?;
+        let text = params["arguments"]["input"].as_str().unwrap_or_default();
+        let config = Arc::new(RwLock::new(self.config.clone()));
+        let input = Input::from_str(&config, text, Some(role.clone()));
+        // Prompts only carry user and assistant messages, so the system prompt goes first as user
+        let messages: Vec<_> = role
+            .build_messages(&input)
+            .into_iter()
+            .filter_map(|message| {
+                let text = message.content.to_text();
+                if text.is_empty() {
+                    return None;
+                }
+                let role = match message.role {
+                    MessageRole::Assistant => "assistant",
+                    _ => "user",
+                };
+                Some(json!({ "role": role, "content": { "type": "text", "text": text } }))
+            })
+            .collect();
+        Ok(json!({ "messages": messages }))
+    }
+
+    fn list_resources(&self) -> Vec<Value> {
+        self.config
+            .list_sessions()
+            .into_iter()
+            .map(|name| {
+                json!({
+                    "uri": format!("{SESSION_URI_PREFIX}{name}"),
+                    "name": name,
+                    "mimeType": SESSION_MIME_TYPE,
+                })
+            })
+            .collect()
+    }
+
+    fn read_resource(&self, params: &Value) -> Result<Value> {
+        let uri = params["uri"].as_str().context("Missing resource uri")?;
+        let name = uri
+            .strip_prefix(SESSION_URI_PREFIX)
+            .filter(|name| self.config.list_sessions().iter().any(|v| v == name))
+            .with_context(|| format!("Unknown resource '{uri}'"))?;
+        let path = self.config.session_file(name);
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read session at '{}'", path.display()))?;
+        Ok(json!({
+            "contents": [{ "uri": uri, "mimeType": SESSION_MIME_TYPE, "text": text }],
+        }))
+    }
+}
+
+/// Takes over stdout for the protocol and points fd 1 at stderr, so that whatever else aichat
+/// or the tools it runs print cannot corrupt the stream.
+#[cfg(unix)]
+fn protocol_output() -> Result<Box<dyn AsyncWrite + Send + Unpin>> {
+    use std::os::fd::FromRawFd;
+
+    let fd = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if fd < 0 {
+        bail!(
+            "Failed to duplicate stdout, {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        bail!(
+            "Failed to redirect stdout, {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    // SAFETY: `fd` is a fresh duplicate owned by nothing else
+    let file = unsafe { fs::File::from_raw_fd(fd) };
+    Ok(Box::new(tokio::fs::File::from_std(file)))
+}
+
+#[cfg(not(unix))]
+fn protocol_output() -> Result<Box<dyn AsyncWrite + Send + Unpin>> {
+    Ok(Box::new(tokio::io::stdout()))
+}
+
+fn tool_name(prefix: &str, name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{prefix}{name}")
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
@@ -67,7 +67,8 @@ impl Rag {
             bail!("Failed to init rag in non-interactive mode");
         }
         println!("⚙ Initializing RAG...");
-        let (embedding_model, chunk_size, chunk_overlap) = Self::create_config(config)?;
+        let (embedding_model, chunk_size, chunk_overlap, token_chunks) =
+            Self::create_config(config)?;
         let (reranker_model, top_k) = {
             let config = config.read();
             (config.rag_reranker_model.clone(), config.rag_top_k)
@@ -76,6 +77,7 @@ impl Rag {
             embedding_model.id(),
             chunk_size,
             chunk_overlap,
+            token_chunks,
             reranker_model,
             top_k,
             embedding_model.max_batch_size(),
@@ -149,12 +151,14 @@ impl Rag {
         Ok(())
     }
 
-    pub fn create_config(config: &GlobalConfig) -> Result<(Model, usize, usize)> {
-        let (embedding_model_id, chunk_size, chunk_overlap) = {
+    /// Returns the embedding model, the chunk size and overlap, and whether they count tokens.
+    pub fn create_config(config: &GlobalConfig) -> Result<(Model, usize, usize, bool)> {
+        let (embedding_model_id, chunk_size, chunk_tokens, chunk_overlap) = {
             let config = config.read();
             (
                 config.rag_embedding_model.clone(),
                 config.rag_chunk_size,
+                config.rag_chunk_tokens,
                 config.rag_chunk_overlap,
             )
         };
@@ -174,12 +178,17 @@ impl Rag {
         let embedding_model =
             Model::retrieve_model(&config.read(), &embedding_model_id, ModelType::Embedding)?;
 
-        let chunk_size = match chunk_size {
-            Some(value) => {
+        let token_chunks = chunk_tokens.is_some();
+        let chunk_size = match (chunk_tokens, chunk_size) {
+            (Some(value), _) => {
+                println!("Set chunk size: {value} tokens");
+                value
+            }
+            (None, Some(value)) => {
                 println!("Set chunk size: {value}");
                 value
             }
-            None => set_chunk_size(&embedding_model)?,
+            (None, None) => set_chunk_size(&embedding_model)?,
         };
         let chunk_overlap = match chunk_overlap {
             Some(value) => {
@@ -192,7 +201,7 @@ impl Rag {
             }
         };
 
-        Ok((embedding_model, chunk_size, chunk_overlap))
+        Ok((embedding_model, chunk_size, chunk_overlap, token_chunks))
     }
 
     pub fn get_config(&self) -> (Option<String>, usize) {
@@ -452,11 +461,7 @@ impl Rag {
                 .swap_remove(EXTENSION_METADATA)
                 .unwrap_or_else(|| DEFAULT_EXTENSION.into());
             let separator = get_separators(&extension);
-            let splitter = RecursiveCharacterTextSplitter::new(
-                self.data.chunk_size,
-                self.data.chunk_overlap,
-                &separator,
-            );
+            let splitter = self.splitter(&separator);
 
             let split_options = SplitterChunkHeaderOptions::default();
             let document = RagDocument::new(contents);
@@ -531,7 +536,7 @@ impl Rag {
             Some(model_id) => {
                 let model =
                     Model::retrieve_model(&self.config.read(), model_id, ModelType::Reranker)?;
-                let client = init_client(&self.config, Some(model), None)?;
+                let client = init_client(&self.config, Some(model))?;
                 let ids: IndexSet<DocumentId> = [vector_search_ids, keyword_search_ids]
                     .concat()
                     .into_iter()
@@ -574,17 +579,28 @@ impl Rag {
         Ok(output)
     }
 
+    /// Chunks are measured in tokens of the embedding model when created with `rag_chunk_tokens`.
+    fn splitter(&self, separators: &[&str]) -> RecursiveCharacterTextSplitter {
+        let splitter = RecursiveCharacterTextSplitter::new(
+            self.data.chunk_size,
+            self.data.chunk_overlap,
+            separators,
+        );
+        if self.data.token_chunks {
+            let model = self.embedding_model.clone();
+            splitter.with_length_function(move |text| model.count_tokens(text))
+        } else {
+            splitter
+        }
+    }
+
     async fn vector_search(
         &self,
         query: &str,
         top_k: usize,
         min_score: f32,
     ) -> Result<Vec<(DocumentId, f32)>> {
-        let splitter = RecursiveCharacterTextSplitter::new(
-            self.data.chunk_size,
-            self.data.chunk_overlap,
-            &DEFAULT_SEPARATES,
-        );
+        let splitter = self.splitter(&DEFAULT_SEPARATES);
         let texts = splitter.split_text(query);
         let embeddings_data = EmbeddingsData::new(texts, true);
         let embeddings = self.create_embeddings(embeddings_data, None).await?;
@@ -634,7 +650,7 @@ impl Rag {
         data: EmbeddingsData,
         spinner: Option<Spinner>,
     ) -> Result<EmbeddingsOutput> {
-        let embedding_client = init_client(&self.config, Some(self.embedding_model.clone()), None)?;
+        let embedding_client = init_client(&self.config, Some(self.embedding_model.clone()))?;
         let EmbeddingsData { texts, query } = data;
         let batch_size = self
             .data
@@ -694,6 +710,8 @@ pub struct RagData {
     pub embedding_model: String,
     pub chunk_size: usize,
     pub chunk_overlap: usize,
+    #[serde(default)]
+    pub token_chunks: bool,
     pub reranker_model: Option<String>,
     pub top_k: usize,
     pub batch_size: Option<usize>,
@@ -710,6 +728,7 @@ impl Debug for RagData {
             .field("embedding_model", &self.embedding_model)
             .field("chunk_size", &self.chunk_size)
             .field("chunk_overlap", &self.chunk_overlap)
+            .field("token_chunks", &self.token_chunks)
             .field("reranker_model", &self.reranker_model)
             .field("top_k", &self.top_k)
             .field("batch_size", &self.batch_size)
@@ -725,6 +744,7 @@ impl RagData {
         embedding_model: String,
         chunk_size: usize,
         chunk_overlap: usize,
+        token_chunks: bool,
         reranker_model: Option<String>,
         top_k: usize,
         batch_size: Option<usize>,
@@ -733,6 +753,7 @@ impl RagData {
             embedding_model,
             chunk_size,
             chunk_overlap,
+            token_chunks,
             reranker_model,
             top_k,
             batch_size,
@@ -994,10 +1015,7 @@ fn reciprocal_rank_fusion(
 ) -> Vec<DocumentId> {
     let rrf_k = top_k * 2;
     let mut map: IndexMap<DocumentId, f32> = IndexMap::new();
-    for (document_ids, weight) in list_of_document_ids
-        .into_iter()
-        .zip(list_of_weights.into_iter())
-    {
+    for (document_ids, weight) in list_of_document_ids.into_iter().zip(list_of_weights) {
         for (index, &item) in document_ids.iter().enumerate() {
             *map.entry(item).or_default() += (1.0 / ((rrf_k + index + 1) as f32)) * weight;
         }
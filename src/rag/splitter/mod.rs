@@ -69,6 +69,14 @@ impl RecursiveCharacterTextSplitter {
         self
     }
 
+    pub fn with_length_function(
+        mut self,
+        length_function: impl Fn(&str) -> usize + Send + Sync + 'static,
+    ) -> Self {
+        self.length_function = Box::new(length_function);
+        self
+    }
+
     pub fn split_documents(
         &self,
         documents: &[RagDocument],
@@ -307,6 +315,7 @@ fn split_on_separator<'a>(text: &'a str, separator: &str, keep_separator: bool)
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::count_tokens;
     use indexmap::IndexMap;
     use pretty_assertions::assert_eq;
     use serde_json::{json, Value};
@@ -326,6 +335,20 @@ mod tests {
         assert_eq!(output, vec!["foo bar", "bar baz", "baz 123"]);
     }
 
+    #[test]
+    fn test_split_text_by_tokens() {
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(20);
+        let splitter = RecursiveCharacterTextSplitter::new(30, 0, &DEFAULT_SEPARATES)
+            .with_length_function(|text| count_tokens(Some("cl100k_base"), text));
+        let output = splitter.split_text(&text);
+        assert!(output.len() > 1);
+        for chunk in &output {
+            assert!(count_tokens(Some("cl100k_base"), chunk) <= 30);
+        }
+        let by_bytes = RecursiveCharacterTextSplitter::new(30, 0, &DEFAULT_SEPARATES);
+        assert!(by_bytes.split_text(&text).len() > output.len());
+    }
+
     #[test]
     fn test_create_document() {
         let splitter = RecursiveCharacterTextSplitter::new(3, 0, &[" "]);
@@ -4,23 +4,74 @@ mod stream;
 pub use self::markdown::{MarkdownRender, RenderOptions};
 use self::stream::{markdown_stream, raw_stream};
 
-use crate::utils::{error_text, pretty_error, AbortSignal, IS_STDOUT_TERMINAL};
+use crate::utils::{
+    error_text, estimate_token_length, pretty_error, AbortSignal, IS_STDOUT_TERMINAL,
+};
 use crate::{client::SseEvent, config::GlobalConfig};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 use tokio::sync::mpsc::UnboundedReceiver;
 
+/// How the reasoning of thinking models is rendered in the terminal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningDisplay {
+    #[default]
+    Show,
+    Collapse,
+    Hide,
+}
+
+impl FromStr for ReasoningDisplay {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "show" => Ok(Self::Show),
+            "collapse" => Ok(Self::Collapse),
+            "hide" => Ok(Self::Hide),
+            _ => bail!("Invalid reasoning display '{s}', expected one of show, collapse, hide"),
+        }
+    }
+}
+
+impl std::fmt::Display for ReasoningDisplay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            Self::Show => "show",
+            Self::Collapse => "collapse",
+            Self::Hide => "hide",
+        };
+        write!(f, "{value}")
+    }
+}
+
+/// Returns the text that stands for the reasoning ahead of the answer, if any.
+pub fn render_reasoning(display: ReasoningDisplay, reasoning: &str) -> Option<String> {
+    match display {
+        ReasoningDisplay::Show => Some(format!("<think>\n{reasoning}\n</think>\n\n")),
+        ReasoningDisplay::Collapse => Some(format!(
+            "> Reasoning collapsed (~{} tokens)\n\n",
+            estimate_token_length(reasoning)
+        )),
+        ReasoningDisplay::Hide => None,
+    }
+}
+
 pub async fn render_stream(
     rx: UnboundedReceiver<SseEvent>,
     config: &GlobalConfig,
     abort_signal: AbortSignal,
 ) -> Result<()> {
+    let reasoning_display = config.read().reasoning_display;
     let ret = if *IS_STDOUT_TERMINAL && config.read().highlight {
         let render_options = config.read().render_options()?;
         let mut render = MarkdownRender::init(render_options)?;
-        markdown_stream(rx, &mut render, &abort_signal).await
+        markdown_stream(rx, &mut render, reasoning_display, &abort_signal).await
     } else {
-        raw_stream(rx, &abort_signal).await
+        raw_stream(rx, reasoning_display, &abort_signal).await
     };
     ret.map_err(|err| err.context("Failed to reader stream"))
 }
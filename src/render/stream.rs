@@ -1,4 +1,4 @@
-use super::{MarkdownRender, SseEvent};
+use super::{render_reasoning, MarkdownRender, ReasoningDisplay, SseEvent};
 
 use crate::utils::{poll_abort_signal, spawn_spinner, AbortSignal};
 
@@ -17,12 +17,14 @@ use tokio::sync::mpsc::UnboundedReceiver;
 pub async fn markdown_stream(
     rx: UnboundedReceiver<SseEvent>,
     render: &mut MarkdownRender,
+    reasoning_display: ReasoningDisplay,
     abort_signal: &AbortSignal,
 ) -> Result<()> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
 
-    let ret = markdown_stream_inner(rx, render, abort_signal, &mut stdout).await;
+    let mut reasoning = ReasoningView::new(reasoning_display);
+    let ret = markdown_stream_inner(rx, render, &mut reasoning, abort_signal, &mut stdout).await;
 
     disable_raw_mode()?;
 
@@ -34,25 +36,39 @@ pub async fn markdown_stream(
 
 pub async fn raw_stream(
     mut rx: UnboundedReceiver<SseEvent>,
+    reasoning_display: ReasoningDisplay,
     abort_signal: &AbortSignal,
 ) -> Result<()> {
     let mut spinner = Some(spawn_spinner("Generating"));
+    let mut reasoning = ReasoningView::new(reasoning_display);
 
     loop {
         if abort_signal.aborted() {
             break;
         }
         if let Some(evt) = rx.recv().await {
-            if let Some(spinner) = spinner.take() {
-                spinner.stop();
+            if !matches!(evt, SseEvent::Reasoning(_)) || reasoning.is_shown() {
+                if let Some(spinner) = spinner.take() {
+                    spinner.stop();
+                }
             }
 
             match evt {
                 SseEvent::Text(text) => {
-                    print!("{text}");
+                    let head = reasoning.finish().unwrap_or_default();
+                    print!("{head}{text}");
                     stdout().flush()?;
                 }
+                SseEvent::Reasoning(text) => {
+                    if let Some(text) = reasoning.push(&text) {
+                        print!("{text}");
+                        stdout().flush()?;
+                    }
+                }
                 SseEvent::Done => {
+                    if let Some(text) = reasoning.finish() {
+                        print!("{text}");
+                    }
                     break;
                 }
             }
@@ -67,6 +83,7 @@ pub async fn raw_stream(
 async fn markdown_stream_inner(
     mut rx: UnboundedReceiver<SseEvent>,
     render: &mut MarkdownRender,
+    reasoning: &mut ReasoningView,
     abort_signal: &AbortSignal,
     writer: &mut Stdout,
 ) -> Result<()> {
@@ -81,7 +98,7 @@ async fn markdown_stream_inner(
         if abort_signal.aborted() {
             break;
         }
-        for reply_event in gather_events(&mut rx).await {
+        for reply_event in gather_events(&mut rx, reasoning).await {
             if let Some(spinner) = spinner.take() {
                 spinner.stop();
             }
@@ -145,6 +162,7 @@ async fn markdown_stream_inner(
 
                     writer.flush()?;
                 }
+                SseEvent::Reasoning(_) => {}
                 SseEvent::Done => {
                     break 'outer;
                 }
@@ -162,15 +180,24 @@ async fn markdown_stream_inner(
     Ok(())
 }
 
-async fn gather_events(rx: &mut UnboundedReceiver<SseEvent>) -> Vec<SseEvent> {
+/// Merges the pending events into text, with the reasoning rendered per `ReasoningView`.
+async fn gather_events(
+    rx: &mut UnboundedReceiver<SseEvent>,
+    reasoning: &mut ReasoningView,
+) -> Vec<SseEvent> {
     let mut texts = vec![];
     let mut done = false;
     tokio::select! {
         _ = async {
             while let Some(reply_event) = rx.recv().await {
                 match reply_event {
-                    SseEvent::Text(v) => texts.push(v),
+                    SseEvent::Text(v) => {
+                        texts.extend(reasoning.finish());
+                        texts.push(v);
+                    }
+                    SseEvent::Reasoning(v) => texts.extend(reasoning.push(&v)),
                     SseEvent::Done => {
+                        texts.extend(reasoning.finish());
                         done = true;
                         break;
                     }
@@ -189,6 +216,47 @@ async fn gather_events(rx: &mut UnboundedReceiver<SseEvent>) -> Vec<SseEvent> {
     events
 }
 
+/// Tracks the reasoning of a streaming reply and yields the text that represents it.
+struct ReasoningView {
+    display: ReasoningDisplay,
+    reasoning: String,
+}
+
+impl ReasoningView {
+    fn new(display: ReasoningDisplay) -> Self {
+        Self {
+            display,
+            reasoning: String::new(),
+        }
+    }
+
+    fn is_shown(&self) -> bool {
+        self.display == ReasoningDisplay::Show
+    }
+
+    fn push(&mut self, text: &str) -> Option<String> {
+        let first = self.reasoning.is_empty();
+        self.reasoning.push_str(text);
+        match self.display {
+            ReasoningDisplay::Show if first => Some(format!("<think>\n{text}")),
+            ReasoningDisplay::Show => Some(text.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Closes the reasoning once the answer starts or the reply ends.
+    fn finish(&mut self) -> Option<String> {
+        if self.reasoning.is_empty() {
+            return None;
+        }
+        let reasoning = std::mem::take(&mut self.reasoning);
+        match self.display {
+            ReasoningDisplay::Show => Some("\n</think>\n\n".to_string()),
+            display => render_reasoning(display, &reasoning),
+        }
+    }
+}
+
 fn print_block(writer: &mut Stdout, text: &str, columns: u16) -> Result<u16> {
     let mut num = 0;
     for line in text.split('\n') {
@@ -215,3 +283,26 @@ fn need_rows(text: &str, columns: u16) -> u16 {
     let buffer_width = display_width(text).max(1) as u16;
     buffer_width.div_ceil(columns)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reasoning_view() {
+        let mut view = ReasoningView::new(ReasoningDisplay::Show);
+        assert_eq!(view.finish(), None);
+        assert_eq!(view.push("a").as_deref(), Some("<think>\na"));
+        assert_eq!(view.push("b").as_deref(), Some("b"));
+        assert_eq!(view.finish().as_deref(), Some("\n</think>\n\n"));
+        assert_eq!(view.finish(), None);
+
+        let mut view = ReasoningView::new(ReasoningDisplay::Collapse);
+        assert_eq!(view.push("let me think"), None);
+        assert!(view.finish().unwrap().starts_with("> Reasoning collapsed"));
+
+        let mut view = ReasoningView::new(ReasoningDisplay::Hide);
+        assert_eq!(view.push("let me think"), None);
+        assert_eq!(view.finish(), None);
+    }
+}
@@ -6,7 +6,9 @@ use self::completer::ReplCompleter;
 use self::highlighter::ReplHighlighter;
 use self::prompt::ReplPrompt;
 
-use crate::client::{call_chat_completions, call_chat_completions_streaming};
+use crate::client::{
+    call_chat_completions, call_chat_completions_streaming, call_structured_output,
+};
 use crate::config::{
     macro_execute, AgentVariables, AssertState, Config, GlobalConfig, Input, LastMessage,
     StateFlags,
@@ -31,7 +33,7 @@ use std::{env, process};
 
 const MENU_NAME: &str = "completion_menu";
 
-static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
+static REPL_COMMANDS: LazyLock<[ReplCommand; 37]> = LazyLock::new(|| {
     [
         ReplCommand::new(".help", "Show this help guide", AssertState::pass()),
         ReplCommand::new(".info", "Show system info", AssertState::pass()),
@@ -176,6 +178,7 @@ static REPL_COMMANDS: LazyLock<[ReplCommand; 36]> = LazyLock::new(|| {
             "Regenerate last response",
             AssertState::pass(),
         ),
+        ReplCommand::new(".usage", "Show usage and cost", AssertState::pass()),
         ReplCommand::new(".copy", "Copy last response", AssertState::pass()),
         ReplCommand::new(".set", "Modify runtime settings", AssertState::pass()),
         ReplCommand::new(
@@ -660,6 +663,10 @@ pub async fn run_repl_command(
                     println!("Usage: .delete <role|session|rag|macro|agent-data>")
                 }
             },
+            ".usage" => {
+                let report = Config::usage_report(args)?;
+                println!("{report}");
+            }
             ".copy" => {
                 let output = match config
                     .read()
@@ -735,10 +742,12 @@ async fn ask(
 
     let client = input.create_client()?;
     config.write().before_chat_completion(&input)?;
-    let (output, tool_results) = if input.stream() {
-        call_chat_completions_streaming(&input, client.as_ref(), abort_signal.clone()).await?
+    let (output, tool_results) = if input.role().schema().is_some() {
+        call_structured_output(&mut input, client.as_ref(), abort_signal.clone()).await?
+    } else if input.stream() {
+        call_chat_completions_streaming(&mut input, client.as_ref(), abort_signal.clone()).await?
     } else {
-        call_chat_completions(&input, true, false, client.as_ref(), abort_signal.clone()).await?
+        call_chat_completions(&mut input, true, false, client.as_ref(), abort_signal.clone()).await?
     };
     config
         .write()
@@ -273,6 +273,7 @@ impl Server {
             temperature,
             top_p,
             max_tokens,
+            reasoning_effort,
             stream,
             tools,
         } = req_body;
@@ -300,7 +301,7 @@ impl Server {
             config.write().set_model(&model_name)?;
         }
 
-        let mut client = init_client(&config, None, None)?;
+        let mut client = init_client(&config, None)?;
         if max_tokens.is_some() {
             client.model_mut().set_max_tokens(max_tokens, true);
         }
@@ -312,12 +313,18 @@ impl Server {
 
         patch_messages(&mut messages, client.model());
 
+        let input_tokens = client.model().total_tokens(&messages) as u64;
+        let cost = calculate_cost(client.model(), input_tokens, 0).unwrap_or_default();
+        config.read().guard_budgets(None, cost)?;
+
         let data: ChatCompletionsData = ChatCompletionsData {
             messages,
             temperature,
             top_p,
             functions,
             stream,
+            schema: None,
+            reasoning: reasoning_effort.filter(|_| client.model().supports_reasoning()),
         };
 
         if stream {
@@ -340,6 +347,9 @@ impl Server {
                             SseEvent::Text(text) => {
                                 let _ = tx.send(ResEvent::Text(text));
                             }
+                            SseEvent::Reasoning(text) => {
+                                let _ = tx.send(ResEvent::Reasoning(text));
+                            }
                             SseEvent::Done => {
                                 let _ = tx.send(ResEvent::Done);
                                 sse_rx.close();
@@ -354,17 +364,25 @@ impl Server {
                     mut data: ChatCompletionsData,
                     tx: &UnboundedSender<ResEvent>,
                     is_first: Arc<AtomicBool>,
+                    input_tokens: u64,
                 ) {
                     if client.model().no_stream() {
                         data.stream = false;
                         let ret = client.chat_completions_inner(http_client, data).await;
                         match ret {
                             Ok(output) => {
+                                record_usage(client.model(), input_tokens, &output);
                                 let ChatCompletionsOutput {
-                                    text, tool_calls, ..
+                                    text,
+                                    reasoning,
+                                    tool_calls,
+                                    ..
                                 } = output;
                                 let _ = tx.send(ResEvent::First(None));
                                 is_first.store(false, Ordering::SeqCst);
+                                if let Some(reasoning) = reasoning {
+                                    let _ = tx.send(ResEvent::Reasoning(reasoning));
+                                }
                                 let _ = tx.send(ResEvent::Text(text));
                                 if !tool_calls.is_empty() {
                                     let _ = tx.send(ResEvent::ToolCalls(tool_calls));
@@ -380,7 +398,21 @@ impl Server {
                             .chat_completions_streaming_inner(http_client, handler, data)
                             .await;
                         let first = match ret {
-                            Ok(()) => None,
+                            Ok(()) => {
+                                let output_tokens = estimate_output_tokens(
+                                    client.model(),
+                                    &format!("{}{}", handler.reasoning_buffer(), handler.buffer()),
+                                    handler.tool_calls(),
+                                );
+                                UsageRecord::new(
+                                    "chat",
+                                    client.model(),
+                                    input_tokens,
+                                    output_tokens,
+                                )
+                                .save();
+                                None
+                            }
                             Err(err) => Some(format!("{err:?}")),
                         };
                         if is_first.load(Ordering::SeqCst) {
@@ -402,7 +434,8 @@ impl Server {
                         &mut handler,
                         data,
                         &tx,
-                        is_first
+                        is_first,
+                        input_tokens,
                     ),
                 );
             });
@@ -424,6 +457,12 @@ impl Server {
                         ResEvent::Text(text) => {
                             Some(Ok(create_text_frame(completion_id, model, *created, &text)))
                         }
+                        ResEvent::Reasoning(text) => Some(Ok(create_reasoning_frame(
+                            completion_id,
+                            model,
+                            *created,
+                            &text,
+                        ))),
                         ResEvent::ToolCalls(tool_calls) => {
                             has_tool_calls.store(true, Ordering::SeqCst);
                             Some(Ok(create_tool_calls_frame(
@@ -452,6 +491,7 @@ impl Server {
             Ok(res)
         } else {
             let output = client.chat_completions_inner(&http_client, data).await?;
+            record_usage(client.model(), input_tokens, &output);
             let res = Response::builder()
                 .header("Content-Type", "application/json")
                 .body(
@@ -490,7 +530,7 @@ impl Server {
             EmbeddingsReqBodyInput::Single(v) => vec![v],
             EmbeddingsReqBodyInput::Multiple(v) => v,
         };
-        let client = init_client(&config, Some(embedding_model), None)?;
+        let client = init_client(&config, Some(embedding_model))?;
         let data = client
             .embeddings(&EmbeddingsData {
                 query: false,
@@ -546,7 +586,7 @@ impl Server {
         let reranker_model =
             Model::retrieve_model(&config.read(), &reranker_model_id, ModelType::Reranker)?;
 
-        let client = init_client(&config, Some(reranker_model), None)?;
+        let client = init_client(&config, Some(reranker_model))?;
         let data = client
             .rerank(&RerankData {
                 query,
@@ -589,6 +629,7 @@ struct ChatCompletionsReqBody {
     temperature: Option<f64>,
     top_p: Option<f64>,
     max_tokens: Option<isize>,
+    reasoning_effort: Option<Reasoning>,
     #[serde(default)]
     stream: bool,
     tools: Option<Vec<Value>>,
@@ -619,6 +660,7 @@ struct RerankReqBody {
 enum ResEvent {
     First(Option<String>),
     Text(String),
+    Reasoning(String),
     ToolCalls(Vec<ToolCall>),
     Done,
 }
@@ -664,6 +706,17 @@ fn create_text_frame(id: &str, model: &str, created: i64, content: &str) -> Fram
     Frame::data(Bytes::from(format!("data: {value}\n\n")))
 }
 
+/// Streams reasoning as `reasoning_content` so that it stays out of the answer content.
+fn create_reasoning_frame(id: &str, model: &str, created: i64, reasoning: &str) -> Frame<Bytes> {
+    let choice = json!({
+        "index": 0,
+        "delta": { "role": "assistant", "reasoning_content": reasoning },
+        "finish_reason": null,
+    });
+    let value = build_chat_completion_chunk_json(id, model, created, &choice);
+    Frame::data(Bytes::from(format!("data: {value}\n\n")))
+}
+
 fn create_tool_calls_frame(
     id: &str,
     model: &str,
@@ -744,7 +797,7 @@ fn ret_non_stream(id: &str, model: &str, created: i64, output: &ChatCompletionsO
     let input_tokens = output.input_tokens.unwrap_or_default();
     let output_tokens = output.output_tokens.unwrap_or_default();
     let total_tokens = input_tokens + output_tokens;
-    let choice = if output.tool_calls.is_empty() {
+    let mut choice = if output.tool_calls.is_empty() {
         json!({
             "index": 0,
             "message": {
@@ -785,6 +838,9 @@ fn ret_non_stream(id: &str, model: &str, created: i64, output: &ChatCompletionsO
             "finish_reason": "tool_calls",
         })
     };
+    if let Some(reasoning) = &output.reasoning {
+        choice["message"]["reasoning_content"] = reasoning.clone().into();
+    }
     let res_body = json!({
         "id": id,
         "object": "chat.completion",
@@ -800,6 +856,26 @@ fn ret_non_stream(id: &str, model: &str, created: i64, output: &ChatCompletionsO
     Bytes::from(res_body.to_string())
 }
 
+fn record_usage(model: &Model, input_tokens: u64, output: &ChatCompletionsOutput) {
+    let output_tokens = output
+        .output_tokens
+        .unwrap_or_else(|| estimate_output_tokens(model, &output.text, &output.tool_calls));
+    let mut record = UsageRecord::new(
+        "chat",
+        model,
+        output.input_tokens.unwrap_or(input_tokens),
+        output_tokens,
+    );
+    if output.cache_creation_input_tokens.is_some() || output.cache_read_input_tokens.is_some() {
+        record.set_cache_tokens(
+            model,
+            output.cache_creation_input_tokens.unwrap_or_default(),
+            output.cache_read_input_tokens.unwrap_or_default(),
+        );
+    }
+    record.save();
+}
+
 fn ret_err<T: std::fmt::Display>(err: T) -> AppResponse {
     let data = json!({
         "error": {
@@ -882,7 +958,7 @@ fn parse_messages(message: Vec<Value>) -> Result<Vec<Message>> {
                     if tool_calls.len() == tool_values.len() {
                         let mut list = vec![];
                         for ((id, name, arguments), (value, tool_call_id)) in
-                            tool_calls.into_iter().zip(tool_values.into_iter())
+                            tool_calls.into_iter().zip(tool_values)
                         {
                             if id != tool_call_id {
                                 return Err(err());
@@ -0,0 +1,222 @@
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+
+/// Validates a value against the commonly used subset of JSON Schema: `type`, `enum`, `const`,
+/// `properties`, `required`, `additionalProperties`, `items`, length and range limits, and
+/// `anyOf`/`oneOf`/`allOf`. Unsupported keywords are ignored.
+pub fn validate_json_schema(schema: &Value, value: &Value) -> Result<()> {
+    let mut errors = vec![];
+    validate(schema, value, "$", &mut errors);
+    if !errors.is_empty() {
+        bail!("{}", errors.join("; "));
+    }
+    Ok(())
+}
+
+fn validate(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let schema = match schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => {
+            errors.push(format!("{path}: is not allowed"));
+            return;
+        }
+        Value::Object(v) => v,
+        _ => return,
+    };
+
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::String(v) => vec![v.as_str()],
+            Value::Array(list) => list.iter().filter_map(|v| v.as_str()).collect(),
+            _ => vec![],
+        };
+        if !types.is_empty() && !types.iter().any(|v| is_type(value, v)) {
+            errors.push(format!(
+                "{path}: expected {}, got {}",
+                types.join(" or "),
+                type_name(value)
+            ));
+            return;
+        }
+    }
+    if let Some(list) = schema.get("enum").and_then(|v| v.as_array()) {
+        if !list.contains(value) {
+            errors.push(format!(
+                "{path}: must be one of {}",
+                Value::Array(list.clone())
+            ));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            errors.push(format!("{path}: must be {expected}"));
+        }
+    }
+
+    match value {
+        Value::Object(map) => validate_object(schema, map, path, errors),
+        Value::Array(list) => validate_array(schema, list, path, errors),
+        Value::String(text) => {
+            let len = text.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(|v| v.as_u64()) {
+                if len < min {
+                    errors.push(format!("{path}: must be at least {min} characters"));
+                }
+            }
+            if let Some(max) = schema.get("maxLength").and_then(|v| v.as_u64()) {
+                if len > max {
+                    errors.push(format!("{path}: must be at most {max} characters"));
+                }
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if let Some(min) = schema.get("minimum").and_then(|v| v.as_f64()) {
+                if number < min {
+                    errors.push(format!("{path}: must be >= {min}"));
+                }
+            }
+            if let Some(max) = schema.get("maximum").and_then(|v| v.as_f64()) {
+                if number > max {
+                    errors.push(format!("{path}: must be <= {max}"));
+                }
+            }
+            if let Some(min) = schema.get("exclusiveMinimum").and_then(|v| v.as_f64()) {
+                if number <= min {
+                    errors.push(format!("{path}: must be > {min}"));
+                }
+            }
+            if let Some(max) = schema.get("exclusiveMaximum").and_then(|v| v.as_f64()) {
+                if number >= max {
+                    errors.push(format!("{path}: must be < {max}"));
+                }
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(list) = schema.get("allOf").and_then(|v| v.as_array()) {
+        for item in list {
+            validate(item, value, path, errors);
+        }
+    }
+    for (key, exactly_one) in [("anyOf", false), ("oneOf", true)] {
+        if let Some(list) = schema.get(key).and_then(|v| v.as_array()) {
+            let matched = list
+                .iter()
+                .filter(|item| {
+                    let mut item_errors = vec![];
+                    validate(item, value, path, &mut item_errors);
+                    item_errors.is_empty()
+                })
+                .count();
+            if matched == 0 || (exactly_one && matched > 1) {
+                errors.push(format!("{path}: does not match {key}"));
+            }
+        }
+    }
+}
+
+fn validate_object(
+    schema: &Map<String, Value>,
+    map: &Map<String, Value>,
+    path: &str,
+    errors: &mut Vec<String>,
+) {
+    if let Some(required) = schema.get("required").and_then(|v| v.as_array()) {
+        for key in required.iter().filter_map(|v| v.as_str()) {
+            if !map.contains_key(key) {
+                errors.push(format!("{path}: missing required property '{key}'"));
+            }
+        }
+    }
+    let properties = schema.get("properties").and_then(|v| v.as_object());
+    let additional = schema.get("additionalProperties");
+    for (key, item) in map {
+        let item_path = format!("{path}.{key}");
+        match properties.and_then(|v| v.get(key)) {
+            Some(item_schema) => validate(item_schema, item, &item_path, errors),
+            None => match additional {
+                Some(Value::Bool(false)) => {
+                    errors.push(format!("{path}: unexpected property '{key}'"))
+                }
+                Some(item_schema) => validate(item_schema, item, &item_path, errors),
+                None => {}
+            },
+        }
+    }
+}
+
+fn validate_array(
+    schema: &Map<String, Value>,
+    list: &[Value],
+    path: &str,
+    errors: &mut Vec<String>,
+) {
+    let len = list.len() as u64;
+    if let Some(min) = schema.get("minItems").and_then(|v| v.as_u64()) {
+        if len < min {
+            errors.push(format!("{path}: must have at least {min} items"));
+        }
+    }
+    if let Some(max) = schema.get("maxItems").and_then(|v| v.as_u64()) {
+        if len > max {
+            errors.push(format!("{path}: must have at most {max} items"));
+        }
+    }
+    if let Some(item_schema) = schema.get("items") {
+        for (i, item) in list.iter().enumerate() {
+            validate(item_schema, item, &format!("{path}[{i}]"), errors);
+        }
+    }
+}
+
+fn is_type(value: &Value, name: &str) -> bool {
+    match name {
+        "integer" => value.as_i64().is_some() || value.as_u64().is_some(),
+        "number" => value.is_number(),
+        _ => type_name(value) == name,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_json_schema() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string", "minLength": 1 },
+                "age": { "type": "integer", "minimum": 0 },
+                "tags": { "type": "array", "items": { "enum": ["a", "b"] } },
+            },
+            "required": ["name"],
+            "additionalProperties": false,
+        });
+        assert!(validate_json_schema(&schema, &json!({"name": "x", "tags": ["a"]})).is_ok());
+        let err = validate_json_schema(&schema, &json!({"age": 1.5, "tags": ["c"], "extra": true}))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("$: missing required property 'name'"));
+        assert!(err.contains("$.age: expected integer, got number"));
+        assert!(err.contains("$.tags[0]: must be one of [\"a\",\"b\"]"));
+        assert!(err.contains("$: unexpected property 'extra'"));
+        let schema = json!({ "anyOf": [{ "type": "string" }, { "type": "null" }] });
+        assert!(validate_json_schema(&schema, &json!(null)).is_ok());
+        assert!(validate_json_schema(&schema, &json!(1)).is_err());
+    }
+}
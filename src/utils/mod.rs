@@ -4,11 +4,13 @@ mod command;
 mod crypto;
 mod html_to_md;
 mod input;
+mod json_schema;
 mod loader;
 mod path;
 mod render_prompt;
 mod request;
 mod spinner;
+mod tokenizer;
 mod variables;
 
 
@@ -19,11 +21,13 @@ pub use self::command::*;
 pub use self::crypto::*;
 pub use self::html_to_md::*;
 pub use self::input::*;
+pub use self::json_schema::validate_json_schema;
 pub use self::loader::*;
 pub use self::path::*;
 pub use self::render_prompt::render_prompt;
 pub use self::request::*;
 pub use self::spinner::*;
+pub use self::tokenizer::count_tokens;
 pub use self::variables::*;
 
 use anyhow::{Context, Result};
@@ -122,7 +126,7 @@ where
             Some((v, score))
         })
         .collect();
-    list.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+    list.sort_unstable_by_key(|v| std::cmp::Reverse(v.1));
     list.into_iter().map(|(v, _)| v).collect()
 }
 
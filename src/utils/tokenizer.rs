@@ -0,0 +1,220 @@
+use super::estimate_token_length;
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use fancy_regex::Regex;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::OnceLock;
+
+/// Vocabularies in the tiktoken format, e.g. `cl100k_base.tiktoken`.
+#[cfg(feature = "tokenizers")]
+#[derive(rust_embed::Embed)]
+#[folder = "assets/tokenizers/"]
+struct TokenizersAsset;
+
+const CL100K_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+const O200K_PATTERN: &str = concat!(
+    r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|\p{N}{1,3}",
+    r"| ?[^\s\p{L}\p{N}]+[\r\n/]*",
+    r"|\s*[\r\n]+",
+    r"|\s+(?!\S)",
+    r"|\s+",
+);
+
+static CL100K: OnceLock<Option<Bpe>> = OnceLock::new();
+static O200K: OnceLock<Option<Bpe>> = OnceLock::new();
+
+/// Counts tokens with the named tokenizer, falling back to the heuristic estimate
+/// when the tokenizer is unknown or its vocabulary is unavailable, e.g. in builds
+/// without the `tokenizers` feature.
+pub fn count_tokens(tokenizer: Option<&str>, text: &str) -> usize {
+    match tokenizer.and_then(get_bpe) {
+        Some(bpe) => bpe.count(text),
+        None => estimate_token_length(text),
+    }
+}
+
+fn get_bpe(name: &str) -> Option<&'static Bpe> {
+    if !cfg!(feature = "tokenizers") {
+        return None;
+    }
+    let (cell, pattern) = match name {
+        "cl100k_base" => (&CL100K, CL100K_PATTERN),
+        "o200k_base" => (&O200K, O200K_PATTERN),
+        _ => {
+            debug!("Unknown tokenizer '{name}', estimate tokens instead");
+            return None;
+        }
+    };
+    cell.get_or_init(|| match load_bpe(name, pattern) {
+        Ok(v) => Some(v),
+        Err(err) => {
+            warn!("{err}, estimate tokens instead");
+            None
+        }
+    })
+    .as_ref()
+}
+
+fn load_bpe(name: &str, pattern: &str) -> Result<Bpe> {
+    let data =
+        load_vocab(name).ok_or_else(|| anyhow!("Missing vocabulary of tokenizer '{name}'"))?;
+    let vocab = std::str::from_utf8(&data)
+        .with_context(|| format!("Invalid vocabulary of tokenizer '{name}'"))?;
+    Bpe::new(vocab, pattern).with_context(|| format!("Failed to load tokenizer '{name}'"))
+}
+
+#[cfg(feature = "tokenizers")]
+fn load_vocab(name: &str) -> Option<Cow<'static, [u8]>> {
+    TokenizersAsset::get(&format!("{name}.tiktoken")).map(|file| file.data)
+}
+
+#[cfg(not(feature = "tokenizers"))]
+fn load_vocab(_name: &str) -> Option<Cow<'static, [u8]>> {
+    None
+}
+
+/// A byte-level BPE tokenizer compatible with tiktoken vocabularies.
+#[derive(Debug)]
+pub struct Bpe {
+    ranks: HashMap<Vec<u8>, u32>,
+    pattern: Regex,
+}
+
+impl Bpe {
+    pub fn new(vocab: &str, pattern: &str) -> Result<Self> {
+        let mut ranks = HashMap::new();
+        for (i, line) in vocab.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (token, rank) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("Invalid vocabulary line {}", i + 1))?;
+            let token = STANDARD
+                .decode(token)
+                .with_context(|| format!("Invalid vocabulary line {}", i + 1))?;
+            let rank = rank
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid vocabulary line {}", i + 1))?;
+            ranks.insert(token, rank);
+        }
+        let pattern = Regex::new(pattern)?;
+        Ok(Self { ranks, pattern })
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        self.pattern
+            .find_iter(text)
+            .filter_map(|v| v.ok())
+            .map(|v| {
+                let piece = v.as_str().as_bytes();
+                if self.ranks.contains_key(piece) {
+                    1
+                } else {
+                    self.merge(piece)
+                }
+            })
+            .sum()
+    }
+
+    /// Merges the lowest-ranked adjacent pair, the leftmost on ties, until none is left and
+    /// returns the number of parts. Candidate pairs wait in a heap, outdated ones are skipped.
+    fn merge(&self, piece: &[u8]) -> usize {
+        let len = piece.len();
+        // Parts are identified by their start offset and linked to their neighbors
+        let mut next: Vec<usize> = (1..=len).collect();
+        let mut prev: Vec<usize> = (0..len).map(|v| v.wrapping_sub(1)).collect();
+        let mut merged = vec![false; len];
+        let mut heap = BinaryHeap::new();
+        let push = |heap: &mut BinaryHeap<_>, next: &[usize], start: usize| {
+            let mid = next[start];
+            if mid < len {
+                let end = next[mid];
+                if let Some(rank) = self.ranks.get(&piece[start..end]) {
+                    heap.push(Reverse((*rank, start, end)));
+                }
+            }
+        };
+        for start in 0..len {
+            push(&mut heap, &next, start);
+        }
+        let mut parts = len;
+        while let Some(Reverse((_, start, end))) = heap.pop() {
+            let mid = next[start];
+            if merged[start] || mid == len || next[mid] != end {
+                continue;
+            }
+            merged[mid] = true;
+            next[start] = end;
+            if end < len {
+                prev[end] = start;
+            }
+            parts -= 1;
+            if start > 0 {
+                push(&mut heap, &next, prev[start]);
+            }
+            push(&mut heap, &next, start);
+        }
+        parts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vocab() -> String {
+        let mut tokens: Vec<Vec<u8>> = (0..=255u8).map(|v| vec![v]).collect();
+        tokens
+            .extend(["he", "ll", "hell", "hello", " w", "or", " wor", " world"].map(|v| v.into()));
+        tokens
+            .iter()
+            .enumerate()
+            .map(|(i, v)| format!("{} {i}", STANDARD.encode(v)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_bpe_count() {
+        let bpe = Bpe::new(&vocab(), CL100K_PATTERN).unwrap();
+        assert_eq!(bpe.count("hello world"), 2);
+        assert_eq!(bpe.count("hellx"), 2);
+        assert_eq!(bpe.count("hello  world!"), 4);
+        assert_eq!(bpe.count(""), 0);
+        assert!(Bpe::new(&vocab(), O200K_PATTERN).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "tokenizers")]
+    fn test_count_tokens() {
+        let text = "Hello world! Tokenizers split text into subword pieces.";
+        assert_eq!(count_tokens(Some("cl100k_base"), text), 12);
+        assert_eq!(count_tokens(Some("o200k_base"), text), 12);
+        assert_eq!(count_tokens(Some("cl100k_base"), &"a".repeat(1000)), 125);
+    }
+
+    #[test]
+    fn test_count_tokens_fallback() {
+        assert_eq!(
+            count_tokens(Some("unknown"), "hello world"),
+            estimate_token_length("hello world")
+        );
+        assert_eq!(
+            count_tokens(None, "hello world"),
+            estimate_token_length("hello world")
+        );
+        #[cfg(not(feature = "tokenizers"))]
+        assert_eq!(
+            count_tokens(Some("cl100k_base"), "hello world"),
+            estimate_token_length("hello world")
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use anyhow::Result;
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+// Runs `aichat auth vault <args>` against a temporary config directory
+fn aichat_vault(config_dir: &Path, args: &[&str]) -> Result<Command> {
+    fs::create_dir_all(config_dir)?;
+    fs::write(
+        config_dir.join("config.yaml"),
+        "model: openai:gpt-4o\nclients:\n- type: openai\n  api_key: vault:openai\n",
+    )?;
+    let mut cmd = Command::cargo_bin("aichat")?;
+    cmd.env("AICHAT_CONFIG_DIR", config_dir)
+        .env("AICHAT_VAULT_PASSPHRASE", "passphrase")
+        .env_remove("AICHAT_VAULT_SESSION_TTL")
+        .args(["auth", "vault"])
+        .args(args);
+    Ok(cmd)
+}
+
+#[test]
+fn test_vault_set_get_list() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let config_dir = temp_dir.path().join("aichat");
+
+    aichat_vault(&config_dir, &["list"])?
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No vault"));
+
+    aichat_vault(&config_dir, &["set", "openai"])?
+        .write_stdin("sk-secret\n")
+        .assert()
+        .success();
+    let contents = fs::read_to_string(config_dir.join("vault.json"))?;
+    assert!(!contents.contains("sk-secret"));
+
+    aichat_vault(&config_dir, &["get", "openai"])?
+        .assert()
+        .success()
+        .stdout("sk-secret\n");
+
+    aichat_vault(&config_dir, &["list"])?
+        .env("AICHAT_VAULT_PASSPHRASE", "wrong")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Wrong passphrase"));
+
+    Ok(())
+}
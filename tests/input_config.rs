@@ -1,13 +1,100 @@
-use aichat::config::{Config as GlobalConfig, Input, Role};
+use anyhow::Result;
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+// Runs `aichat` against a temporary config directory
+fn aichat(config_dir: &Path) -> Result<Command> {
+    fs::create_dir_all(config_dir)?;
+    fs::write(
+        config_dir.join("config.yaml"),
+        "model: openai:gpt-4o\nclients:\n- type: openai\n  api_key: test\n",
+    )?;
+    let mut cmd = Command::cargo_bin("aichat")?;
+    cmd.env("AICHAT_CONFIG_DIR", config_dir);
+    Ok(cmd)
+}
+
+// Runs `aichat --dry-run`, printing the input instead of sending it
+fn aichat_dry_run(config_dir: &Path) -> Result<Command> {
+    let mut cmd = aichat(config_dir)?;
+    cmd.arg("--dry-run");
+    Ok(cmd)
+}
 
 #[test]
-fn test_input_from_str() {
-    let config = GlobalConfig::default(); // Assuming GlobalConfig has a Default implementation
-    let text = "Hello, world!";
-    let role = Some(Role::default()); // Assuming Role has a Default implementation
-    let authenticator = None;
+fn test_input_from_str() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let config_dir = temp_dir.path().join("aichat");
+
+    aichat_dry_run(&config_dir)?
+        .arg("Hello, world!")
+        .assert()
+        .success()
+        .stdout("Hello, world!\n");
+
+    fs::create_dir_all(config_dir.join("roles"))?;
+    fs::write(
+        config_dir.join("roles").join("translator.md"),
+        "Translate the input into French.",
+    )?;
+    aichat_dry_run(&config_dir)?
+        .args(["--role", "translator", "Hello, world!"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "Translate the input into French.",
+        ))
+        .stdout(predicate::str::ends_with("Hello, world!\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_env_config_secret_refs() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let config_dir = temp_dir.path().join("aichat");
+    let dry_run_file = temp_dir.path().join("dry_run");
+    fs::write(&dry_run_file, "true\n")?;
+
+    aichat(&config_dir)?
+        .env("AICHAT_DRY_RUN", "env:AICHAT_TEST_DRY_RUN")
+        .env("AICHAT_TEST_DRY_RUN", "true")
+        .arg("Hello, world!")
+        .assert()
+        .success()
+        .stdout("Hello, world!\n");
+
+    aichat(&config_dir)?
+        .env("AICHAT_DRY_RUN", format!("file:{}", dry_run_file.display()))
+        .arg("Hello, world!")
+        .assert()
+        .success()
+        .stdout("Hello, world!\n");
+
+    // Only secrets get masked, not the settings resolved from references
+    let info = aichat(&config_dir)?
+        .env("AICHAT_DRY_RUN", "true")
+        .arg("--info")
+        .output()?;
+    aichat(&config_dir)?
+        .env("AICHAT_DRY_RUN", "env:AICHAT_TEST_DRY_RUN")
+        .env("AICHAT_TEST_DRY_RUN", "true")
+        .arg("--info")
+        .assert()
+        .success()
+        .stdout(String::from_utf8(info.stdout)?)
+        .stdout(predicate::str::contains("***").not());
 
-    let input = Input::from_str(&config, text, role, authenticator);
+    aichat_dry_run(&config_dir)?
+        .env("AICHAT_TEMPERATURE", "env:AICHAT_TEST_MISSING")
+        .env_remove("AICHAT_TEST_MISSING")
+        .arg("Hello, world!")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid env AICHAT_TEMPERATURE"));
 
-    assert_eq!(input.text(), text);
+    Ok(())
 }
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use assert_cmd::Command;
+use serde_json::{json, Value};
+use std::fs;
+use tempfile::tempdir;
+
+// Sends the requests to `aichat --mcp` and returns its responses keyed by id
+fn mcp_responses(requests: &[Value]) -> Result<Vec<Value>> {
+    let temp_dir = tempdir()?;
+    let config_dir = temp_dir.path().join("aichat");
+    let sessions_dir = config_dir.join("sessions");
+    fs::create_dir_all(&sessions_dir)?;
+    fs::write(
+        config_dir.join("config.yaml"),
+        "model: openai:gpt-4o\nclients:\n- type: openai\n  api_key: test\n",
+    )?;
+    fs::write(sessions_dir.join("notes.yaml"), "messages: []\n")?;
+    let stdin: String = requests.iter().map(|v| format!("{v}\n")).collect();
+    let output = Command::cargo_bin("aichat")?
+        .env("AICHAT_CONFIG_DIR", &config_dir)
+        .arg("--mcp")
+        .write_stdin(stdin)
+        .output()?;
+    assert!(output.status.success());
+    let mut responses = String::from_utf8(output.stdout)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<Value>, _>>()?;
+    responses.sort_by_key(|v| v["id"].as_u64());
+    Ok(responses)
+}
+
+#[test]
+fn test_mcp_server() -> Result<()> {
+    let request = |id: u64, method: &str, params: Value| {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        })
+    };
+    let responses = mcp_responses(&[
+        request(1, "initialize", json!({})),
+        json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+        request(2, "prompts/list", json!({})),
+        request(
+            3,
+            "prompts/get",
+            json!({ "name": "%code%", "arguments": { "input": "fizzbuzz in rust" } }),
+        ),
+        request(4, "resources/list", json!({})),
+        request(
+            5,
+            "resources/read",
+            json!({ "uri": "aichat://sessions/notes" }),
+        ),
+        request(
+            6,
+            "resources/read",
+            json!({ "uri": "aichat://sessions/../config" }),
+        ),
+        request(7, "sampling/createMessage", json!({})),
+    ])?;
+    assert_eq!(responses.len(), 7);
+
+    let res = &responses[0];
+    assert_eq!(res["id"], 1);
+    assert_eq!(res["result"]["serverInfo"]["name"], "aichat");
+    assert!(res["result"]["capabilities"]["prompts"].is_object());
+
+    let prompts = responses[1]["result"]["prompts"].as_array().unwrap();
+    assert!(prompts.iter().any(|v| v["name"] == "%code%"));
+
+    let messages = responses[2]["result"]["messages"].as_array().unwrap();
+    assert!(messages.iter().all(|v| v["role"] != "system"));
+    assert_eq!(
+        messages.last().unwrap()["content"]["text"],
+        "fizzbuzz in rust"
+    );
+
+    let resources = &responses[3]["result"]["resources"];
+    assert_eq!(resources[0]["uri"], "aichat://sessions/notes");
+
+    let contents = &responses[4]["result"]["contents"];
+    assert_eq!(contents[0]["text"], "messages: []\n");
+
+    // -32602 is INVALID_PARAMS and -32601 is METHOD_NOT_FOUND
+    assert_eq!(responses[5]["error"]["code"], -32602);
+    assert_eq!(responses[6]["error"]["code"], -32601);
+
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use anyhow::Result;
+use assert_cmd::Command;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+// Runs `aichat` with an MCP server that only leaves a marker file when it is spawned
+fn aichat(config_dir: &Path, marker: &Path) -> Result<Command> {
+    fs::create_dir_all(config_dir)?;
+    fs::write(
+        config_dir.join("config.yaml"),
+        format!(
+            r#"model: openai:gpt-4o
+function_calling: true
+mcp_servers:
+- name: marker
+  command: sh
+  args: ['-c', 'touch "{}"']
+clients:
+- type: openai
+  api_key: test
+  api_base: http://127.0.0.1:1
+"#,
+            marker.display()
+        ),
+    )?;
+    let mut cmd = Command::cargo_bin("aichat")?;
+    cmd.env("AICHAT_CONFIG_DIR", config_dir);
+    Ok(cmd)
+}
+
+#[test]
+fn test_mcp_servers_not_started_without_chat() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let config_dir = temp_dir.path().join("aichat");
+    let marker = temp_dir.path().join("marker");
+
+    aichat(&config_dir, &marker)?
+        .args(["--count-tokens", "Hello, world!"])
+        .assert()
+        .success();
+    aichat(&config_dir, &marker)?
+        .args(["--dry-run", "Hello, world!"])
+        .assert()
+        .success();
+    aichat(&config_dir, &marker)?
+        .arg("--info")
+        .assert()
+        .success();
+    assert!(!marker.exists());
+
+    aichat(&config_dir, &marker)?
+        .arg("Hello, world!")
+        .assert()
+        .failure();
+    assert!(marker.exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_agent_mcp_servers_not_started_without_chat() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let config_dir = temp_dir.path().join("aichat");
+    let marker = temp_dir.path().join("marker");
+    let agent_marker = temp_dir.path().join("agent-marker");
+    let agent_dir = config_dir.join("functions").join("agents").join("demo");
+    fs::create_dir_all(&agent_dir)?;
+    fs::write(
+        agent_dir.join("index.yaml"),
+        format!(
+            r#"name: demo
+instructions: You are a demo agent.
+mcp_servers:
+- name: agent-marker
+  command: sh
+  args: ['-c', 'touch "{}"']
+"#,
+            agent_marker.display()
+        ),
+    )?;
+
+    for args in [
+        &["--count-tokens", "Hello, world!"][..],
+        &["--dry-run", "Hello, world!"],
+        &["--list-sessions"],
+        &["--info"],
+    ] {
+        aichat(&config_dir, &marker)?
+            .args(["--agent", "demo"])
+            .args(args)
+            .assert()
+            .success();
+    }
+    assert!(!agent_marker.exists());
+
+    aichat(&config_dir, &marker)?
+        .args(["--agent", "demo", "Hello, world!"])
+        .assert()
+        .failure();
+    assert!(agent_marker.exists());
+
+    Ok(())
+}